@@ -92,6 +92,20 @@ struct UShaderData {
 };
 
 @group(3) @binding(0) var<uniform> shader_data: UShaderData;
+// Band spectrum, one texel per band from low to high, levels 0-1
+@group(3) @binding(1) var band_texture: texture_2d<f32>;
+
+// 1 inside the spectrum bars rising from the bottom of each face
+fn spectrum_bars(uv: vec2<f32>) -> f32 {
+    let count = i32(textureDimensions(band_texture).x);
+    let position = uv.x * f32(count);
+    let band = clamp(i32(position), 0, count - 1);
+    let level = textureLoad(band_texture, vec2<i32>(band, 0), 0).r;
+    // Bars take the bottom third, with gaps between them
+    let height = (1.0 - uv.y) * 3.0;
+    let gap = fract(position);
+    return select(0.0, 1.0, height < level && gap > 0.15 && gap < 0.85);
+}
 
 @fragment
 fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
@@ -174,5 +188,7 @@ fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
         min(final_hsv.z * 1.2, 1.0)   // Boost value
     ));
     
-    return vec4<f32>(boosted_color, 1.0);
+    // Spectrum bars over the top
+    let bars = spectrum_bars(uv);
+    return vec4<f32>(mix(boosted_color, vec3(1.0), bars * 0.6), 1.0);
 }
\ No newline at end of file
@@ -0,0 +1,132 @@
+// Local stand-in for an Icecast server, for trying the network stream source
+//
+//   cargo run --example icy_server -- assets/song.mp3
+//   cargo run -- --source stream --url http://127.0.0.1:8000/stream.mp3
+//
+// Loops the file forever as a chunked HTTP response, paced at a fixed byte
+// rate, and answers `Icy-MetaData: 1` requests with an `icy-metaint` header
+// and a StreamTitle naming the file and loop count. The pace should roughly
+// match the file's bitrate; too fast and the client drops audio, too slow
+// and it underruns.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+const METAINT: usize = 16000;
+const CHUNK: usize = 4096;
+
+struct Options {
+    path: String,
+    port: u16,
+    bytes_per_second: usize,
+}
+
+fn parse_args() -> Options {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().unwrap_or_else(|| {
+        eprintln!("usage: icy_server FILE [PORT] [KBPS]");
+        std::process::exit(2);
+    });
+    let port = args.next().and_then(|a| a.parse().ok()).unwrap_or(8000);
+    let kbps: usize = args.next().and_then(|a| a.parse().ok()).unwrap_or(128);
+    Options { path, port, bytes_per_second: kbps * 1000 / 8 }
+}
+
+fn content_type(path: &str) -> &'static str {
+    if path.ends_with(".ogg") || path.ends_with(".oga") {
+        "application/ogg"
+    } else {
+        "audio/mpeg"
+    }
+}
+
+// Metadata block: a length byte in 16 byte units, then the zero-padded text
+fn metadata_block(title: &str) -> Vec<u8> {
+    let text = format!("StreamTitle='{}';", title);
+    let blocks = text.len().div_ceil(16).min(255);
+    let mut block = vec![blocks as u8];
+    block.extend(text.bytes().take(blocks * 16));
+    block.resize(1 + blocks * 16, 0);
+    block
+}
+
+fn write_chunk(stream: &mut TcpStream, data: &[u8]) -> std::io::Result<()> {
+    write!(stream, "{:x}\r\n", data.len())?;
+    stream.write_all(data)?;
+    stream.write_all(b"\r\n")
+}
+
+fn serve(mut stream: TcpStream, file: &[u8], options: &Options) -> std::io::Result<()> {
+    // Headers only matter for Icy-MetaData
+    let mut icy = false;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        let lower = line.to_ascii_lowercase();
+        icy |= lower.starts_with("icy-metadata:") && lower.trim_end().ends_with('1');
+    }
+
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nTransfer-Encoding: chunked\r\nicy-name: Local test stream\r\n",
+           content_type(&options.path))?;
+    if icy {
+        write!(stream, "icy-metaint: {}\r\n", METAINT)?;
+    }
+    stream.write_all(b"\r\n")?;
+
+    let name = std::path::Path::new(&options.path).file_name().map_or(String::new(), |n| n.to_string_lossy().into_owned());
+    let started = Instant::now();
+    let mut sent = 0usize;
+    let mut until_meta = METAINT;
+    for pass in 1.. {
+        let title = format!("{} (loop {})", name, pass);
+        println!("[SERVER] {}", title);
+        let mut pos = 0;
+        while pos < file.len() {
+            let len = CHUNK.min(file.len() - pos);
+            let len = if icy { len.min(until_meta) } else { len };
+            let mut chunk = file[pos..pos + len].to_vec();
+            pos += len;
+            sent += len;
+            if icy {
+                until_meta -= len;
+                if until_meta == 0 {
+                    chunk.extend(metadata_block(&title));
+                    until_meta = METAINT;
+                }
+            }
+            write_chunk(&mut stream, &chunk)?;
+
+            // Hold back to the stream's bitrate
+            let due = Duration::from_secs_f64(sent as f64 / options.bytes_per_second as f64);
+            if let Some(wait) = due.checked_sub(started.elapsed()) {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() -> std::io::Result<()> {
+    let options = parse_args();
+    let file = std::fs::read(&options.path)?;
+    let listener = TcpListener::bind(("127.0.0.1", options.port))?;
+    println!("[SERVER] Streaming {} on http://127.0.0.1:{}/", options.path, options.port);
+
+    std::thread::scope(|scope| {
+        for stream in listener.incoming().flatten() {
+            let (file, options) = (&file, &options);
+            scope.spawn(move || {
+                let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+                println!("[SERVER] {} connected", peer);
+                if let Err(e) = serve(stream, file, options) {
+                    println!("[SERVER] {} disconnected: {}", peer, e);
+                }
+            });
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,74 @@
+// Minimal OSC client for poking at the visualizer's OSC server
+//
+//   cargo run -- --osc 127.0.0.1:9000
+//   cargo run --example osc_send -- 127.0.0.1:9000 /sine/frequency 220
+//   cargo run --example osc_send -- 127.0.0.1:9000 /source oscillator
+//   cargo run --example osc_send -- 127.0.0.1:9000 /state
+//   cargo run --example osc_send -- 127.0.0.1:9000 /subscribe 10
+//
+// Arguments that parse as integers are sent as ints, other numbers as
+// floats, anything else as strings. Replies are printed until a second
+// passes without one; after /subscribe the number is how many seconds to
+// keep listening to broadcasts.
+
+use std::net::UdpSocket;
+use std::time::{Duration, Instant};
+
+use rosc::{OscMessage, OscPacket, OscType};
+
+fn print_packet(packet: &OscPacket) {
+    match packet {
+        OscPacket::Message(msg) => println!("{} {:?}", msg.addr, msg.args),
+        OscPacket::Bundle(bundle) => bundle.content.iter().for_each(print_packet),
+    }
+}
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let (Some(server), Some(addr)) = (args.next(), args.next()) else {
+        eprintln!("usage: osc_send HOST:PORT /address [ARGS...]");
+        std::process::exit(2);
+    };
+    let rest: Vec<String> = args.collect();
+
+    let listen_for = if addr == "/subscribe" {
+        Duration::from_secs(rest.first().and_then(|s| s.parse().ok()).unwrap_or(10))
+    } else {
+        Duration::ZERO
+    };
+    let args = if addr == "/subscribe" {
+        Vec::new()
+    } else {
+        rest.iter()
+            .map(|arg| {
+                if let Ok(i) = arg.parse::<i32>() {
+                    OscType::Int(i)
+                } else if let Ok(f) = arg.parse::<f32>() {
+                    OscType::Float(f)
+                } else {
+                    OscType::String(arg.clone())
+                }
+            })
+            .collect()
+    };
+
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    let packet = OscPacket::Message(OscMessage { addr, args });
+    let bytes = rosc::encoder::encode(&packet).map_err(|e| std::io::Error::other(format!("{:?}", e)))?;
+    socket.send_to(&bytes, &server)?;
+
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let deadline = Instant::now() + listen_for;
+    let mut buf = [0u8; 65536];
+    loop {
+        match socket.recv_from(&mut buf) {
+            Ok((len, _)) => match rosc::decoder::decode_udp(&buf[..len]) {
+                Ok((_, packet)) => print_packet(&packet),
+                Err(e) => eprintln!("bad reply: {:?}", e),
+            },
+            Err(_) if Instant::now() < deadline => continue,
+            Err(_) => break,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,302 @@
+// Band spectrum for the shaders
+//
+// `BandAnalyzer` folds the analyzer's linear FFT bins into N bands on a
+// perceptual scale, and `write_texture` puts them, scaled 0-1 from
+// FLOOR_DB to 0 dBFS, into an N x 1 R32Float texture that the visualizer
+// material binds next to its uniform:
+//
+//   @group(3) @binding(1) var band_texture: texture_2d<f32>;
+//
+//   let count = textureDimensions(band_texture).x;
+//   let level = textureLoad(band_texture, vec2<i32>(band, 0), 0).r;
+//
+// A texture rather than a storage buffer because WebGL2 has no storage
+// buffers, and a 2D one because it has no 1D textures either.
+//
+// Octave and third-octave bands are the nominal ones of a measurement
+// analyzer from 20 Hz to 20 kHz (or Nyquist): centres one or a third of an
+// octave apart around 1 kHz, each band reaching halfway to its neighbours,
+// so there are 10 or 31 of them whatever the band count. Mel bands take the
+// band count and are the usual triangular filters spread evenly on the mel
+// scale, reaching to their neighbours' centres. A band too narrow to hold
+// an FFT bin reads the bin nearest its centre.
+
+use bevy::asset::RenderAssetUsages;
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy_egui::egui;
+
+use crate::spectrum::SpectrumAnalyzer;
+
+const MIN_FREQ: f32 = 20.0;
+const MAX_FREQ: f32 = 20000.0;
+// Level that maps to 0 in the texture
+pub const FLOOR_DB: f32 = -60.0;
+pub const BAND_COUNTS: [usize; 3] = [16, 32, 64];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum BandSpacing {
+    Octave,
+    #[default]
+    ThirdOctave,
+    Mel,
+}
+
+impl BandSpacing {
+    pub const ALL: [BandSpacing; 3] = [BandSpacing::Octave, BandSpacing::ThirdOctave, BandSpacing::Mel];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BandSpacing::Octave => "Octave",
+            BandSpacing::ThirdOctave => "Third-octave",
+            BandSpacing::Mel => "Mel",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BandSettings {
+    pub count: usize,
+    pub spacing: BandSpacing,
+}
+
+impl Default for BandSettings {
+    fn default() -> Self {
+        Self { count: 32, spacing: BandSpacing::default() }
+    }
+}
+
+fn mel(hz: f32) -> f32 {
+    2595.0 * (1.0 + hz / 700.0).log10()
+}
+
+fn mel_to_hz(mel: f32) -> f32 {
+    700.0 * (10f32.powf(mel / 2595.0) - 1.0)
+}
+
+// How much of a frequency goes into a band
+type BandWeight = Box<dyn Fn(f32) -> f32>;
+
+// Weighted bins making up one band
+struct BandFilter {
+    weights: Vec<(usize, f32)>,
+    // Holds a single bin because the band is narrower than one
+    narrow: bool,
+}
+
+#[derive(Resource, Default)]
+pub struct BandAnalyzer {
+    settings: BandSettings,
+    // FFT size and bin width the filters were built for
+    built_for: (usize, f32),
+    filters: Vec<BandFilter>,
+    levels: Vec<f32>,
+    db: Vec<f32>,
+}
+
+impl BandAnalyzer {
+    pub fn configure(&mut self, settings: BandSettings) {
+        if settings != self.settings {
+            self.settings = settings;
+            // Rebuilt on the next update
+            self.filters.clear();
+        }
+    }
+
+    fn build(&mut self, size: usize, bin_width: f32) {
+        let bins = size / 2 + 1;
+        let top = MAX_FREQ.min(bin_width * (bins - 1) as f32);
+        let nearest = |hz: f32| ((hz / bin_width).round() as usize).min(bins - 1);
+
+        // Each band as a weight for a frequency, plus its centre
+        let bands: Vec<(f32, BandWeight)> = match self.settings.spacing {
+            BandSpacing::Octave | BandSpacing::ThirdOctave => {
+                let per_octave = if self.settings.spacing == BandSpacing::Octave { 1.0 } else { 3.0 };
+                // Nominal bands around 1 kHz; the slack takes in the 20 Hz and
+                // 20 kHz third-octave bands, which sit a little outside
+                let step = |hz: f32| per_octave * (hz / 1000.0).log2();
+                let (first, last) = ((step(MIN_FREQ) - 0.1).ceil() as i32, (step(top) + 0.1).floor() as i32);
+                let at = |n: f32| 1000.0 * 2f32.powf(n / per_octave);
+                (first..=last)
+                    .map(|n| {
+                        // Edges halfway between centres, worked out the same way
+                        // on both sides so neighbours meet and each bin lands in one band
+                        let centre = at(n as f32);
+                        let (low, high) = (at(n as f32 - 0.5), at(n as f32 + 0.5));
+                        let weight: BandWeight =
+                            Box::new(move |hz| if (low..high).contains(&hz) { 1.0 } else { 0.0 });
+                        (centre, weight)
+                    })
+                    .collect()
+            }
+            BandSpacing::Mel => {
+                let count = self.settings.count.max(1);
+                let (low, high) = (mel(MIN_FREQ), mel(top));
+                let points: Vec<f32> =
+                    (0..count + 2).map(|i| mel_to_hz(low + (high - low) * i as f32 / (count + 1) as f32)).collect();
+                points
+                    .windows(3)
+                    .map(|p| {
+                        let (left, centre, right) = (p[0], p[1], p[2]);
+                        let weight: BandWeight = Box::new(move |hz| {
+                            if hz <= left || hz >= right {
+                                0.0
+                            } else if hz <= centre {
+                                (hz - left) / (centre - left)
+                            } else {
+                                (right - hz) / (right - centre)
+                            }
+                        });
+                        (centre, weight)
+                    })
+                    .collect()
+            }
+        };
+
+        self.filters = bands
+            .into_iter()
+            .map(|(centre, weight)| {
+                let weights: Vec<(usize, f32)> = (1..bins)
+                    .map(|bin| (bin, weight(bin as f32 * bin_width)))
+                    .filter(|(_, w)| *w > 0.0)
+                    .collect();
+                if weights.is_empty() {
+                    BandFilter { weights: vec![(nearest(centre), 1.0)], narrow: true }
+                } else {
+                    BandFilter { weights, narrow: false }
+                }
+            })
+            .collect();
+        self.levels = vec![0.0; self.filters.len()];
+        self.db = vec![FLOOR_DB; self.filters.len()];
+        self.built_for = (size, bin_width);
+    }
+
+    // Fold the analyzer's latest spectrum into the bands
+    pub fn update(&mut self, analyzer: &SpectrumAnalyzer) {
+        let (size, bin_width) = (analyzer.size(), analyzer.bin_width());
+        if bin_width <= 0.0 {
+            return;
+        }
+        if self.filters.is_empty() || self.built_for != (size, bin_width) {
+            self.build(size, bin_width);
+        }
+
+        let magnitudes = analyzer.magnitudes();
+        let noise_bandwidth = analyzer.noise_bandwidth();
+        for (filter, (level, db)) in self.filters.iter().zip(self.levels.iter_mut().zip(self.db.iter_mut())) {
+            let power: f32 = filter.weights.iter().map(|&(bin, w)| w * magnitudes[bin] * magnitudes[bin]).sum();
+            // Same scaling as SpectrumAnalyzer::band_amplitude; a lone bin reads as it is
+            let amplitude = if filter.narrow { power.sqrt() } else { (power / noise_bandwidth).sqrt() };
+            *db = (20.0 * amplitude.log10()).max(FLOOR_DB);
+            *level = 1.0 - *db / FLOOR_DB;
+        }
+    }
+
+    // Band levels, 0 at FLOOR_DB and 1 at full scale, lowest band first
+    pub fn levels(&self) -> &[f32] {
+        &self.levels
+    }
+
+    // Resize the texture to the band count if needed and fill it with the levels
+    pub fn write_texture(&self, image: &mut Image) {
+        let width = self.levels.len().max(1) as u32;
+        if image.width() != width {
+            image.resize(Extent3d { width, height: 1, depth_or_array_layers: 1 });
+        }
+        let mut data: Vec<u8> = self.levels.iter().flat_map(|level| level.to_le_bytes()).collect();
+        data.resize(width as usize * 4, 0);
+        image.data = Some(data);
+    }
+}
+
+// Texture the bands are uploaded to; starts out silent
+pub fn band_image(count: usize) -> Image {
+    Image::new_fill(
+        Extent3d { width: count.max(1) as u32, height: 1, depth_or_array_layers: 1 },
+        TextureDimension::D2,
+        &0f32.to_le_bytes(),
+        TextureFormat::R32Float,
+        RenderAssetUsages::default(),
+    )
+}
+
+// Band count and spacing, and the bands as bars
+pub fn bands_panel(ui: &mut egui::Ui, settings: &mut BandSettings, bands: &BandAnalyzer) {
+    // Octave scales have a fixed number of bands
+    ui.add_enabled_ui(settings.spacing == BandSpacing::Mel, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Bands:");
+            for count in BAND_COUNTS {
+                ui.radio_value(&mut settings.count, count, count.to_string());
+            }
+        });
+    });
+    egui::ComboBox::from_label("Band spacing")
+        .selected_text(settings.spacing.name())
+        .show_ui(ui, |ui| {
+            for spacing in BandSpacing::ALL {
+                ui.selectable_value(&mut settings.spacing, spacing, spacing.name());
+            }
+        });
+
+    let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), 40.0), egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 0.0, egui::Color32::from_gray(20));
+    let levels = bands.levels();
+    let width = rect.width() / levels.len().max(1) as f32;
+    for (i, level) in levels.iter().enumerate() {
+        let x = rect.left() + i as f32 * width;
+        let bar = egui::Rect::from_min_max(
+            egui::pos2(x, rect.bottom() - level.clamp(0.0, 1.0) * rect.height()),
+            egui::pos2(x + (width - 1.0).max(1.0), rect.bottom()),
+        );
+        painter.rect_filled(bar, 0.0, egui::Color32::from_rgb(80, 200, 120));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bands built for a 4096-point FFT at 48 kHz
+    fn analyzer(spacing: BandSpacing) -> BandAnalyzer {
+        let mut bands = BandAnalyzer::default();
+        bands.configure(BandSettings { count: 16, spacing });
+        bands.build(4096, 48000.0 / 4096.0);
+        bands
+    }
+
+    #[test]
+    fn octave_bands_are_nominal() {
+        let bands = analyzer(BandSpacing::Octave);
+        assert_eq!(bands.filters.len(), 10);
+        assert_eq!(bands.levels().len(), 10);
+        let bands = analyzer(BandSpacing::ThirdOctave);
+        assert_eq!(bands.filters.len(), 31);
+    }
+
+    #[test]
+    fn octave_bands_cover_each_bin_once() {
+        for spacing in [BandSpacing::Octave, BandSpacing::ThirdOctave] {
+            let bands = analyzer(spacing);
+            let mut hits = vec![0; 4096 / 2 + 1];
+            for filter in bands.filters.iter().filter(|f| !f.narrow) {
+                for &(bin, weight) in &filter.weights {
+                    assert_eq!(weight, 1.0);
+                    hits[bin] += 1;
+                }
+            }
+            let covered: Vec<usize> = (0..hits.len()).filter(|&bin| hits[bin] > 0).collect();
+            assert!(hits.iter().all(|&n| n <= 1), "{:?}: a bin is in two bands", spacing);
+            // No gaps between the lowest and highest covered bins
+            let (first, last) = (covered[0], covered[covered.len() - 1]);
+            assert_eq!(covered.len(), last - first + 1, "{:?}: a bin is in no band", spacing);
+        }
+    }
+
+    #[test]
+    fn mel_takes_the_band_count() {
+        assert_eq!(analyzer(BandSpacing::Mel).filters.len(), 16);
+    }
+}
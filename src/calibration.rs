@@ -0,0 +1,488 @@
+// Calibration test signals
+//
+// Known inputs for checking the analysis: sweeps, an impulse train, chords,
+// noise limited to each analysis band, and silence. Every signal is a plain
+// `SignalGenerator` that can be run offline with `generate`; `SignalNode`
+// wraps one for the audio graph, and each signal is registered as its own
+// DspGraph source. The numeric settings are Shared values the generator
+// reads as it runs, so only a new chord needs the graph rebuilt.
+
+use std::f64::consts::TAU;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+
+use bevy_egui::egui;
+use bevy_fundsp::prelude::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Band {
+    Bass,
+    Mid,
+    Treble,
+}
+
+impl Band {
+    // Edges shared with the analysis in prepare_my_material
+    pub fn range(&self) -> (f32, f32) {
+        match self {
+            Band::Bass => (crate::BASS_MIN_FREQ, crate::BASS_MAX_FREQ),
+            Band::Mid => (crate::MIDRANGE_MIN_FREQ, crate::MIDRANGE_MAX_FREQ),
+            Band::Treble => (crate::TREBLE_MIN_FREQ, crate::TREBLE_MAX_FREQ),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestSignal {
+    LinearSweep,
+    LogSweep,
+    ImpulseTrain,
+    Chord,
+    BandNoise(Band),
+    Silence,
+}
+
+impl TestSignal {
+    pub const ALL: [TestSignal; 8] = [
+        TestSignal::LinearSweep,
+        TestSignal::LogSweep,
+        TestSignal::ImpulseTrain,
+        TestSignal::Chord,
+        TestSignal::BandNoise(Band::Bass),
+        TestSignal::BandNoise(Band::Mid),
+        TestSignal::BandNoise(Band::Treble),
+        TestSignal::Silence,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TestSignal::LinearSweep => "Linear Sweep",
+            TestSignal::LogSweep => "Log Sweep",
+            TestSignal::ImpulseTrain => "Impulse Train",
+            TestSignal::Chord => "Chord",
+            TestSignal::BandNoise(Band::Bass) => "Bass Noise",
+            TestSignal::BandNoise(Band::Mid) => "Mid Noise",
+            TestSignal::BandNoise(Band::Treble) => "Treble Noise",
+            TestSignal::Silence => "Silence",
+        }
+    }
+
+    // Graph id of the source playing this signal
+    pub fn id(&self) -> Uuid {
+        let index = TestSignal::ALL.iter().position(|s| s == self).unwrap();
+        Uuid::from_u128(0x63616c69_6272_6174_0000_000000000000u128 + index as u128)
+    }
+}
+
+// Clones share the Shared values, so a generator follows the settings it
+// was made from
+#[derive(Clone)]
+pub struct SignalSettings {
+    pub sweep_start: Shared, // Hz
+    pub sweep_end: Shared,   // Hz
+    pub sweep_seconds: Shared,
+    pub impulse_rate: Shared, // impulses per second
+    pub chord: String,        // frequencies in Hz, separated by spaces or commas
+    pub level: Shared,
+}
+
+impl Default for SignalSettings {
+    fn default() -> Self {
+        Self {
+            sweep_start: shared(20.0),
+            sweep_end: shared(20000.0),
+            sweep_seconds: shared(10.0),
+            impulse_rate: shared(2.0),
+            chord: String::from("110 440 6000"),
+            level: shared(0.5),
+        }
+    }
+}
+
+impl SignalSettings {
+    pub fn chord_frequencies(&self) -> Vec<f32> {
+        self.chord
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter_map(|s| s.parse::<f32>().ok())
+            .filter(|hz| *hz > 0.0)
+            .collect()
+    }
+}
+
+// Second-order section, RBJ cookbook coefficients
+#[derive(Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn new(cutoff: f64, sample_rate: f64, highpass: bool) -> Self {
+        let w0 = TAU * cutoff.min(sample_rate * 0.45) / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * std::f64::consts::FRAC_1_SQRT_2);
+        let a0 = 1.0 + alpha;
+        let b = if highpass {
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0]
+        } else {
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0]
+        };
+        Self {
+            b: [b[0] / a0, b[1] / a0, b[2] / a0],
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            z: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+// Deterministic so offline runs give the same noise every time
+#[derive(Clone)]
+struct XorShift(u32);
+
+impl XorShift {
+    fn next(&mut self) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f64 / u32::MAX as f64 * 2.0 - 1.0
+    }
+}
+
+#[derive(Clone)]
+pub struct SignalGenerator {
+    signal: TestSignal,
+    settings: SignalSettings,
+    chord: Vec<f32>,
+    sample_rate: f64,
+    frame: u64,
+    phases: Vec<f64>,
+    noise: XorShift,
+    // Two highpass then two lowpass sections: a 4th-order band edge each side
+    filters: Vec<Biquad>,
+    noise_gain: f64,
+}
+
+impl SignalGenerator {
+    pub fn new(signal: TestSignal, settings: &SignalSettings, sample_rate: f64) -> Self {
+        let chord = settings.chord_frequencies();
+        let (filters, noise_gain) = match signal {
+            TestSignal::BandNoise(band) => {
+                let (low, high) = band.range();
+                // Same RMS in every band (a quarter of the level) whatever its width,
+                // so the three bands should read alike
+                let nyquist = sample_rate / 2.0;
+                let width = ((high.min(nyquist as f32) - low) as f64 / nyquist).max(1e-3);
+                let gain = 0.25 * 3f64.sqrt() / width.sqrt();
+                let filters = vec![
+                    Biquad::new(low as f64, sample_rate, true),
+                    Biquad::new(low as f64, sample_rate, true),
+                    Biquad::new(high as f64, sample_rate, false),
+                    Biquad::new(high as f64, sample_rate, false),
+                ];
+                (filters, gain)
+            }
+            _ => (Vec::new(), 1.0),
+        };
+        Self {
+            signal,
+            settings: settings.clone(),
+            phases: vec![0.0; chord.len().max(1)],
+            chord,
+            sample_rate,
+            frame: 0,
+            noise: XorShift(0x2545_f491),
+            filters,
+            noise_gain,
+        }
+    }
+
+    // Frequency of a sweep at the current frame, restarting every sweep period
+    fn sweep_frequency(&self, log: bool) -> f64 {
+        let period = (self.settings.sweep_seconds.value().max(0.1) as f64 * self.sample_rate) as u64;
+        let t = (self.frame % period) as f64 / period as f64;
+        let (start, end) = (self.settings.sweep_start.value() as f64, self.settings.sweep_end.value() as f64);
+        if log {
+            start * (end / start).powf(t)
+        } else {
+            start + (end - start) * t
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        let value = match self.signal {
+            TestSignal::LinearSweep | TestSignal::LogSweep => {
+                let frequency = self.sweep_frequency(self.signal == TestSignal::LogSweep);
+                // Integrating the frequency keeps the phase continuous as it changes
+                self.phases[0] = (self.phases[0] + frequency / self.sample_rate).fract();
+                (self.phases[0] * TAU).sin()
+            }
+            TestSignal::ImpulseTrain => {
+                let period = (self.sample_rate / self.settings.impulse_rate.value().max(0.1) as f64).max(1.0) as u64;
+                if self.frame.is_multiple_of(period) { 1.0 } else { 0.0 }
+            }
+            TestSignal::Chord => {
+                let mut sum = 0.0;
+                for (phase, &frequency) in self.phases.iter_mut().zip(&self.chord) {
+                    *phase = (*phase + frequency as f64 / self.sample_rate).fract();
+                    sum += (*phase * TAU).sin();
+                }
+                // Normalised so the peak can't exceed the level
+                sum / self.chord.len().max(1) as f64
+            }
+            TestSignal::BandNoise(_) => {
+                let mut x = self.noise.next();
+                for filter in &mut self.filters {
+                    x = filter.process(x);
+                }
+                (x * self.noise_gain).clamp(-1.0, 1.0)
+            }
+            TestSignal::Silence => 0.0,
+        };
+        self.frame += 1;
+        value as f32 * self.settings.level.value()
+    }
+}
+
+// Offline rendering, for tests and analysis checks without an audio device
+pub fn generate(signal: TestSignal, settings: &SignalSettings, sample_rate: f64, frames: usize) -> Vec<f32> {
+    let mut generator = SignalGenerator::new(signal, settings, sample_rate);
+    (0..frames).map(|_| generator.next_sample()).collect()
+}
+
+// Graph node playing a SignalGenerator
+#[derive(Clone)]
+pub struct SignalNode {
+    generator: SignalGenerator,
+}
+
+impl SignalNode {
+    pub fn new(generator: SignalGenerator) -> An<Self> {
+        An(Self { generator })
+    }
+}
+
+impl AudioNode for SignalNode {
+    const ID: u64 = 0x7465_7374;
+    type Inputs = U0;
+    type Outputs = U1;
+
+    fn reset(&mut self) {
+        let g = &self.generator;
+        self.generator = SignalGenerator::new(g.signal, &g.settings, g.sample_rate);
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        let g = &self.generator;
+        self.generator = SignalGenerator::new(g.signal, &g.settings, sample_rate);
+    }
+
+    fn tick(&mut self, _input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        [self.generator.next_sample()].into()
+    }
+}
+
+// Graph for one test signal; the chord is read when the player spawns
+pub struct TestSignalDsp {
+    pub signal: TestSignal,
+    pub settings: Arc<Mutex<SignalSettings>>,
+    pub sample_rate: f64,
+}
+
+impl DspGraph for TestSignalDsp {
+    fn id(&self) -> Uuid {
+        self.signal.id()
+    }
+
+    fn generate_graph(&self) -> Box<dyn AudioUnit> {
+        let settings = self.settings.lock().unwrap().clone();
+        let generator = SignalGenerator::new(self.signal, &settings, self.sample_rate);
+        Box::new(SignalNode::new(generator) >> split::<U2>())
+    }
+}
+
+// Slider for a setting the running signal reads
+fn shared_slider(ui: &mut egui::Ui, value: &Shared, range: RangeInclusive<f32>, logarithmic: bool, text: &str) {
+    let mut current = value.value();
+    if ui.add(egui::Slider::new(&mut current, range).logarithmic(logarithmic).text(text)).changed() {
+        value.set_value(current);
+    }
+}
+
+// Side panel section for the test signals. Returns true when the signal
+// needs rebuilding to pick up a change, which only a new chord does.
+pub fn calibration_panel(ui: &mut egui::Ui, signal: TestSignal, settings: &mut SignalSettings) -> bool {
+    let mut changed = false;
+
+    match signal {
+        TestSignal::LinearSweep | TestSignal::LogSweep => {
+            shared_slider(ui, &settings.sweep_start, 20.0..=24000.0, true, "From (Hz)");
+            shared_slider(ui, &settings.sweep_end, 20.0..=24000.0, true, "To (Hz)");
+            shared_slider(ui, &settings.sweep_seconds, 0.5..=60.0, false, "Duration (s)");
+        }
+        TestSignal::ImpulseTrain => {
+            shared_slider(ui, &settings.impulse_rate, 0.5..=100.0, false, "Rate (Hz)");
+        }
+        TestSignal::Chord => {
+            ui.horizontal(|ui| {
+                ui.label("Tones (Hz):");
+                changed |= ui.text_edit_singleline(&mut settings.chord).lost_focus();
+            });
+        }
+        TestSignal::BandNoise(band) => {
+            let (low, high) = band.range();
+            ui.label(format!("{} - {} Hz", low, high));
+        }
+        TestSignal::Silence => {}
+    }
+    shared_slider(ui, &settings.level, 0.0..=1.0, false, "Level");
+
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spectrum::{AnalysisSettings, SpectrumAnalyzer};
+
+    const RATE: f64 = 48000.0;
+
+    // Average frequency over `seconds` from `start`, from rising zero crossings
+    fn frequency(samples: &[f32], start: f64, seconds: f64) -> f64 {
+        let from = (start * RATE) as usize;
+        let window = &samples[from..from + (seconds * RATE) as usize];
+        let crossings = window.windows(2).filter(|w| w[0] < 0.0 && w[1] >= 0.0).count();
+        crossings as f64 / seconds
+    }
+
+    fn sweep_settings() -> SignalSettings {
+        let settings = SignalSettings::default();
+        settings.sweep_start.set_value(100.0);
+        settings.sweep_end.set_value(10100.0);
+        settings.sweep_seconds.set_value(1.0);
+        settings
+    }
+
+    fn assert_near(actual: f64, expected: f64, tolerance: f64) {
+        assert!((actual - expected).abs() <= expected * tolerance, "{} is not within {}% of {}", actual, tolerance * 100.0, expected);
+    }
+
+    #[test]
+    fn linear_sweep() {
+        let settings = sweep_settings();
+        let samples = generate(TestSignal::LinearSweep, &settings, RATE, (1.5 * RATE) as usize);
+
+        let peak = samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        assert!(peak <= 0.5 && peak > 0.49, "peak {}", peak);
+        // 100 Hz plus 10 kHz per second, centred on the window
+        assert_near(frequency(&samples, 0.24, 0.02), 2600.0, 0.03);
+        assert_near(frequency(&samples, 0.74, 0.02), 7600.0, 0.03);
+        // Starts over after a second
+        assert_near(frequency(&samples, 1.24, 0.02), 2600.0, 0.03);
+    }
+
+    #[test]
+    fn log_sweep() {
+        let settings = sweep_settings();
+        settings.sweep_end.set_value(10000.0);
+        let samples = generate(TestSignal::LogSweep, &settings, RATE, RATE as usize);
+
+        // Two decades in a second: 1 kHz halfway, 100 Hz more per decade
+        assert_near(frequency(&samples, 0.48, 0.04), 1000.0, 0.05);
+        assert_near(frequency(&samples, 0.2, 0.1), 316.0, 0.05);
+        assert_near(frequency(&samples, 0.73, 0.04), 3162.0, 0.05);
+    }
+
+    #[test]
+    fn impulse_train() {
+        let settings = SignalSettings::default();
+        settings.impulse_rate.set_value(4.0);
+        let samples = generate(TestSignal::ImpulseTrain, &settings, RATE, RATE as usize);
+
+        let impulses: Vec<usize> = samples.iter().enumerate().filter(|(_, s)| **s != 0.0).map(|(i, _)| i).collect();
+        assert_eq!(impulses, vec![0, 12000, 24000, 36000]);
+        assert!(impulses.iter().all(|&i| samples[i] == 0.5));
+    }
+
+    #[test]
+    fn settings_apply_while_running() {
+        let settings = SignalSettings::default();
+        settings.impulse_rate.set_value(4.0);
+        let mut generator = SignalGenerator::new(TestSignal::ImpulseTrain, &settings, RATE);
+        assert_eq!(generator.next_sample(), 0.5);
+
+        settings.level.set_value(0.25);
+        settings.impulse_rate.set_value(8.0);
+        let samples: Vec<f32> = (1..=6000).map(|_| generator.next_sample()).collect();
+        assert_eq!(samples[5999], 0.25);
+        assert!(samples[..5999].iter().all(|s| *s == 0.0));
+    }
+
+    // Each analysis band's amplitude over a second of `signal`
+    fn band_levels(signal: TestSignal, settings: &SignalSettings) -> [f32; 3] {
+        let samples = generate(signal, settings, RATE, RATE as usize);
+        let mut analyzer = SpectrumAnalyzer::new(AnalysisSettings {
+            capture_length: 4096,
+            fft_size: 4096,
+            hop: 4096,
+            ..Default::default()
+        });
+        assert!(analyzer.process(&samples, samples.len(), RATE as f32));
+        [Band::Bass, Band::Mid, Band::Treble].map(|band| {
+            let (low, high) = band.range();
+            analyzer.band_amplitude(low, high)
+        })
+    }
+
+    #[test]
+    fn band_noise_stays_in_its_band() {
+        let settings = SignalSettings::default();
+        let mut own = Vec::new();
+        for (i, band) in [Band::Bass, Band::Mid, Band::Treble].into_iter().enumerate() {
+            let levels = band_levels(TestSignal::BandNoise(band), &settings);
+            // Only the slopes of the band edges leak into the neighbours
+            for (j, level) in levels.iter().enumerate() {
+                if j != i {
+                    assert!(*level < levels[i] * 0.4, "{:?} noise reads {:?}", band, levels);
+                }
+            }
+            own.push(levels[i]);
+        }
+        // Every band reads alike
+        let loudest = own.iter().cloned().fold(0.0, f32::max);
+        let quietest = own.iter().cloned().fold(f32::MAX, f32::min);
+        assert!(quietest > loudest * 0.9, "{:?}", own);
+    }
+
+    #[test]
+    fn chord_tones_land_in_their_bands() {
+        // One tone per band, each a third of the level
+        let settings = SignalSettings::default();
+        let levels = band_levels(TestSignal::Chord, &settings);
+        for level in levels {
+            assert!((level - 0.5 / 3.0).abs() < 0.01, "{:?}", levels);
+        }
+
+        // Both tones in the midrange leave the other bands empty
+        let settings = SignalSettings {
+            chord: String::from("500, 2000"),
+            ..SignalSettings::default()
+        };
+        let levels = band_levels(TestSignal::Chord, &settings);
+        assert!((levels[1] - 0.5 / 2f32.sqrt()).abs() < 0.01, "{:?}", levels);
+        assert!(levels[0] < 1e-3 && levels[2] < 1e-3, "{:?}", levels);
+    }
+
+    #[test]
+    fn silence() {
+        let samples = generate(TestSignal::Silence, &SignalSettings::default(), RATE, 1000);
+        assert!(samples.iter().all(|s| *s == 0.0));
+    }
+}
@@ -0,0 +1,166 @@
+// Command-line options
+//
+// Everything here has a matching control in the side panel; the CLI only
+// picks the starting state. Invalid input exits through clap with status 2.
+
+use clap::{CommandFactory, Parser, ValueEnum};
+use clap::error::ErrorKind;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SourceArg {
+    Sine,
+    File,
+    /// Live capture from an input device
+    Input,
+    /// Play the synth from the computer keyboard
+    Keyboard,
+    /// Internet radio from the URL given with --url
+    Stream,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ModeArg {
+    /// Statistics of the raw waveform
+    Raw,
+    /// Bass/mid/treble from the FFT
+    Fft,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PcmFormatArg {
+    /// Signed 16-bit little-endian
+    S16le,
+    /// 32-bit float little-endian
+    F32le,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ShaderArg {
+    Enhanced,
+    Haxor,
+}
+
+impl ShaderArg {
+    pub fn path(&self) -> &'static str {
+        match self {
+            ShaderArg::Enhanced => "shaders/enhanced_audio_visualizer.wgsl",
+            ShaderArg::Haxor => "shaders/haxor.wgsl",
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(name = "bevy_visualizer", version, about = "Audio-reactive shader visualizer")]
+pub struct Cli {
+    /// Audio files to queue, played in order
+    #[arg(value_name = "FILE")]
+    pub files: Vec<String>,
+
+    /// M3U-style playlist: one path per line, `#` lines ignored
+    #[arg(long, value_name = "PATH")]
+    pub playlist: Option<String>,
+
+    /// Source to start on
+    #[arg(long, value_enum, default_value_t = SourceArg::File)]
+    pub source: SourceArg,
+
+    /// Starting sine frequency in Hz (20-24000)
+    #[arg(long, default_value_t = 440.0, value_parser = parse_frequency)]
+    pub frequency: f32,
+
+    /// Analysis mode driving the shader
+    #[arg(long, value_enum, default_value_t = ModeArg::Raw)]
+    pub mode: ModeArg,
+
+    /// HTTP/Icecast stream URL for the stream source
+    #[arg(long, value_name = "URL")]
+    pub url: Option<String>,
+
+    /// Play raw PCM piped into stdin, e.g. `ffmpeg -i song.flac -f f32le -`
+    #[arg(long)]
+    pub stdin: bool,
+
+    /// Sample format of the PCM on stdin
+    #[arg(long, value_enum, default_value_t = PcmFormatArg::F32le, requires = "stdin")]
+    pub stdin_format: PcmFormatArg,
+
+    /// Interleaved channels of the PCM on stdin
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u16).range(1..=32), requires = "stdin")]
+    pub stdin_channels: u16,
+
+    /// Sample rate of the PCM on stdin in Hz
+    #[arg(long, default_value_t = 44100, value_parser = clap::value_parser!(u32).range(1000..=384000), requires = "stdin")]
+    pub stdin_rate: u32,
+
+    /// Rate the audio engine runs at in Hz; files and streams are resampled to it
+    #[arg(long, default_value_t = 44100, value_parser = clap::value_parser!(u32).range(8000..=192000))]
+    pub sample_rate: u32,
+
+    /// Listen for OSC control messages on this UDP address, e.g. 0.0.0.0:9000
+    #[arg(long, value_name = "ADDR")]
+    pub osc: Option<std::net::SocketAddr>,
+
+    /// Visualizer shader to start with
+    #[arg(long, value_enum, default_value_t = ShaderArg::Enhanced)]
+    pub shader: ShaderArg,
+
+    /// Start in borderless fullscreen
+    #[arg(long)]
+    pub fullscreen: bool,
+
+    /// Window width in pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(64..))]
+    pub width: Option<u32>,
+
+    /// Window height in pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(64..))]
+    pub height: Option<u32>,
+}
+
+fn parse_frequency(s: &str) -> Result<f32, String> {
+    let hz: f32 = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    if (20.0..=24000.0).contains(&hz) {
+        Ok(hz)
+    } else {
+        Err(format!("{} Hz is outside 20-24000 Hz", hz))
+    }
+}
+
+impl Cli {
+    // Tracks from the positional files followed by the playlist file, or
+    // the bundled test file if neither was given
+    pub fn tracks(&self) -> Vec<String> {
+        let mut tracks = self.files.clone();
+
+        if let Some(path) = &self.playlist {
+            let contents = std::fs::read_to_string(path).unwrap_or_else(|e| {
+                Cli::command()
+                    .error(ErrorKind::Io, format!("could not read playlist {}: {}", path, e))
+                    .exit()
+            });
+            // Entries are relative to the playlist's own directory
+            let base = std::path::Path::new(path).parent().unwrap_or(std::path::Path::new(""));
+            tracks.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(|line| base.join(line).to_string_lossy().into_owned()),
+            );
+        }
+
+        if tracks.is_empty() {
+            tracks.push(String::from("assets/test.wav"));
+        }
+        tracks
+    }
+}
+
+// The web build has no command line, so it always gets the defaults
+pub fn parse_args() -> Cli {
+    #[cfg(not(target_arch = "wasm32"))]
+    let cli = Cli::parse();
+    #[cfg(target_arch = "wasm32")]
+    let cli = Cli::parse_from(["bevy_visualizer"]);
+    cli
+}
@@ -0,0 +1,703 @@
+// Audio decoding layer
+//
+// Turns the raw bytes of an audio file into the fundsp `Wave` that
+// `WaveFileDsp` plays. The container is picked from the file's magic bytes,
+// never from its extension, so a mislabelled `.wav` that is really an MP3
+// still decodes.
+
+use std::fmt;
+use std::io::Cursor;
+
+use bevy::log::debug;
+use fundsp::wave::Wave;
+use hound::{SampleFormat, WavReader};
+
+use symphonia::core::audio::SampleBuffer as DecodedBuffer;
+use symphonia::core::codecs::{CodecParameters, DecoderOptions};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::default::formats::{FlacReader, MpaReader, OggReader};
+
+// Containers/codecs we know how to decode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AudioFormat {
+    Wav,
+    Flac,
+    OggVorbis,
+    OggOpus,
+    Mp3,
+}
+
+impl AudioFormat {
+    // Identify the format from the first bytes of the file
+    pub fn sniff(bytes: &[u8]) -> Option<AudioFormat> {
+        if bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WAVE" {
+            return Some(AudioFormat::Wav);
+        }
+
+        if bytes.starts_with(b"OggS") {
+            // The first page carries the codec identification header right
+            // after the segment table: 27 byte page header + one lacing byte
+            // per segment.
+            let segments = *bytes.get(26)? as usize;
+            let packet = bytes.get(27 + segments..)?;
+            if packet.starts_with(b"\x01vorbis") {
+                return Some(AudioFormat::OggVorbis);
+            }
+            if packet.starts_with(b"OpusHead") {
+                return Some(AudioFormat::OggOpus);
+            }
+            return None;
+        }
+
+        // FLAC and MP3 may both be prefixed with an ID3v2 tag, skip it first
+        let body = skip_id3v2(bytes);
+
+        if body.starts_with(b"fLaC") {
+            return Some(AudioFormat::Flac);
+        }
+
+        // MPEG audio frame sync: 11 set bits, and a layer field that isn't
+        // the reserved 00 value
+        if body.len() >= 2 && body[0] == 0xFF && (body[1] & 0xE0) == 0xE0 && (body[1] & 0x06) != 0 {
+            return Some(AudioFormat::Mp3);
+        }
+
+        // A bare ID3 tag with nothing we recognise behind it is almost
+        // always an MP3 whose first frame is preceded by padding
+        if body.len() != bytes.len() {
+            return Some(AudioFormat::Mp3);
+        }
+
+        None
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AudioFormat::Wav => "WAV",
+            AudioFormat::Flac => "FLAC",
+            AudioFormat::OggVorbis => "Ogg Vorbis",
+            AudioFormat::OggOpus => "Ogg Opus",
+            AudioFormat::Mp3 => "MP3",
+        }
+    }
+}
+
+// Returns the slice following an ID3v2 tag, or the input if there is none
+fn skip_id3v2(bytes: &[u8]) -> &[u8] {
+    if bytes.len() < 10 || &bytes[0..3] != b"ID3" {
+        return bytes;
+    }
+    // Tag size is a 28 bit "syncsafe" integer (7 bits per byte)
+    let size = bytes[6..10]
+        .iter()
+        .fold(0usize, |acc, &b| (acc << 7) | (b & 0x7F) as usize);
+    // Flag bit 4 signals a 10 byte footer
+    let footer = if bytes[5] & 0x10 != 0 { 10 } else { 0 };
+    bytes.get(10 + size + footer..).unwrap_or(&[])
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    UnknownFormat,
+    Unsupported(&'static str),
+    Wav(hound::Error),
+    Symphonia(SymphoniaError),
+    #[cfg(not(target_arch = "wasm32"))]
+    Opus(audiopus::Error),
+    Empty,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnknownFormat => write!(f, "unrecognised audio format"),
+            DecodeError::Unsupported(what) => write!(f, "unsupported: {}", what),
+            DecodeError::Wav(e) => write!(f, "WAV error: {}", e),
+            DecodeError::Symphonia(e) => write!(f, "decode error: {}", e),
+            #[cfg(not(target_arch = "wasm32"))]
+            DecodeError::Opus(e) => write!(f, "Opus error: {}", e),
+            DecodeError::Empty => write!(f, "file contains no audio"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<hound::Error> for DecodeError {
+    fn from(e: hound::Error) -> Self {
+        DecodeError::Wav(e)
+    }
+}
+
+impl From<SymphoniaError> for DecodeError {
+    fn from(e: SymphoniaError) -> Self {
+        DecodeError::Symphonia(e)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<audiopus::Error> for DecodeError {
+    fn from(e: audiopus::Error) -> Self {
+        DecodeError::Opus(e)
+    }
+}
+
+// Everything that can go wrong getting a track from disk into a Wave
+#[derive(Debug)]
+pub enum LoadError {
+    Io { path: String, source: std::io::Error },
+    Decode { path: String, source: DecodeError },
+    Resample { path: String, source: crate::resample::ResampleError },
+    Midi { path: String, source: crate::midi::MidiError },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io { path, source } => write!(f, "could not read {}: {}", path, source),
+            LoadError::Decode { path, source } => write!(f, "could not decode {}: {}", path, source),
+            LoadError::Resample { path, source } => write!(f, "could not resample {}: {}", path, source),
+            LoadError::Midi { path, source } => write!(f, "could not read MIDI file {}: {}", path, source),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io { source, .. } => Some(source),
+            LoadError::Decode { source, .. } => Some(source),
+            LoadError::Resample { source, .. } => Some(source.as_ref()),
+            LoadError::Midi { source, .. } => Some(source),
+        }
+    }
+}
+
+// Decode a complete audio file held in memory
+pub fn decode_audio(bytes: &[u8]) -> Result<Wave, DecodeError> {
+    let format = AudioFormat::sniff(bytes).ok_or(DecodeError::UnknownFormat)?;
+    println!("[DECODE] Detected format: {}", format.name());
+
+    let wave = match format {
+        AudioFormat::Wav => decode_wav(bytes)?,
+        AudioFormat::Flac | AudioFormat::OggVorbis | AudioFormat::Mp3 => {
+            decode_with_symphonia(symphonia_reader(format, media_stream(bytes))?)?
+        }
+        AudioFormat::OggOpus => decode_opus(bytes)?,
+    };
+
+    if wave.is_empty() {
+        return Err(DecodeError::Empty);
+    }
+
+    println!("[DECODE] Decoded {} frames, {} channels, {} Hz",
+             wave.len(), wave.channels(), wave.sample_rate());
+    Ok(wave)
+}
+
+fn media_stream(bytes: &[u8]) -> MediaSourceStream {
+    MediaSourceStream::new(Box::new(Cursor::new(bytes.to_vec())), Default::default())
+}
+
+// Demuxer for the formats symphonia decodes, over a file or memory alike
+pub fn symphonia_reader(format: AudioFormat, stream: MediaSourceStream) -> Result<Box<dyn FormatReader>, DecodeError> {
+    let options = FormatOptions::default();
+    Ok(match format {
+        AudioFormat::Flac => Box::new(FlacReader::try_new(stream, &options)?),
+        AudioFormat::OggVorbis => Box::new(OggReader::try_new(stream, &options)?),
+        AudioFormat::Mp3 => Box::new(MpaReader::try_new(stream, &options)?),
+        AudioFormat::Wav | AudioFormat::OggOpus => return Err(DecodeError::Unsupported("format not decoded by symphonia")),
+    })
+}
+
+// Build a Wave from interleaved samples
+fn wave_from_interleaved(samples: &[f32], num_channels: usize, sample_rate: f64) -> Wave {
+    let num_samples = samples.len() / num_channels;
+
+    let mut wavefile = Wave::with_capacity(num_channels, sample_rate, num_samples);
+    wavefile.resize(num_samples);
+
+    for channel in 0..num_channels {
+        for pos in 0..num_samples {
+            let sample = samples[pos * num_channels + channel];
+            wavefile.set(channel, pos, sample);
+        }
+    }
+
+    wavefile
+}
+
+fn decode_wav(bytes: &[u8]) -> Result<Wave, DecodeError> {
+    let reader = WavReader::new(Cursor::new(bytes))?;
+    let spec = reader.spec();
+
+    println!("[WAV] File properties: {} channels, {} Hz sample rate, {} bit {:?}",
+             spec.channels, spec.sample_rate, spec.bits_per_sample, spec.sample_format);
+
+    // Any read error is reported instead of silently dropping samples
+    let samples = match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Float, 32) => reader
+            .into_samples::<f32>()
+            .collect::<Result<Vec<f32>, _>>()?,
+        // hound sign-extends every integer depth (and re-centres unsigned
+        // 8 bit) into i32, so full scale is 2^(bits - 1)
+        (SampleFormat::Int, bits @ 8..=32) => {
+            let scale = 1.0 / (1u64 << (bits - 1)) as f32;
+            reader
+                .into_samples::<i32>()
+                .map(|sample| sample.map(|s| s as f32 * scale))
+                .collect::<Result<Vec<f32>, _>>()?
+        }
+        _ => return Err(DecodeError::Unsupported("WAV sample format")),
+    };
+
+    println!("[WAV] Loaded {} samples", samples.len());
+
+    if spec.channels == 0 {
+        return Err(DecodeError::Empty);
+    }
+
+    Ok(wave_from_interleaved(&samples, spec.channels as usize, spec.sample_rate as f64))
+}
+
+// FLAC, Vorbis and MP3 all go through a symphonia demuxer + decoder
+fn decode_with_symphonia(mut reader: Box<dyn FormatReader>) -> Result<Wave, DecodeError> {
+    let track = reader.default_track().ok_or(DecodeError::Empty)?;
+    let track_id = track.id;
+    let params: CodecParameters = track.codec_params.clone();
+
+    let mut decoder = symphonia::default::get_codecs().make(&params, &DecoderOptions::default())?;
+
+    let mut samples: Vec<f32> = Vec::new();
+    let mut num_channels = params.channels.map(|c| c.count()).unwrap_or(0);
+    let mut sample_rate = params.sample_rate.unwrap_or(0);
+    let mut buffer: Option<DecodedBuffer<f32>> = None;
+
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            // End of stream is reported as an unexpected EOF
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                num_channels = spec.channels.count();
+                sample_rate = spec.rate;
+
+                // Reuse the interleave buffer unless this packet is bigger
+                let needed = decoded.capacity() * num_channels;
+                if buffer.as_ref().is_none_or(|b| b.capacity() < needed) {
+                    buffer = Some(DecodedBuffer::new(decoded.capacity() as u64, spec));
+                }
+                if let Some(buf) = buffer.as_mut() {
+                    buf.copy_interleaved_ref(decoded);
+                    samples.extend_from_slice(buf.samples());
+                }
+            }
+            // A corrupt frame is skipped rather than aborting the whole file
+            Err(SymphoniaError::DecodeError(msg)) => {
+                debug!("Skipping undecodable packet: {}", msg);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    if num_channels == 0 || sample_rate == 0 {
+        return Err(DecodeError::Empty);
+    }
+
+    Ok(wave_from_interleaved(&samples, num_channels, sample_rate as f64))
+}
+
+// Opus always decodes at 48 kHz
+#[cfg(not(target_arch = "wasm32"))]
+const OPUS_RATE: u32 = 48_000;
+
+// Opus: symphonia demuxes the Ogg pages but has no Opus codec, so the
+// packets are handed to libopus. Surround files come out in Vorbis channel
+// order and are put in WAVE order, so they are downmixed like any other.
+#[cfg(not(target_arch = "wasm32"))]
+fn decode_opus(bytes: &[u8]) -> Result<Wave, DecodeError> {
+    // Largest Opus frame is 120 ms
+    const MAX_FRAME: usize = 5760;
+
+    let mut reader = OggReader::try_new(media_stream(bytes), &FormatOptions::default())?;
+    let track = reader.default_track().ok_or(DecodeError::Empty)?;
+    let track_id = track.id;
+    let pre_skip = track.codec_params.delay.unwrap_or(0) as usize;
+    // Final granule position: the playback length plus the pre-skip, in
+    // frames. The demuxer reads the last page up front and reports it with
+    // whatever the last packet decodes past it as padding.
+    let end = track
+        .codec_params
+        .n_frames
+        .map(|frames| frames.saturating_sub(track.codec_params.padding.unwrap_or(0) as u64) as usize);
+    let mut decoder = OpusDecoder::new(track.codec_params.extra_data.as_deref().unwrap_or_default())?;
+    let num_channels = decoder.channels;
+
+    let mut samples: Vec<f32> = Vec::new();
+    let mut frame = vec![0.0f32; MAX_FRAME * num_channels];
+
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        if packet.track_id() != track_id || packet.buf().is_empty() {
+            continue;
+        }
+
+        // A corrupt packet is skipped rather than aborting the whole file
+        match decoder.decode(packet.buf(), &mut frame) {
+            Ok(decoded) => samples.extend_from_slice(&frame[..decoded * num_channels]),
+            Err(e) => debug!("Skipping undecodable Opus packet: {}", e),
+        }
+    }
+
+    // Drop the encoder priming samples announced in the OpusHead, and the
+    // padding the last packet decodes past the final granule position
+    // (RFC 7845 section 4)
+    let end = end.map_or(samples.len(), |frames| (frames * num_channels).min(samples.len()));
+    let skip = (pre_skip * num_channels).min(end);
+    let samples = vorbis_to_wave_order(&samples[skip..end], num_channels);
+    Ok(wave_from_interleaved(&samples, num_channels, OPUS_RATE as f64))
+}
+
+// libopus's multistream decoder, which audiopus doesn't wrap. It covers
+// mono and stereo (channel mapping family 0, a single stream) as well as
+// surround (family 1, several streams mapped onto the channels).
+#[cfg(not(target_arch = "wasm32"))]
+struct OpusDecoder {
+    state: std::ptr::NonNull<audiopus::ffi::OpusMSDecoder>,
+    channels: usize,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl OpusDecoder {
+    // Set up from the OpusHead packet (RFC 7845 section 5.1)
+    fn new(head: &[u8]) -> Result<Self, DecodeError> {
+        let channels = match head.get(9) {
+            Some(&count) if count > 0 => count as usize,
+            _ => return Err(DecodeError::Unsupported("Opus file without channels")),
+        };
+        let (streams, coupled, mapping) = match head.get(18) {
+            Some(0) if channels <= 2 => (1, channels as u8 - 1, (0..channels as u8).collect()),
+            Some(1) => match (head.get(19), head.get(20), head.get(21..21 + channels)) {
+                (Some(&streams), Some(&coupled), Some(mapping)) => (streams, coupled, mapping.to_vec()),
+                _ => return Err(DecodeError::Unsupported("truncated Opus channel mapping")),
+            },
+            _ => return Err(DecodeError::Unsupported("Opus channel mapping")),
+        };
+
+        let mut error = 0;
+        // SAFETY: `mapping` has an entry per output channel, which is all
+        // libopus reads from it
+        let state = unsafe {
+            audiopus::ffi::opus_multistream_decoder_create(
+                OPUS_RATE as i32,
+                channels as i32,
+                streams as i32,
+                coupled as i32,
+                mapping.as_ptr(),
+                &mut error,
+            )
+        };
+        match std::ptr::NonNull::new(state) {
+            Some(state) if error == audiopus::ffi::OPUS_OK => Ok(Self { state, channels }),
+            _ => Err(opus_error(error)),
+        }
+    }
+
+    // Decode one packet into `output`, returning the frames written
+    fn decode(&mut self, packet: &[u8], output: &mut [f32]) -> Result<usize, DecodeError> {
+        let frames = output.len() / self.channels;
+        // SAFETY: libopus reads `packet.len()` bytes and writes at most
+        // `frames` interleaved frames, which fit in `output`
+        let decoded = unsafe {
+            audiopus::ffi::opus_multistream_decode_float(
+                self.state.as_ptr(),
+                packet.as_ptr(),
+                packet.len() as i32,
+                output.as_mut_ptr(),
+                frames as i32,
+                0,
+            )
+        };
+        if decoded < 0 {
+            return Err(opus_error(decoded));
+        }
+        Ok(decoded as usize)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for OpusDecoder {
+    fn drop(&mut self) {
+        // SAFETY: created by opus_multistream_decoder_create and freed only here
+        unsafe { audiopus::ffi::opus_multistream_decoder_destroy(self.state.as_ptr()) }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn opus_error(code: i32) -> DecodeError {
+    DecodeError::Opus(audiopus::ErrorCode::from(code).into())
+}
+
+// Opus surround uses the Vorbis channel order (RFC 7845 section 5.1.1.2);
+// shuffle it into the WAVE order the downmix expects. Mono, stereo and
+// quad are the same in both.
+#[cfg(not(target_arch = "wasm32"))]
+fn vorbis_to_wave_order(samples: &[f32], num_channels: usize) -> Vec<f32> {
+    // For each WAVE channel, the Vorbis channel it comes from
+    let order: &[usize] = match num_channels {
+        3 => &[0, 2, 1],                   // L C R
+        5 => &[0, 2, 1, 3, 4],             // FL C FR RL RR
+        6 => &[0, 2, 1, 5, 3, 4],          // FL C FR RL RR LFE
+        7 => &[0, 2, 1, 6, 5, 3, 4],       // FL C FR SL SR RC LFE
+        8 => &[0, 2, 1, 7, 5, 6, 3, 4],    // FL C FR SL SR RL RR LFE
+        _ => return samples.to_vec(),
+    };
+    samples
+        .chunks_exact(num_channels)
+        .flat_map(|frame| order.iter().map(|&channel| frame[channel]))
+        .collect()
+}
+
+// libopus can't be built for wasm32-unknown-unknown and there is no
+// pure-Rust Opus decoder to fall back on, so the web build goes without Opus
+#[cfg(target_arch = "wasm32")]
+fn decode_opus(_bytes: &[u8]) -> Result<Wave, DecodeError> {
+    Err(DecodeError::Unsupported("Opus decoding is not available in the web build"))
+}
+
+// Per-channel (left, right) gains for folding a layout down to stereo.
+// Channel order follows the WAVE/SMPTE convention symphonia also decodes to:
+// FL FR FC LFE BL BR SL SR. Centre and surrounds go in at -3 dB as in
+// ITU-R BS.775; the LFE is dropped.
+fn stereo_downmix_gains(num_channels: usize) -> Vec<(f32, f32)> {
+    const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+    let fl = (1.0, 0.0);
+    let fr = (0.0, 1.0);
+    let fc = (MINUS_3DB, MINUS_3DB);
+    let lfe = (0.0, 0.0);
+    let sl = (MINUS_3DB, 0.0);
+    let sr = (0.0, MINUS_3DB);
+
+    match num_channels {
+        3 => vec![fl, fr, fc],
+        4 => vec![fl, fr, sl, sr],
+        5 => vec![fl, fr, fc, sl, sr],
+        6 => vec![fl, fr, fc, lfe, sl, sr],
+        7 => vec![fl, fr, fc, lfe, fc, sl, sr], // 6.1: back centre like the front one
+        8 => vec![fl, fr, fc, lfe, sl, sr, sl, sr],
+        // Unknown layouts: alternate channels between the two sides
+        n => (0..n).map(|ch| if ch % 2 == 0 { fl } else { fr }).collect(),
+    }
+}
+
+// Interleaved samples of any layout as stereo frames; mono goes to both
+// sides, wider layouts are folded down with `stereo_downmix_gains`
+pub fn stereo_frames(samples: &[f32], num_channels: usize) -> Vec<[f32; 2]> {
+    match num_channels {
+        0 => Vec::new(),
+        1 => samples.iter().map(|&s| [s, s]).collect(),
+        2 => samples.chunks_exact(2).map(|f| [f[0], f[1]]).collect(),
+        n => {
+            let gains = stereo_downmix_gains(n);
+            // Normalise so a full-scale signal on every channel can't clip
+            let left_sum: f32 = gains.iter().map(|g| g.0).sum();
+            let right_sum: f32 = gains.iter().map(|g| g.1).sum();
+            let norm = 1.0 / left_sum.max(right_sum);
+            samples
+                .chunks_exact(n)
+                .map(|frame| {
+                    let (left, right) = frame
+                        .iter()
+                        .zip(&gains)
+                        .fold((0.0, 0.0), |(l, r), (s, g)| (l + s * g.0, r + s * g.1));
+                    [left * norm, right * norm]
+                })
+                .collect()
+        }
+    }
+}
+
+// Fold anything wider than stereo down to two channels, the same way as
+// `stereo_frames`
+pub fn downmix_to_stereo(wave: Wave) -> Wave {
+    let num_channels = wave.channels();
+    if num_channels <= 2 {
+        return wave;
+    }
+
+    println!("[DECODE] Downmixing {} channels to stereo", num_channels);
+
+    let len = wave.len();
+    let mut samples = Vec::with_capacity(len * num_channels);
+    for pos in 0..len {
+        samples.extend((0..num_channels).map(|channel| wave.at(channel, pos)));
+    }
+
+    let mut stereo = Wave::with_capacity(2, wave.sample_rate(), len);
+    stereo.resize(len);
+    for (pos, [left, right]) in stereo_frames(&samples, num_channels).into_iter().enumerate() {
+        stereo.set(0, pos, left);
+        stereo.set(1, pos, right);
+    }
+
+    stereo
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // First page of an Ogg stream whose first packet starts with `packet`
+    fn ogg_page(packet: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.resize(26, 0);
+        page.extend_from_slice(&[1, packet.len() as u8]);
+        page.extend_from_slice(packet);
+        page
+    }
+
+    // An ID3v2 tag of `size` bytes after its header, followed by `body`
+    fn id3(size: usize, body: &[u8]) -> Vec<u8> {
+        let mut tag = b"ID3\x04\x00\x00".to_vec();
+        tag.extend((0..4).rev().map(|i| ((size >> (7 * i)) & 0x7F) as u8));
+        tag.resize(10 + size, 0);
+        tag.extend_from_slice(body);
+        tag
+    }
+
+    #[test]
+    fn sniff_magic_headers() {
+        assert_eq!(AudioFormat::sniff(b"RIFF\x24\x00\x00\x00WAVEfmt "), Some(AudioFormat::Wav));
+        assert_eq!(AudioFormat::sniff(b"fLaC\x00\x00\x00\x22"), Some(AudioFormat::Flac));
+        assert_eq!(AudioFormat::sniff(&ogg_page(b"\x01vorbis\x00\x00\x00\x00")), Some(AudioFormat::OggVorbis));
+        assert_eq!(AudioFormat::sniff(&ogg_page(b"OpusHead\x01\x02")), Some(AudioFormat::OggOpus));
+        // MPEG-1 layer III frame header
+        assert_eq!(AudioFormat::sniff(&[0xFF, 0xFB, 0x90, 0x00]), Some(AudioFormat::Mp3));
+    }
+
+    #[test]
+    fn sniff_behind_id3_tag() {
+        assert_eq!(AudioFormat::sniff(&id3(300, b"fLaC")), Some(AudioFormat::Flac));
+        assert_eq!(AudioFormat::sniff(&id3(300, &[0xFF, 0xFB, 0x90, 0x00])), Some(AudioFormat::Mp3));
+        // Padding before the first frame, or a tag longer than what was read
+        assert_eq!(AudioFormat::sniff(&id3(20, &[0; 16])), Some(AudioFormat::Mp3));
+        assert_eq!(AudioFormat::sniff(&id3(300, b"")[..100]), Some(AudioFormat::Mp3));
+    }
+
+    // A stereo WAV file holding `samples`, interleaved
+    fn wav_bytes<S: hound::Sample + Copy>(bits: u16, format: SampleFormat, samples: &[S]) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate: 44100,
+            bits_per_sample: bits,
+            sample_format: format,
+        };
+        let mut bytes = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        bytes.into_inner()
+    }
+
+    // Left then right channel of a decoded stereo file
+    fn decoded(bytes: &[u8]) -> (Vec<f32>, Vec<f32>) {
+        let wave = decode_audio(bytes).unwrap();
+        assert_eq!((wave.channels(), wave.sample_rate()), (2, 44100.0));
+        let channel = |ch| (0..wave.len()).map(|i| wave.at(ch, i)).collect();
+        (channel(0), channel(1))
+    }
+
+    #[test]
+    fn wav_integer_depths_normalize_to_full_scale() {
+        // Full-scale negative, half scale positive, silence on the right
+        let expected = (vec![-1.0, 0.5], vec![0.0, 0.0]);
+        assert_eq!(decoded(&wav_bytes::<i8>(8, SampleFormat::Int, &[-128, 0, 64, 0])), expected);
+        assert_eq!(decoded(&wav_bytes::<i16>(16, SampleFormat::Int, &[i16::MIN, 0, 16384, 0])), expected);
+        assert_eq!(decoded(&wav_bytes::<i32>(24, SampleFormat::Int, &[-(1 << 23), 0, 1 << 22, 0])), expected);
+        assert_eq!(decoded(&wav_bytes::<i32>(32, SampleFormat::Int, &[i32::MIN, 0, 1 << 30, 0])), expected);
+    }
+
+    #[test]
+    fn wav_float_passes_through() {
+        let (left, right) = decoded(&wav_bytes::<f32>(32, SampleFormat::Float, &[0.25, -0.75, 1.0, 0.0]));
+        assert_eq!((left, right), (vec![0.25, 1.0], vec![-0.75, 0.0]));
+    }
+
+    // Stereo output of a wave with `frame` as its only frame
+    fn downmixed(frame: &[f32]) -> [f32; 2] {
+        let mut wave = Wave::new(0, 48000.0);
+        for &sample in frame {
+            wave.push_channel(&[sample]);
+        }
+        let stereo = downmix_to_stereo(wave);
+        assert_eq!((stereo.channels(), stereo.len()), (2, 1));
+        let result = [stereo.at(0, 0), stereo.at(1, 0)];
+        // Decoded files and streams fold down alike
+        assert_eq!(stereo_frames(frame, frame.len()), vec![result]);
+        result
+    }
+
+    fn assert_close(actual: [f32; 2], expected: [f32; 2]) {
+        assert!(actual.iter().zip(&expected).all(|(a, e)| (a - e).abs() < 1e-6), "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn downmix_5_1() {
+        // FL FR FC LFE SL SR; each side sums to 1 + 2 * -3 dB
+        const H: f32 = std::f32::consts::FRAC_1_SQRT_2;
+        let norm = 1.0 / (1.0 + 2.0 * H);
+        assert_close(downmixed(&[1.0, 0.0, 0.0, 0.0, 0.0, 0.0]), [norm, 0.0]);
+        assert_close(downmixed(&[0.0, 1.0, 0.0, 0.0, 0.0, 0.0]), [0.0, norm]);
+        assert_close(downmixed(&[0.0, 0.0, 1.0, 0.0, 0.0, 0.0]), [H * norm, H * norm]);
+        assert_close(downmixed(&[0.0, 0.0, 0.0, 1.0, 0.0, 0.0]), [0.0, 0.0]);
+        assert_close(downmixed(&[0.0, 0.0, 0.0, 0.0, 1.0, 0.0]), [H * norm, 0.0]);
+        assert_close(downmixed(&[0.0, 0.0, 0.0, 0.0, 0.0, 1.0]), [0.0, H * norm]);
+        // Full scale everywhere reaches full scale without clipping
+        assert_close(downmixed(&[1.0; 6]), [1.0, 1.0]);
+    }
+
+    #[test]
+    fn downmix_7_1() {
+        // FL FR FC LFE BL BR SL SR; each side sums to 1 + 3 * -3 dB
+        const H: f32 = std::f32::consts::FRAC_1_SQRT_2;
+        let norm = 1.0 / (1.0 + 3.0 * H);
+        let mut channel = [0.0; 8];
+        let expected = [[norm, 0.0], [0.0, norm], [H * norm, H * norm], [0.0, 0.0], [H * norm, 0.0], [0.0, H * norm], [H * norm, 0.0], [0.0, H * norm]];
+        for (ch, expected) in expected.into_iter().enumerate() {
+            channel.fill(0.0);
+            channel[ch] = 1.0;
+            assert_close(downmixed(&channel), expected);
+        }
+        assert_close(downmixed(&[1.0; 8]), [1.0, 1.0]);
+    }
+
+    #[test]
+    fn sniff_rejects_unknown() {
+        assert_eq!(AudioFormat::sniff(b""), None);
+        assert_eq!(AudioFormat::sniff(b"RIFF\x24\x00\x00\x00AVI "), None);
+        assert_eq!(AudioFormat::sniff(&ogg_page(b"\x80theora")), None);
+        assert_eq!(AudioFormat::sniff(b"OggS"), None);
+        // Frame sync with the reserved layer
+        assert_eq!(AudioFormat::sniff(&[0xFF, 0xF9, 0x90, 0x00]), None);
+        assert_eq!(AudioFormat::sniff(b"MThd\x00\x00\x00\x06"), None);
+    }
+}
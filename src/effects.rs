@@ -0,0 +1,458 @@
+// Effects chain between the sources and the output
+//
+// The mixer's master bus runs the summed channels through a pre-effects
+// snoop, the chain built here, and the post-effects snoop, so analysis can
+// follow either side of the chain.
+//
+// Parameters are Shared values the graph reads while it runs, so dragging a
+// slider is heard straight away. Adding, removing or reordering effects
+// changes the graph itself and needs the player respawned; so do the few
+// parameters fundsp only takes when a node is built (the reverb's).
+
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+
+use bevy_egui::egui;
+use bevy_fundsp::prelude::*;
+
+// Longest echo the delay can hold, in seconds
+const MAX_DELAY: f32 = 2.0;
+// Smoothing for parameters that would zipper if they jumped
+const SMOOTHING: f32 = 0.02;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EffectKind {
+    Lowpass,
+    Highpass,
+    Bandpass,
+    Delay,
+    Reverb,
+    Distortion,
+    Compressor,
+}
+
+// One adjustable parameter of an effect
+pub struct Param {
+    pub name: &'static str,
+    pub range: RangeInclusive<f32>,
+    pub default: f32,
+    pub logarithmic: bool,
+    // Only read when the graph is built, so changing it respawns the player
+    pub rebuild: bool,
+}
+
+const fn param(name: &'static str, range: RangeInclusive<f32>, default: f32, logarithmic: bool) -> Param {
+    Param { name, range, default, logarithmic, rebuild: false }
+}
+
+const LOWPASS_PARAMS: [Param; 2] = [
+    param("Cutoff (Hz)", 20.0..=20000.0, 1000.0, true),
+    param("Q", 0.1..=10.0, 0.707, true),
+];
+const HIGHPASS_PARAMS: [Param; 2] = [
+    param("Cutoff (Hz)", 20.0..=20000.0, 200.0, true),
+    param("Q", 0.1..=10.0, 0.707, true),
+];
+const BANDPASS_PARAMS: [Param; 2] = [
+    param("Center (Hz)", 20.0..=20000.0, 1000.0, true),
+    param("Q", 0.1..=10.0, 1.0, true),
+];
+const DELAY_PARAMS: [Param; 3] = [
+    param("Time (s)", 0.01..=MAX_DELAY, 0.3, false),
+    param("Feedback", 0.0..=0.95, 0.4, false),
+    param("Mix", 0.0..=1.0, 0.35, false),
+];
+const REVERB_PARAMS: [Param; 4] = [
+    Param { rebuild: true, ..param("Room Size (m)", 1.0..=100.0, 20.0, true) },
+    Param { rebuild: true, ..param("Time (s)", 0.1..=10.0, 2.0, true) },
+    Param { rebuild: true, ..param("Damping", 0.0..=1.0, 0.5, false) },
+    param("Mix", 0.0..=1.0, 0.3, false),
+];
+const DISTORTION_PARAMS: [Param; 2] = [
+    param("Drive", 1.0..=50.0, 5.0, true),
+    param("Level", 0.0..=1.0, 0.5, false),
+];
+const COMPRESSOR_PARAMS: [Param; 5] = [
+    param("Threshold (dB)", -60.0..=0.0, -20.0, false),
+    param("Ratio", 1.0..=20.0, 4.0, true),
+    param("Attack (ms)", 0.1..=100.0, 5.0, true),
+    param("Release (ms)", 10.0..=1000.0, 100.0, true),
+    param("Makeup (dB)", 0.0..=24.0, 0.0, false),
+];
+
+impl EffectKind {
+    pub const ALL: [EffectKind; 7] = [
+        EffectKind::Lowpass,
+        EffectKind::Highpass,
+        EffectKind::Bandpass,
+        EffectKind::Delay,
+        EffectKind::Reverb,
+        EffectKind::Distortion,
+        EffectKind::Compressor,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EffectKind::Lowpass => "Low-pass",
+            EffectKind::Highpass => "High-pass",
+            EffectKind::Bandpass => "Band-pass",
+            EffectKind::Delay => "Delay",
+            EffectKind::Reverb => "Reverb",
+            EffectKind::Distortion => "Distortion",
+            EffectKind::Compressor => "Compressor",
+        }
+    }
+
+    pub fn params(&self) -> &'static [Param] {
+        match self {
+            EffectKind::Lowpass => &LOWPASS_PARAMS,
+            EffectKind::Highpass => &HIGHPASS_PARAMS,
+            EffectKind::Bandpass => &BANDPASS_PARAMS,
+            EffectKind::Delay => &DELAY_PARAMS,
+            EffectKind::Reverb => &REVERB_PARAMS,
+            EffectKind::Distortion => &DISTORTION_PARAMS,
+            EffectKind::Compressor => &COMPRESSOR_PARAMS,
+        }
+    }
+}
+
+// An effect in the chain; values are in the order of `kind.params()`
+#[derive(Clone)]
+pub struct Effect {
+    pub kind: EffectKind,
+    pub values: Vec<Shared>,
+}
+
+impl Effect {
+    pub fn new(kind: EffectKind) -> Self {
+        Self {
+            kind,
+            values: kind.params().iter().map(|p| shared(p.default)).collect(),
+        }
+    }
+
+    fn smoothed(&self, index: usize) -> An<impl AudioNode<Inputs = U0, Outputs = U1>> {
+        var(&self.values[index]) >> follow(SMOOTHING)
+    }
+
+    // Stereo graph of the effect, U2 -> U2. Filters, the delay and the
+    // distortion run a mono copy per channel; the reverb and the compressor
+    // work on both channels together.
+    fn graph(&self) -> Net {
+        match self.kind {
+            EffectKind::Lowpass => per_channel(|| (pass() | self.smoothed(0) | self.smoothed(1)) >> lowpass()),
+            EffectKind::Highpass => per_channel(|| (pass() | self.smoothed(0) | self.smoothed(1)) >> highpass()),
+            EffectKind::Bandpass => per_channel(|| (pass() | self.smoothed(0) | self.smoothed(1)) >> bandpass()),
+            EffectKind::Delay => per_channel(|| {
+                Echo::new(self.values[0].clone(), self.values[1].clone(), self.values[2].clone())
+            }),
+            EffectKind::Distortion => per_channel(|| ((pass() * self.smoothed(0)) >> shape(Tanh(1.0))) * self.smoothed(1)),
+            EffectKind::Reverb => {
+                let value = |i: usize| self.values[i].value() as f64;
+                // Crossfade like the delay's mix: dry at 0, all reverb at 1
+                let dry = multipass::<U2>() * ((1.0 - self.smoothed(3)) >> split::<U2>());
+                let wet = reverb_stereo(value(0), value(1), value(2)) * (self.smoothed(3) >> split::<U2>());
+                Net::wrap(Box::new(dry & wet))
+            }
+            EffectKind::Compressor => Net::wrap(Box::new(Compressor::new(self.values.clone()))),
+        }
+    }
+}
+
+// A mono effect on each side of a stereo signal, one copy per channel
+fn per_channel<N: AudioNode<Inputs = U1, Outputs = U1> + 'static>(channel: impl Fn() -> An<N>) -> Net {
+    Net::wrap(Box::new(channel())) | Net::wrap(Box::new(channel()))
+}
+
+// The effects list shared by the mixer graph and the UI
+pub type EffectSlot = Arc<Mutex<Vec<Effect>>>;
+
+// Stereo chain of the effects in order; a plain pass-through when empty
+pub fn build_chain(effects: &[Effect]) -> Net {
+    effects
+        .iter()
+        .fold(Net::wrap(Box::new(multipass::<U2>())), |chain, effect| chain >> effect.graph())
+}
+
+// Feedback delay with a dry/wet mix. Time, feedback and mix are read every
+// sample; the time is smoothed so moving it bends the pitch rather than clicking.
+#[derive(Clone)]
+pub struct Echo {
+    time: Shared,
+    feedback: Shared,
+    mix: Shared,
+    buffer: Vec<f32>,
+    write: usize,
+    delay: f32, // current delay in samples
+    sample_rate: f32,
+}
+
+impl Echo {
+    pub fn new(time: Shared, feedback: Shared, mix: Shared) -> An<Self> {
+        let mut echo = Self {
+            time,
+            feedback,
+            mix,
+            buffer: Vec::new(),
+            write: 0,
+            delay: 0.0,
+            sample_rate: 0.0,
+        };
+        echo.set_sample_rate(44100.0);
+        An(echo)
+    }
+
+    // Buffer sample `delay` samples back, linearly interpolated
+    fn read(&self, delay: f32) -> f32 {
+        let len = self.buffer.len();
+        let whole = delay.floor() as usize;
+        let frac = delay - whole as f32;
+        let a = self.buffer[(self.write + len - whole) % len];
+        let b = self.buffer[(self.write + len - whole - 1) % len];
+        a + (b - a) * frac
+    }
+}
+
+impl AudioNode for Echo {
+    const ID: u64 = 0x6563_686f;
+    type Inputs = U1;
+    type Outputs = U1;
+
+    fn reset(&mut self) {
+        self.buffer.fill(0.0);
+        self.write = 0;
+        self.delay = self.time.value() * self.sample_rate;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate as f32;
+        self.buffer = vec![0.0; (MAX_DELAY * self.sample_rate) as usize + 2];
+        self.reset();
+    }
+
+    fn tick(&mut self, input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let max = (self.buffer.len() - 2) as f32;
+        let target = (self.time.value() * self.sample_rate).clamp(1.0, max);
+        self.delay += (target - self.delay) / (SMOOTHING * self.sample_rate);
+        self.delay = self.delay.clamp(1.0, max);
+
+        // The slot about to be written is this sample's, so reading back
+        // from it puts the echo `delay` samples after its input
+        self.write = (self.write + 1) % self.buffer.len();
+        let delayed = self.read(self.delay);
+        let feedback = self.feedback.value().clamp(0.0, 0.95);
+        self.buffer[self.write] = input[0] + delayed * feedback;
+
+        let mix = self.mix.value().clamp(0.0, 1.0);
+        [input[0] * (1.0 - mix) + delayed * mix].into()
+    }
+}
+
+// Stereo-linked feed-forward compressor. Values are threshold (dB), ratio,
+// attack (ms), release (ms) and makeup gain (dB), as in COMPRESSOR_PARAMS.
+#[derive(Clone)]
+pub struct Compressor {
+    values: Vec<Shared>,
+    reduction: f32, // current gain reduction in dB, >= 0
+    sample_rate: f32,
+}
+
+impl Compressor {
+    pub fn new(values: Vec<Shared>) -> An<Self> {
+        An(Self { values, reduction: 0.0, sample_rate: 44100.0 })
+    }
+
+    // One-pole coefficient for a time constant in milliseconds
+    fn coefficient(&self, ms: f32) -> f32 {
+        (-1.0 / (ms.max(0.01) * 0.001 * self.sample_rate)).exp()
+    }
+}
+
+impl AudioNode for Compressor {
+    const ID: u64 = 0x636f_6d70;
+    type Inputs = U2;
+    type Outputs = U2;
+
+    fn reset(&mut self) {
+        self.reduction = 0.0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate as f32;
+    }
+
+    fn tick(&mut self, input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let threshold = self.values[0].value();
+        let ratio = self.values[1].value().max(1.0);
+        let makeup = self.values[4].value();
+
+        // Both channels follow the louder one so the stereo image doesn't shift
+        let peak = input[0].abs().max(input[1].abs());
+        let level = 20.0 * peak.max(1e-6).log10();
+        let target = (level - threshold).max(0.0) * (1.0 - 1.0 / ratio);
+
+        let coefficient = if target > self.reduction {
+            self.coefficient(self.values[2].value())
+        } else {
+            self.coefficient(self.values[3].value())
+        };
+        self.reduction = target + (self.reduction - target) * coefficient;
+
+        let gain = 10f32.powf((makeup - self.reduction) / 20.0);
+        [input[0] * gain, input[1] * gain].into()
+    }
+}
+
+// Side panel section for the effects chain. Returns true when the chain's
+// graph needs rebuilding to pick up a change.
+pub fn effects_panel(ui: &mut egui::Ui, effects: &mut Vec<Effect>) -> bool {
+    let mut rebuild = false;
+    let mut move_up = None;
+    let mut remove = None;
+    let count = effects.len();
+
+    for (index, effect) in effects.iter().enumerate() {
+        ui.push_id(index, |ui| {
+            ui.horizontal(|ui| {
+                ui.label(format!("{}. {}", index + 1, effect.kind.name()));
+                if ui.add_enabled(index > 0, egui::Button::new("Up")).clicked() {
+                    move_up = Some(index);
+                }
+                if ui.add_enabled(index + 1 < count, egui::Button::new("Down")).clicked() {
+                    move_up = Some(index + 1);
+                }
+                if ui.button("Remove").clicked() {
+                    remove = Some(index);
+                }
+            });
+            for (param, shared) in effect.kind.params().iter().zip(&effect.values) {
+                let mut value = shared.value();
+                let response = ui.add(
+                    egui::Slider::new(&mut value, param.range.clone())
+                        .logarithmic(param.logarithmic)
+                        .text(param.name),
+                );
+                if response.changed() {
+                    shared.set_value(value);
+                }
+                // Rebuild once the drag is over, not on every step of it
+                if param.rebuild && (response.drag_stopped() || (response.changed() && !response.dragged())) {
+                    rebuild = true;
+                }
+            }
+        });
+    }
+
+    if let Some(index) = move_up {
+        effects.swap(index - 1, index);
+        rebuild = true;
+    }
+    if let Some(index) = remove {
+        effects.remove(index);
+        rebuild = true;
+    }
+
+    egui::ComboBox::from_id_salt("add_effect")
+        .selected_text("Add effect...")
+        .show_ui(ui, |ui| {
+            for kind in EffectKind::ALL {
+                if ui.selectable_label(false, kind.name()).clicked() {
+                    effects.push(Effect::new(kind));
+                    rebuild = true;
+                }
+            }
+        });
+
+    rebuild
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Milliseconds are samples, which keeps the times readable
+    const RATE: f64 = 1000.0;
+
+    fn echo(time: f32, feedback: f32, mix: f32) -> Box<dyn AudioUnit> {
+        let mut echo: Box<dyn AudioUnit> = Box::new(Echo::new(shared(time), shared(feedback), shared(mix)));
+        echo.set_sample_rate(RATE);
+        echo
+    }
+
+    fn impulse_response(unit: &mut dyn AudioUnit, len: usize) -> Vec<f32> {
+        (0..len).map(|i| unit.filter_mono(if i == 0 { 1.0 } else { 0.0 })).collect()
+    }
+
+    // Sample indices and values of the non-zero samples
+    fn nonzero(samples: &[f32]) -> Vec<(usize, f32)> {
+        samples.iter().copied().enumerate().filter(|(_, s)| s.abs() > 1e-6).collect()
+    }
+
+    #[test]
+    fn echo_delays_by_its_time() {
+        let response = impulse_response(&mut *echo(0.01, 0.0, 1.0), 50);
+        assert_eq!(nonzero(&response), vec![(10, 1.0)]);
+    }
+
+    #[test]
+    fn echo_feedback_repeats_and_decays() {
+        // Half dry, half echoes that halve each time round
+        let response = impulse_response(&mut *echo(0.01, 0.5, 0.5), 45);
+        assert_eq!(nonzero(&response), vec![(0, 0.5), (10, 0.5), (20, 0.25), (30, 0.125), (40, 0.0625)]);
+    }
+
+    #[test]
+    fn echo_step_settles_at_the_feedback_gain() {
+        // A held input adds up to 1 / (1 - feedback) on the wet side
+        let mut unit = echo(0.01, 0.75, 1.0);
+        let response: Vec<f32> = (0..1000).map(|_| unit.filter_mono(1.0)).collect();
+        assert!(response[..10].iter().all(|s| *s == 0.0));
+        assert!((response[999] - 4.0).abs() < 1e-3, "{}", response[999]);
+    }
+
+    // Threshold -20 dB, ratio 4, attack 5 ms, release 100 ms, no makeup
+    fn compressor(makeup: f32) -> Box<dyn AudioUnit> {
+        let values = [-20.0, 4.0, 5.0, 100.0, makeup].map(shared).to_vec();
+        let mut compressor: Box<dyn AudioUnit> = Box::new(Compressor::new(values));
+        compressor.set_sample_rate(RATE);
+        compressor
+    }
+
+    fn gain_db(input: f32, output: f32) -> f32 {
+        20.0 * (output / input).log10()
+    }
+
+    #[test]
+    fn compressor_leaves_quiet_signals_alone() {
+        let mut unit = compressor(0.0);
+        for _ in 0..100 {
+            assert_eq!(unit.filter_stereo(0.05, -0.05), (0.05, -0.05));
+        }
+    }
+
+    #[test]
+    fn compressor_reduces_gain_above_threshold() {
+        // 0 dB is 20 dB over: a quarter of that gets through, 15 dB of reduction
+        let mut unit = compressor(0.0);
+        let attack: Vec<f32> = (0..200).map(|_| unit.filter_stereo(1.0, 0.5).0).collect();
+        assert!((gain_db(1.0, attack[199]) + 15.0).abs() < 0.01, "{}", gain_db(1.0, attack[199]));
+        // Most of the way there after one attack time, and never overshooting
+        assert!(gain_db(1.0, attack[5]) < -8.0 && gain_db(1.0, attack[5]) > -12.0, "{}", gain_db(1.0, attack[5]));
+        assert!(attack.windows(2).all(|w| w[1] <= w[0]));
+        // Both channels get the same gain, so the right one is still half the left
+        let (left, right) = unit.filter_stereo(1.0, 0.5);
+        assert!((right / left - 0.5).abs() < 1e-6);
+
+        // Releases more slowly once the signal drops below the threshold
+        let release: Vec<f32> = (0..1000).map(|_| unit.filter_stereo(0.05, 0.05).0).collect();
+        assert!(gain_db(0.05, release[10]) < -10.0, "{}", gain_db(0.05, release[10]));
+        assert!(gain_db(0.05, release[999]).abs() < 0.01, "{}", gain_db(0.05, release[999]));
+    }
+
+    #[test]
+    fn compressor_makeup_gain() {
+        let mut unit = compressor(6.0);
+        let (left, _) = unit.filter_stereo(0.05, 0.05);
+        assert!((gain_db(0.05, left) - 6.0).abs() < 1e-3);
+    }
+}
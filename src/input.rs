@@ -0,0 +1,373 @@
+// Live audio input
+//
+// Capture backends implement `AudioInput` and push what they record into a
+// PcmQueue. `LiveInputDsp` plays that queue like any other source, so
+// analysis doesn't care where the audio came from. Its mixer channel starts
+// at zero gain to avoid feeding the speakers back into a microphone.
+
+use std::fmt;
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_fundsp::prelude::*;
+
+use crate::pcm_queue::{PcmQueue, QueuePlayer};
+
+#[derive(Debug)]
+pub enum InputError {
+    NoDevice(String),
+    Backend(String),
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::NoDevice(name) => write!(f, "input device not found: {}", name),
+            InputError::Backend(msg) => write!(f, "input error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+pub trait AudioInput: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn devices(&self) -> Vec<String>;
+
+    // Begin pushing captured audio into `queue`, converted to `sample_rate`
+    fn start(&mut self, device: &str, queue: PcmQueue, sample_rate: f32) -> Result<(), InputError>;
+
+    fn stop(&mut self);
+
+    // Called every frame; backends without their own thread produce audio here
+    fn poll(&mut self, _now: f64) {}
+}
+
+// System capture through cpal. The cpal stream isn't Send, so it lives on
+// its own thread until the stop channel is dropped.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+pub struct CpalInput {
+    stop: Option<std::sync::mpsc::Sender<()>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl CpalInput {
+    fn open_stream(name: &str, queue: PcmQueue, sample_rate: f32) -> Result<cpal::Stream, InputError> {
+        use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+        use cpal::SampleFormat;
+
+        let backend = |e: &dyn fmt::Display| InputError::Backend(e.to_string());
+
+        let host = cpal::default_host();
+        let device = host
+            .input_devices()
+            .map_err(|e| backend(&e))?
+            .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+            .ok_or_else(|| InputError::NoDevice(name.to_string()))?;
+
+        let supported = device.default_input_config().map_err(|e| backend(&e))?;
+        let config: cpal::StreamConfig = supported.config();
+        println!("[INPUT] Capturing {}: {} channels, {} Hz, {:?}",
+                 name, config.channels, config.sample_rate.0, supported.sample_format());
+
+        let converter = crate::pcm_queue::PcmConverter::new(
+            config.channels as usize,
+            config.sample_rate.0 as f32,
+            sample_rate,
+        );
+
+        let stream = match supported.sample_format() {
+            SampleFormat::F32 => Self::build::<f32>(&device, &config, converter, queue),
+            SampleFormat::I16 => Self::build::<i16>(&device, &config, converter, queue),
+            SampleFormat::U16 => Self::build::<u16>(&device, &config, converter, queue),
+            SampleFormat::I32 => Self::build::<i32>(&device, &config, converter, queue),
+            other => Err(InputError::Backend(format!("unsupported sample format {:?}", other))),
+        }?;
+        stream.play().map_err(|e| backend(&e))?;
+        Ok(stream)
+    }
+
+    fn build<T>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        mut converter: crate::pcm_queue::PcmConverter,
+        queue: PcmQueue,
+    ) -> Result<cpal::Stream, InputError>
+    where
+        T: cpal::SizedSample,
+        f32: cpal::FromSample<T>,
+    {
+        use cpal::traits::DeviceTrait;
+
+        let mut scratch: Vec<f32> = Vec::new();
+        device
+            .build_input_stream(
+                config,
+                move |data: &[T], _: &cpal::InputCallbackInfo| {
+                    scratch.clear();
+                    scratch.extend(data.iter().map(|&s| s.to_sample::<f32>()));
+                    converter.push_interleaved(&scratch, &queue);
+                },
+                |e| eprintln!("[INPUT] Stream error: {}", e),
+                None,
+            )
+            .map_err(|e| InputError::Backend(e.to_string()))
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AudioInput for CpalInput {
+    fn name(&self) -> &'static str {
+        "System"
+    }
+
+    fn devices(&self) -> Vec<String> {
+        use cpal::traits::{DeviceTrait, HostTrait};
+
+        cpal::default_host()
+            .input_devices()
+            .map(|devices| devices.filter_map(|d| d.name().ok()).collect())
+            .unwrap_or_default()
+    }
+
+    fn start(&mut self, device: &str, queue: PcmQueue, sample_rate: f32) -> Result<(), InputError> {
+        self.stop();
+
+        let name = device.to_string();
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        let (stop_tx, stop_rx) = std::sync::mpsc::channel::<()>();
+
+        std::thread::spawn(move || match Self::open_stream(&name, queue, sample_rate) {
+            Ok(stream) => {
+                let _ = ready_tx.send(Ok(()));
+                // Blocks until the sender is dropped by stop()
+                let _ = stop_rx.recv();
+                drop(stream);
+                println!("[INPUT] Capture stopped");
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+            }
+        });
+
+        ready_rx
+            .recv()
+            .map_err(|_| InputError::Backend(String::from("capture thread exited")))??;
+        self.stop = Some(stop_tx);
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.stop = None;
+    }
+}
+
+// Fake backend generating known signals, for machines without a sound card
+pub struct SyntheticInput {
+    device: Option<String>,
+    queue: Option<PcmQueue>,
+    sample_rate: f64,
+    started_at: Option<f64>,
+    produced: u64,
+    phase: f64,
+}
+
+impl Default for SyntheticInput {
+    fn default() -> Self {
+        Self {
+            device: None,
+            queue: None,
+            sample_rate: 44100.0,
+            started_at: None,
+            produced: 0,
+            phase: 0.0,
+        }
+    }
+}
+
+impl SyntheticInput {
+    const SINE: &'static str = "Sine 440 Hz";
+    const NOISE: &'static str = "White noise";
+}
+
+impl AudioInput for SyntheticInput {
+    fn name(&self) -> &'static str {
+        "Synthetic"
+    }
+
+    fn devices(&self) -> Vec<String> {
+        vec![Self::SINE.to_string(), Self::NOISE.to_string()]
+    }
+
+    fn start(&mut self, device: &str, queue: PcmQueue, sample_rate: f32) -> Result<(), InputError> {
+        if !self.devices().iter().any(|d| d == device) {
+            return Err(InputError::NoDevice(device.to_string()));
+        }
+        self.device = Some(device.to_string());
+        self.queue = Some(queue);
+        self.sample_rate = sample_rate as f64;
+        self.started_at = None;
+        self.produced = 0;
+        Ok(())
+    }
+
+    fn stop(&mut self) {
+        self.device = None;
+        self.queue = None;
+    }
+
+    fn poll(&mut self, now: f64) {
+        let (Some(device), Some(queue)) = (&self.device, &self.queue) else {
+            return;
+        };
+        let started_at = *self.started_at.get_or_insert(now);
+
+        // Produce exactly as much audio as wall-clock time has passed
+        let due = ((now - started_at) * self.sample_rate) as u64;
+        let count = due.saturating_sub(self.produced) as usize;
+        self.produced = due;
+
+        let step = 440.0 / self.sample_rate;
+        let mut frames = Vec::with_capacity(count);
+        for _ in 0..count {
+            let value = if device == Self::SINE {
+                self.phase = (self.phase + step).fract();
+                (self.phase * std::f64::consts::TAU).sin() as f32 * 0.5
+            } else {
+                (rand::random::<f32>() * 2.0 - 1.0) * 0.5
+            };
+            frames.push([value, value]);
+        }
+        queue.push_frames(frames);
+    }
+}
+
+// Graph for the live input source
+pub struct LiveInputDsp {
+    pub queue: PcmQueue,
+}
+
+impl DspGraph for LiveInputDsp {
+    fn id(&self) -> Uuid {
+        crate::sources::LIVE_INPUT
+    }
+
+    fn generate_graph(&self) -> Box<dyn AudioUnit> {
+        Box::new(QueuePlayer::new(self.queue.clone()))
+    }
+}
+
+#[derive(Resource)]
+pub struct LiveInput {
+    backends: Vec<Box<dyn AudioInput>>,
+    backend: usize,
+    device: Option<String>,
+    devices: Vec<String>,
+    // (backend, device) currently capturing
+    running: Option<(usize, String)>,
+    error: Option<String>,
+    pub queue: PcmQueue,
+}
+
+impl LiveInput {
+    pub fn new(queue: PcmQueue) -> Self {
+        let mut backends: Vec<Box<dyn AudioInput>> = Vec::new();
+        #[cfg(not(target_arch = "wasm32"))]
+        backends.push(Box::new(CpalInput::default()));
+        backends.push(Box::new(SyntheticInput::default()));
+
+        let devices = backends[0].devices();
+        Self {
+            backends,
+            backend: 0,
+            device: devices.first().cloned(),
+            devices,
+            running: None,
+            error: None,
+            queue,
+        }
+    }
+
+    fn select_backend(&mut self, backend: usize) {
+        self.backend = backend;
+        self.devices = self.backends[backend].devices();
+        self.device = self.devices.first().cloned();
+    }
+
+    // Start or stop capture so it matches whether the source is in the mix
+    pub fn sync(&mut self, active: bool, sample_rate: f32, now: f64) {
+        let wanted = match (&self.device, active) {
+            (Some(device), true) => Some((self.backend, device.clone())),
+            _ => None,
+        };
+
+        if wanted != self.running {
+            if let Some((backend, _)) = self.running.take() {
+                self.backends[backend].stop();
+                self.queue.clear();
+            }
+            if let Some((backend, device)) = wanted {
+                match self.backends[backend].start(&device, self.queue.clone(), sample_rate) {
+                    Ok(()) => {
+                        self.error = None;
+                        self.running = Some((backend, device));
+                    }
+                    Err(e) => {
+                        eprintln!("[INPUT] {}", e);
+                        self.error = Some(e.to_string());
+                        // Don't retry every frame, wait for a new selection
+                        self.device = None;
+                    }
+                }
+            }
+        }
+
+        if let Some((backend, _)) = &self.running {
+            self.backends[*backend].poll(now);
+        }
+    }
+}
+
+// Side panel section for the live input source
+pub fn live_input_panel(ui: &mut egui::Ui, input: &mut LiveInput) {
+    ui.separator();
+    ui.label("Live Input");
+
+    let mut backend = input.backend;
+    egui::ComboBox::from_label("Backend")
+        .selected_text(input.backends[backend].name())
+        .show_ui(ui, |ui| {
+            for (index, b) in input.backends.iter().enumerate() {
+                ui.selectable_value(&mut backend, index, b.name());
+            }
+        });
+    if backend != input.backend {
+        input.select_backend(backend);
+    }
+
+    ui.horizontal(|ui| {
+        let mut device = input.device.clone();
+        egui::ComboBox::from_label("Device")
+            .selected_text(device.as_deref().unwrap_or("none"))
+            .show_ui(ui, |ui| {
+                for d in &input.devices {
+                    ui.selectable_value(&mut device, Some(d.clone()), d);
+                }
+            });
+        if device != input.device {
+            input.device = device;
+        }
+        if ui.small_button("Refresh").clicked() {
+            input.devices = input.backends[input.backend].devices();
+        }
+    });
+
+    ui.label(format!("Buffered: {} / {} frames, {} underruns, {} frames dropped",
+                     input.queue.len(), input.queue.capacity(), input.queue.underruns(), input.queue.overflows()));
+
+    if let Some(error) = &input.error {
+        ui.colored_label(egui::Color32::RED, error);
+    }
+}
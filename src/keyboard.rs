@@ -0,0 +1,503 @@
+// Computer keyboard synth
+//
+// The home row plays like a piano (A = C, W = C#, S = D, ... ; = E an
+// octave up), Z and X shift the octave. Each key press takes a voice from a
+// fixed pool; every voice is the oscillator bank from oscillator.rs shaped
+// by its own ADSR envelope, and the pool is summed in one fundsp graph.
+//
+// The ECS side only writes per-voice pitch, gate and a note counter into
+// Shared values. A change of counter retriggers the envelope, so a stolen
+// voice restarts its attack even though its gate never went low.
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_fundsp::prelude::*;
+
+use crate::oscillator::Oscillator;
+
+// Size of the voice pool the graph is built with; the UI limit is at most this
+pub const MAX_VOICES: usize = 8;
+type MaxVoices = U8;
+
+// Keys of the lowest octave in semitones above C
+const NOTE_KEYS: [(KeyCode, i32); 17] = [
+    (KeyCode::KeyA, 0),
+    (KeyCode::KeyW, 1),
+    (KeyCode::KeyS, 2),
+    (KeyCode::KeyE, 3),
+    (KeyCode::KeyD, 4),
+    (KeyCode::KeyF, 5),
+    (KeyCode::KeyT, 6),
+    (KeyCode::KeyG, 7),
+    (KeyCode::KeyY, 8),
+    (KeyCode::KeyH, 9),
+    (KeyCode::KeyU, 10),
+    (KeyCode::KeyJ, 11),
+    (KeyCode::KeyK, 12),
+    (KeyCode::KeyO, 13),
+    (KeyCode::KeyL, 14),
+    (KeyCode::KeyP, 15),
+    (KeyCode::Semicolon, 16),
+];
+const OCTAVE_DOWN: KeyCode = KeyCode::KeyZ;
+const OCTAVE_UP: KeyCode = KeyCode::KeyX;
+const OCTAVE_RANGE: std::ops::RangeInclusive<i32> = -3..=3;
+
+// MIDI note the A key plays at octave shift 0
+const BASE_NOTE: i32 = 60; // middle C
+
+pub fn note_to_hz(note: i32) -> f32 {
+    440.0 * 2f32.powf((note - 69) as f32 / 12.0)
+}
+
+// ADSR times in seconds, sustain as a level
+#[derive(Clone)]
+pub struct AdsrParams {
+    pub attack: Shared,
+    pub decay: Shared,
+    pub sustain: Shared,
+    pub release: Shared,
+}
+
+impl Default for AdsrParams {
+    fn default() -> Self {
+        Self {
+            attack: shared(0.01),
+            decay: shared(0.15),
+            sustain: shared(0.7),
+            release: shared(0.3),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+// Envelope generator. Inputs are the gate and the note counter; the output
+// is the envelope level. Times are read every sample so edits apply to
+// notes already sounding.
+#[derive(Clone)]
+pub struct Adsr {
+    params: AdsrParams,
+    stage: Stage,
+    level: f32,
+    release_step: f32,
+    note: f32,
+    sample_rate: f32,
+}
+
+impl Adsr {
+    pub fn new(params: AdsrParams) -> An<Self> {
+        An(Self {
+            params,
+            stage: Stage::Idle,
+            level: 0.0,
+            release_step: 0.0,
+            note: 0.0,
+            sample_rate: 44100.0,
+        })
+    }
+
+    // Per-sample change covering `range` in `seconds`
+    fn step(&self, range: f32, seconds: f32) -> f32 {
+        range / (seconds.max(0.001) * self.sample_rate)
+    }
+}
+
+impl AudioNode for Adsr {
+    const ID: u64 = 0x6164_7372;
+    type Inputs = U2;
+    type Outputs = U1;
+
+    fn reset(&mut self) {
+        self.stage = Stage::Idle;
+        self.level = 0.0;
+        self.note = 0.0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.sample_rate = sample_rate as f32;
+    }
+
+    fn tick(&mut self, input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let gate = input[0] > 0.5;
+        let note = input[1];
+        let sustain = self.params.sustain.value().clamp(0.0, 1.0);
+
+        if gate && (note != self.note || matches!(self.stage, Stage::Idle | Stage::Release)) {
+            // Start from the current level rather than zero so retriggers don't click
+            self.stage = Stage::Attack;
+        } else if !gate && !matches!(self.stage, Stage::Idle | Stage::Release) {
+            self.stage = Stage::Release;
+            self.release_step = self.step(self.level, self.params.release.value());
+        }
+        self.note = note;
+
+        match self.stage {
+            Stage::Idle => self.level = 0.0,
+            Stage::Attack => {
+                self.level += self.step(1.0, self.params.attack.value());
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = Stage::Decay;
+                }
+            }
+            Stage::Decay => {
+                self.level -= self.step(1.0 - sustain, self.params.decay.value());
+                if self.level <= sustain {
+                    self.level = sustain;
+                    self.stage = Stage::Sustain;
+                }
+            }
+            Stage::Sustain => self.level = sustain,
+            Stage::Release => {
+                self.level -= self.release_step;
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = Stage::Idle;
+                }
+            }
+        }
+        [self.level].into()
+    }
+}
+
+// Per-voice controls written by the ECS and read by the graph
+#[derive(Clone)]
+pub struct VoiceParams {
+    pub pitch: Shared,
+    pub gate: Shared,
+    pub note: Shared,
+}
+
+// What the ECS remembers about a voice
+struct Voice {
+    params: VoiceParams,
+    key: Option<KeyCode>,
+    // Order the voice was last started or released in, for stealing
+    age: u64,
+}
+
+#[derive(Resource)]
+pub struct KeyboardSynth {
+    voices: Vec<Voice>,
+    pub adsr: AdsrParams,
+    pub voice_limit: usize,
+    pub octave: i32,
+    clock: u64,
+    notes_started: u64,
+}
+
+impl Default for KeyboardSynth {
+    fn default() -> Self {
+        Self {
+            voices: (0..MAX_VOICES)
+                .map(|_| Voice {
+                    params: VoiceParams { pitch: shared(440.0), gate: shared(0.0), note: shared(0.0) },
+                    key: None,
+                    age: 0,
+                })
+                .collect(),
+            adsr: AdsrParams::default(),
+            voice_limit: MAX_VOICES,
+            octave: 0,
+            clock: 0,
+            notes_started: 0,
+        }
+    }
+}
+
+impl KeyboardSynth {
+    pub fn voice_params(&self) -> Vec<VoiceParams> {
+        self.voices.iter().map(|v| v.params.clone()).collect()
+    }
+
+    pub fn active_voices(&self) -> usize {
+        self.voices.iter().filter(|v| v.key.is_some()).count()
+    }
+
+    fn note_on(&mut self, key: KeyCode, note: i32) {
+        let limit = self.voice_limit.clamp(1, MAX_VOICES);
+        // Longest-released free voice, otherwise steal the oldest held one
+        let index = (0..limit)
+            .filter(|&i| self.voices[i].key.is_none())
+            .min_by_key(|&i| self.voices[i].age)
+            .or_else(|| (0..limit).min_by_key(|&i| self.voices[i].age))
+            .unwrap();
+
+        self.clock += 1;
+        self.notes_started += 1;
+        let voice = &mut self.voices[index];
+        voice.key = Some(key);
+        voice.age = self.clock;
+        voice.params.pitch.set_value(note_to_hz(note));
+        voice.params.note.set_value(self.notes_started as f32);
+        voice.params.gate.set_value(1.0);
+    }
+
+    fn note_off(&mut self, index: usize) {
+        self.clock += 1;
+        let voice = &mut self.voices[index];
+        voice.key = None;
+        voice.age = self.clock;
+        voice.params.gate.set_value(0.0);
+    }
+
+    pub fn all_notes_off(&mut self) {
+        for index in 0..self.voices.len() {
+            if self.voices[index].key.is_some() {
+                self.note_off(index);
+            }
+        }
+    }
+
+    pub fn handle_keys(&mut self, keys: &ButtonInput<KeyCode>) {
+        if keys.just_pressed(OCTAVE_DOWN) && self.octave > *OCTAVE_RANGE.start() {
+            self.octave -= 1;
+        }
+        if keys.just_pressed(OCTAVE_UP) && self.octave < *OCTAVE_RANGE.end() {
+            self.octave += 1;
+        }
+
+        for (key, offset) in NOTE_KEYS {
+            if keys.just_released(key) {
+                if let Some(index) = self.voices.iter().position(|v| v.key == Some(key)) {
+                    self.note_off(index);
+                }
+            }
+            if keys.just_pressed(key) {
+                self.note_on(key, BASE_NOTE + 12 * self.octave + offset);
+            }
+        }
+
+        // Voices over a lowered limit are let go
+        for index in self.voice_limit.max(1)..self.voices.len() {
+            if self.voices[index].key.is_some() {
+                self.note_off(index);
+            }
+        }
+    }
+}
+
+// Graph for the keyboard synth source
+pub struct KeyboardDsp {
+    pub voices: Vec<VoiceParams>,
+    pub adsr: AdsrParams,
+    pub oscillator: Oscillator,
+    pub amplitude: Shared,
+}
+
+impl DspGraph for KeyboardDsp {
+    fn id(&self) -> Uuid {
+        crate::sources::KEYBOARD
+    }
+
+    fn generate_graph(&self) -> Box<dyn AudioUnit> {
+        let voice = |i: u64| {
+            let params = &self.voices[i as usize];
+            let envelope = (var(&params.gate) | var(&params.note)) >> Adsr::new(self.adsr.clone());
+            self.oscillator.bank(&params.pitch) * envelope
+        };
+        // Headroom so a full chord doesn't clip
+        let voices = bus::<MaxVoices, _, _>(voice) * 0.3;
+        let audio = voices * var(&self.amplitude);
+        Box::new(audio >> split::<U2>())
+    }
+}
+
+// Side panel section for the keyboard synth
+pub fn keyboard_panel(ui: &mut egui::Ui, synth: &mut KeyboardSynth) {
+    ui.label("Play with A-; (white keys on the home row, black keys above), Z/X shift the octave");
+    ui.label(format!("Octave: {:+}  Voices: {} / {}", synth.octave, synth.active_voices(), synth.voice_limit));
+    ui.add(egui::Slider::new(&mut synth.voice_limit, 1..=MAX_VOICES).text("Voice Limit"));
+
+    for (shared, label, max) in [
+        (&synth.adsr.attack, "Attack (s)", 2.0),
+        (&synth.adsr.decay, "Decay (s)", 2.0),
+        (&synth.adsr.sustain, "Sustain", 1.0),
+        (&synth.adsr.release, "Release (s)", 4.0),
+    ] {
+        let mut value = shared.value();
+        if ui.add(egui::Slider::new(&mut value, 0.0..=max).text(label)).changed() {
+            shared.set_value(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Envelope times are powers of two so every ramp lands exactly on a sample
+    const RATE: f64 = 1024.0;
+    const EIGHT_SAMPLES: f32 = 1.0 / 128.0;
+    const SIXTEEN_SAMPLES: f32 = 1.0 / 64.0;
+
+    fn adsr(attack: f32, decay: f32, sustain: f32, release: f32) -> Box<dyn AudioUnit> {
+        let params = AdsrParams {
+            attack: shared(attack),
+            decay: shared(decay),
+            sustain: shared(sustain),
+            release: shared(release),
+        };
+        let mut adsr: Box<dyn AudioUnit> = Box::new(Adsr::new(params));
+        adsr.set_sample_rate(RATE);
+        adsr
+    }
+
+    // Envelope levels over `count` samples with the gate and note held
+    fn run(adsr: &mut dyn AudioUnit, gate: f32, note: f32, count: usize) -> Vec<f32> {
+        (0..count)
+            .map(|_| {
+                let mut level = [0.0];
+                adsr.tick(&[gate, note], &mut level);
+                level[0]
+            })
+            .collect()
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-3, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn adsr_stages() {
+        let mut adsr = adsr(EIGHT_SAMPLES, EIGHT_SAMPLES, 0.5, SIXTEEN_SAMPLES);
+        assert_eq!(run(&mut *adsr, 0.0, 0.0, 5), [0.0; 5]);
+
+        let held = run(&mut *adsr, 1.0, 1.0, 100);
+        assert_near(held[3], 0.5);
+        assert_near(held[7], 1.0);
+        assert_near(held[11], 0.75);
+        assert_near(held[15], 0.5);
+        assert!(held[..8].windows(2).all(|w| w[1] > w[0]));
+        assert!(held[16..].iter().all(|&level| level == 0.5));
+
+        let released = run(&mut *adsr, 0.0, 1.0, 30);
+        assert_near(released[7], 0.25);
+        assert_near(released[15], 0.0);
+        assert!(released[16..].iter().all(|&level| level == 0.0));
+    }
+
+    #[test]
+    fn adsr_release_during_attack() {
+        let mut adsr = adsr(EIGHT_SAMPLES, EIGHT_SAMPLES, 0.5, SIXTEEN_SAMPLES);
+        let attack = run(&mut *adsr, 1.0, 1.0, 3);
+        assert_near(attack[2], 0.375);
+        // Falls from where it got to, over the release time
+        let released = run(&mut *adsr, 0.0, 1.0, 16);
+        assert_near(released[7], 0.1875);
+        assert_near(released[15], 0.0);
+    }
+
+    #[test]
+    fn adsr_retriggers_on_a_new_note() {
+        let mut adsr = adsr(EIGHT_SAMPLES, EIGHT_SAMPLES, 0.5, SIXTEEN_SAMPLES);
+        run(&mut *adsr, 1.0, 1.0, 50);
+        // A stolen voice: the gate stays high but the note changes, so the
+        // attack starts again from the sustain level
+        let retriggered = run(&mut *adsr, 1.0, 2.0, 4);
+        assert_near(retriggered[0], 0.625);
+        assert_near(retriggered[3], 1.0);
+    }
+
+    fn press(synth: &mut KeyboardSynth, keys: &[KeyCode]) {
+        let mut input = ButtonInput::default();
+        for &key in keys {
+            input.press(key);
+        }
+        synth.handle_keys(&input);
+    }
+
+    fn release(synth: &mut KeyboardSynth, keys: &[KeyCode]) {
+        let mut input = ButtonInput::default();
+        for &key in keys {
+            input.press(key);
+        }
+        input.clear();
+        for &key in keys {
+            input.release(key);
+        }
+        synth.handle_keys(&input);
+    }
+
+    // Key held by each voice
+    fn held(synth: &KeyboardSynth) -> Vec<Option<KeyCode>> {
+        synth.voices.iter().map(|v| v.key).collect()
+    }
+
+    #[test]
+    fn keys_take_free_voices() {
+        let mut synth = KeyboardSynth::default();
+        press(&mut synth, &[KeyCode::KeyA]);
+        press(&mut synth, &[KeyCode::KeyK]);
+        assert_eq!(synth.active_voices(), 2);
+        let params = synth.voice_params();
+        // Middle C and the C above
+        assert_near(params[0].pitch.value(), 261.6256);
+        assert_near(params[1].pitch.value(), 523.2511);
+        assert_eq!((params[0].gate.value(), params[1].gate.value()), (1.0, 1.0));
+        assert_ne!(params[0].note.value(), params[1].note.value());
+
+        release(&mut synth, &[KeyCode::KeyA]);
+        assert_eq!(synth.active_voices(), 1);
+        assert_eq!(params[0].gate.value(), 0.0);
+    }
+
+    #[test]
+    fn oldest_voice_is_stolen_at_the_limit() {
+        let mut synth = KeyboardSynth { voice_limit: 3, ..default() };
+        for key in [KeyCode::KeyA, KeyCode::KeyS, KeyCode::KeyD] {
+            press(&mut synth, &[key]);
+        }
+        press(&mut synth, &[KeyCode::KeyF]);
+        assert_eq!(synth.active_voices(), 3);
+        assert_eq!(&held(&synth)[..3], [Some(KeyCode::KeyF), Some(KeyCode::KeyS), Some(KeyCode::KeyD)]);
+        // Releasing the stolen key leaves the voice that took it alone
+        release(&mut synth, &[KeyCode::KeyA]);
+        assert_eq!(synth.active_voices(), 3);
+    }
+
+    #[test]
+    fn longest_released_voice_is_reused() {
+        let mut synth = KeyboardSynth { voice_limit: 3, ..default() };
+        press(&mut synth, &[KeyCode::KeyA, KeyCode::KeyS, KeyCode::KeyD]);
+        release(&mut synth, &[KeyCode::KeyS]);
+        release(&mut synth, &[KeyCode::KeyA]);
+        // Voice 1 has been releasing longest, so its tail is the quietest
+        press(&mut synth, &[KeyCode::KeyF]);
+        assert_eq!(&held(&synth)[..3], [None, Some(KeyCode::KeyF), Some(KeyCode::KeyD)]);
+    }
+
+    #[test]
+    fn lowering_the_limit_releases_voices() {
+        let mut synth = KeyboardSynth::default();
+        press(&mut synth, &[KeyCode::KeyA, KeyCode::KeyS, KeyCode::KeyD, KeyCode::KeyF]);
+        assert_eq!(synth.active_voices(), 4);
+        synth.voice_limit = 2;
+        press(&mut synth, &[]);
+        assert_eq!(synth.active_voices(), 2);
+        synth.all_notes_off();
+        assert_eq!(synth.active_voices(), 0);
+    }
+
+    #[test]
+    fn octave_shift() {
+        let mut synth = KeyboardSynth::default();
+        press(&mut synth, &[KeyCode::KeyZ]);
+        press(&mut synth, &[KeyCode::KeyA]);
+        assert_eq!(synth.octave, -1);
+        assert_near(synth.voice_params()[0].pitch.value(), 130.8128);
+        // No further than the range allows
+        for _ in 0..10 {
+            press(&mut synth, &[KeyCode::KeyX]);
+        }
+        assert_eq!(synth.octave, 3);
+    }
+}
@@ -0,0 +1,137 @@
+// Startup asset loading
+//
+// The app starts in `AssetLoadingState::Loading`. Systems hand the assets
+// the app can't start without (the visualizer shader, the first track) to
+// `LoadingAssets`, and `check_asset_loading` follows them through the asset
+// server: once all have settled the state moves to Ready, or to Error if a
+// required one failed. Playback is gated on Ready.
+//
+// A track that fails to load is not fatal, the app falls back to the
+// oscillator and shows the error in the side panel.
+
+use bevy::asset::RecursiveDependencyLoadState;
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContexts};
+
+#[derive(States, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum AssetLoadingState {
+    #[default]
+    Loading,
+    Ready,
+    Error,
+}
+
+struct TrackedAsset {
+    name: String,
+    handle: UntypedHandle,
+    required: bool,
+    done: bool,
+}
+
+#[derive(Resource, Default)]
+pub struct LoadingAssets {
+    assets: Vec<TrackedAsset>,
+    errors: Vec<String>,
+}
+
+impl LoadingAssets {
+    // Wait for an asset before leaving the Loading state. Failures of
+    // assets that aren't required are left to whoever asked for them.
+    pub fn track(&mut self, name: &str, handle: UntypedHandle, required: bool) {
+        self.assets.push(TrackedAsset {
+            name: name.to_string(),
+            handle,
+            required,
+            done: false,
+        });
+    }
+
+    // Record a failure found outside the asset server; it shows on the
+    // loading screen with the failed assets
+    pub fn fail(&mut self, error: String) {
+        eprintln!("[ASSET] {}", error);
+        if !self.errors.contains(&error) {
+            self.errors.push(error);
+        }
+    }
+
+    // Settled and total asset counts
+    pub fn progress(&self) -> (usize, usize) {
+        (self.assets.iter().filter(|a| a.done).count(), self.assets.len())
+    }
+}
+
+// System to follow the tracked assets and leave Loading once they have all
+// loaded or failed
+pub fn check_asset_loading(
+    asset_server: Res<AssetServer>,
+    mut loading: ResMut<LoadingAssets>,
+    mut next_state: ResMut<NextState<AssetLoadingState>>,
+) {
+    let mut errors = Vec::new();
+    for asset in loading.assets.iter_mut().filter(|a| !a.done) {
+        match asset_server.get_recursive_dependency_load_state(asset.handle.id()) {
+            Some(RecursiveDependencyLoadState::Loaded) => {
+                println!("[ASSET] Loaded {}", asset.name);
+                asset.done = true;
+            }
+            Some(RecursiveDependencyLoadState::Failed(error)) => {
+                eprintln!("[ASSET] {}", error);
+                asset.done = true;
+                if asset.required {
+                    errors.push(error.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    loading.errors.extend(errors);
+
+    let (done, total) = loading.progress();
+    if done < total {
+        return;
+    }
+    if loading.errors.is_empty() {
+        println!("[ASSET] All assets loaded");
+        next_state.set(AssetLoadingState::Ready);
+    } else {
+        next_state.set(AssetLoadingState::Error);
+    }
+}
+
+// Loading progress, or what failed, over the scene until the app is Ready
+pub fn loading_screen(
+    mut contexts: EguiContexts,
+    state: Res<State<AssetLoadingState>>,
+    loading: Res<LoadingAssets>,
+    mut next_state: ResMut<NextState<AssetLoadingState>>,
+) {
+    let Ok(ctx) = contexts.ctx_mut() else {
+        return;
+    };
+    let (done, total) = loading.progress();
+    egui::Window::new("Loading")
+        .title_bar(false)
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .show(ctx, |ui| match state.get() {
+            AssetLoadingState::Loading => {
+                ui.heading("Loading...");
+                ui.add(egui::ProgressBar::new(done as f32 / total.max(1) as f32).text(format!("{} / {}", done, total)));
+                for asset in loading.assets.iter().filter(|a| !a.done) {
+                    ui.label(&asset.name);
+                }
+            }
+            AssetLoadingState::Error => {
+                ui.heading("Some assets failed to load");
+                for error in &loading.errors {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+                if ui.button("Continue anyway").clicked() {
+                    next_state.set(AssetLoadingState::Ready);
+                }
+            }
+            AssetLoadingState::Ready => {}
+        });
+}
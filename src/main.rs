@@ -7,64 +7,136 @@ use bevy_egui::{egui, EguiContexts, EguiPlugin, EguiPrimaryContextPass};
 use bevy::log::{debug, trace, info};
 
 use bevy::render::render_resource::ShaderType;
-use bevy::shader::ShaderRef;
+use bevy::shader::{Shader, ShaderRef};
 
 use bevy::{
     reflect::TypePath,
     render::render_resource::AsBindGroup,
 };
 
-use rustfft::{FftPlanner, num_complex::Complex};
 
 use bevy_fundsp::prelude::*;
 use uuid::Uuid;
 use bevy::time::Time;
-use std::sync::{Arc, Mutex};
+use bevy::asset::{LoadState, UnapprovedPathMode};
+use bevy::ecs::system::SystemParam;
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::window::FileDragAndDrop;
+use bevy::window::{MonitorSelection, WindowMode, WindowResolution};
+use std::sync::{Arc, Mutex, OnceLock};
 use fundsp::wave::Wave;
 use fundsp::combinator::An;
-use hound::WavReader;
 
-// Define the play_sine function with audio capture
-fn play_sine(frequency: Shared, snoop_backend: An<fundsp::hacker32::SnoopBackend>) -> impl AudioUnit {
-    // Create a sine wave with a variable frequency and add snoop for audio capture
-    let audio = var(&frequency) >> sine();
-    let snooped_audio = audio >> snoop_backend;
-    snooped_audio.clone() | snooped_audio
+mod cli;
+mod decode;
+mod resample;
+mod playlist;
+mod transport;
+mod pcm_queue;
+mod input;
+mod oscillator;
+mod keyboard;
+mod midi;
+mod calibration;
+mod sources;
+mod effects;
+mod mixer;
+mod stream;
+mod track_asset;
+mod loading;
+mod spectrum;
+mod bands;
+#[cfg(not(target_arch = "wasm32"))]
+mod radio;
+#[cfg(not(target_arch = "wasm32"))]
+mod osc;
+#[cfg(not(target_arch = "wasm32"))]
+mod stdin_pcm;
+use playlist::Playlist;
+use transport::Transport;
+use stream::Track;
+use track_asset::{AudioTrack, AudioTrackLoader, LoadedTrack};
+use loading::{AssetLoadingState, LoadingAssets};
+use spectrum::{AnalysisSettings, SpectrumAnalyzer};
+use bands::{BandAnalyzer, BandSettings};
+use input::{LiveInput, LiveInputDsp};
+use oscillator::{Oscillator, Waveform};
+use keyboard::{KeyboardDsp, KeyboardSynth};
+use midi::{MidiDsp, MidiNote, MidiSlot, MidiSong, MidiState};
+use calibration::{SignalSettings, TestSignal, TestSignalDsp};
+use sources::{RegisterSourceExt, SourceKind, SourceRegistry};
+use effects::EffectSlot;
+use mixer::{Mixer, MixerDsp, SnoopReader};
+
+// Define the play_sine function; its mixer channel adds the snoop for audio capture
+fn play_sine(frequency: Shared, amplitude: Shared, oscillator: Oscillator) -> impl AudioUnit {
+    // Run the oscillator bank at a variable frequency
+    let audio = oscillator.graph(&frequency, &amplitude);
+    audio >> split::<U2>()
 }
 
-// Custom DSP graph type
+// Custom DSP graph type; despite the name it plays whichever waveform is selected
 struct SineWaveDsp {
     frequency: Shared,
-    snoop_backend: An<fundsp::hacker32::SnoopBackend>,
+    amplitude: Shared,
+    oscillator: Oscillator,
 }
 
 impl DspGraph for SineWaveDsp {
     fn id(&self) -> Uuid {
-        Uuid::from_u128(0x1234567890abcdef1234567890abcdefu128)
+        sources::OSCILLATOR
     }
 
     fn generate_graph(&self) -> Box<dyn AudioUnit> {
-        Box::new(play_sine(self.frequency.clone(), self.snoop_backend.clone()))
+        Box::new(play_sine(
+            self.frequency.clone(),
+            self.amplitude.clone(),
+            self.oscillator.clone(),
+        ))
     }
 }
 
+// The track the wave file source plays. Swapped by the playlist; graphs
+// pick up the new track the next time the player is respawned.
+type WaveSlot = Arc<Mutex<Track>>;
+
 // Wave file DSP graph
 struct WaveFileDsp {
-    wave_data: Arc<Wave>,
-    snoop_backend: An<fundsp::hacker32::SnoopBackend>,
+    wave_data: WaveSlot,
+    transport: Transport,
 }
 
 impl DspGraph for WaveFileDsp {
     fn id(&self) -> Uuid {
-        Uuid::from_u128(0xfedcba0987654321fedcba0987654321u128)
+        sources::WAVE_FILE
+    }
+
+    fn generate_graph(&self) -> Box<dyn AudioUnit> {
+        let track = self.wave_data.lock().unwrap().clone();
+        // Tracks play once from the transport's playhead; looping is handled by the playlist's repeat mode.
+        // Multichannel files are downmixed to stereo as they are loaded or streamed.
+        track.player(self.transport.state.clone())
+    }
+}
+
+// A file dropped onto the window, played as a source of its own from its
+// own playhead
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+struct DroppedFileDsp {
+    id: Uuid,
+    track: Track,
+    transport: Transport,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DspGraph for DroppedFileDsp {
+    fn id(&self) -> Uuid {
+        self.id
     }
 
     fn generate_graph(&self) -> Box<dyn AudioUnit> {
-        let wave_data = self.wave_data.clone();
-        // Play back the first channel (channel 0) of the wave file with snoop for audio capture
-        let audio = wavech(&wave_data, 0, Some(0));
-        let snooped_audio = audio >> self.snoop_backend.clone();
-        Box::new(snooped_audio.clone() | snooped_audio)
+        self.track.player(self.transport.state.clone())
     }
 }
 
@@ -82,109 +154,571 @@ impl Default for AudioFrequency {
     }
 }
 
-// Resource to store the snoop receiver for audio capture
+// Resource to store the oscillator's output level
+#[derive(Resource)]
+struct AudioAmplitude {
+    value: Shared,
+}
+
+impl Default for AudioAmplitude {
+    fn default() -> Self {
+        Self {
+            value: shared(1.0),
+        }
+    }
+}
+
+// Resource giving systems access to the wave file source's track slot
+#[derive(Resource)]
+struct CurrentWave {
+    slot: WaveSlot,
+}
+
+// Resource to store the master bus snoop receivers for audio capture
 #[derive(Resource)]
 struct AudioSnoop {
-    receiver: Arc<Mutex<fundsp::hacker::Snoop>>,
+    post: SnoopReader,
+    pre: SnoopReader,
+}
+
+// Resource giving the UI the effects chain the sources are built with
+#[derive(Resource)]
+struct Effects {
+    slot: EffectSlot,
+}
+
+// System to update the audio frequency from the UI
+fn update_audio_frequency(
+    ui_state: Res<UiState>,
+    frequency: Res<AudioFrequency>,
+) {
+    // Update the shared frequency value with the UI slider value
+    frequency.value.set_value(ui_state.value);
 }
 
-// Function to play the audio
-fn play_audio(
+// System to push waveform, pulse width and amplitude to the oscillator
+fn update_oscillator(
+    ui_state: Res<UiState>,
+    amplitude: Res<AudioAmplitude>,
+    oscillator: Res<Oscillator>,
+) {
+    amplitude.value.set_value(ui_state.amplitude);
+    oscillator.pulse_width.set_value(ui_state.pulse_width);
+    oscillator.select(ui_state.waveform);
+}
+
+// System to spawn the mixer, and respawn it when a new track needs
+// starting or its channels or effects changed
+fn update_audio_source(
     mut commands: Commands,
     mut assets: ResMut<Assets<DspSource>>,
     dsp_manager: Res<DspManager>,
-    ui_state: Res<UiState>,
     mut current_audio_player: ResMut<CurrentAudioPlayer>,
-    asset_loading_state: Res<AssetLoadingState>,
+    transport: Res<Transport>,
+    mut loading: ResMut<LoadingAssets>,
+    mut next_state: ResMut<NextState<AssetLoadingState>>,
 ) {
-    // Check if all assets are loaded
-    if !asset_loading_state.assets_loaded {
-        println!("[AUDIO] Waiting for assets to load...");
+    let starting = current_audio_player.entity.is_none() || current_audio_player.restart;
+    if !starting && !current_audio_player.rebuild {
         return;
     }
-    
-    println!("[AUDIO] Playing audio with source: {}", if ui_state.use_wave_file { "Wave File" } else { "Sine Wave" });
-    
-    let source = if ui_state.use_wave_file {
-        // Use wave file DSP
-        println!("[AUDIO] Creating wave file audio source");
-        assets.add(
-            dsp_manager
-                .get_graph_by_id(&Uuid::from_u128(0xfedcba0987654321fedcba0987654321u128))
-                .unwrap_or_else(|| panic!("Wave file DSP source not found!")),
-        )
+    if starting {
+        println!("[AUDIO] Starting mixer");
     } else {
-        // Use sine wave DSP
-        println!("[AUDIO] Creating sine wave audio source");
-        assets.add(
-            dsp_manager
-                .get_graph_by_id(&Uuid::from_u128(0x1234567890abcdef1234567890abcdefu128))
-                .unwrap_or_else(|| panic!("Sine wave DSP source not found!")),
-        )
+        println!("[AUDIO] Rebuilding mixer graph");
+    }
+    
+    // Without the mixer there is nothing to play; say so instead of crashing
+    let Some(graph) = dsp_manager.get_graph_by_id(&mixer::MIXER) else {
+        loading.fail(String::from("Mixer DSP source not found, it was never added to the DSP manager"));
+        next_state.set(AssetLoadingState::Error);
+        return;
     };
     
-    let entity = commands.spawn(AudioPlayer {
+    // Despawn the current audio player
+    if let Some(entity) = current_audio_player.entity {
+        commands.entity(entity).despawn();
+    }
+    
+    let source = assets.add(graph);
+    
+    // A freshly started track plays from the top; a rebuilt graph carries on
+    if starting {
+        transport.rewind();
+    }
+    
+    // Spawn a new audio player for the whole mix
+    let new_entity = commands.spawn(AudioPlayer {
         0: source
     }).id();
     
-    current_audio_player.entity = Some(entity);
-    current_audio_player.current_source = ui_state.use_wave_file;
+    // Update the current audio player resource
+    current_audio_player.entity = Some(new_entity);
+    current_audio_player.restart = false;
+    current_audio_player.rebuild = false;
+    if starting {
+        current_audio_player.track_ended = false;
+    }
 }
 
-// System to update the audio frequency from the UI
-fn update_audio_frequency(
-    ui_state: Res<UiState>,
-    frequency: Res<AudioFrequency>,
+// System to put the source picked on the command line in the mix
+fn add_first_channel(
+    mut ui_state: ResMut<UiState>,
+    registry: Res<SourceRegistry>,
+    mut mixer: ResMut<Mixer>,
 ) {
-    // Update the shared frequency value with the UI slider value
-    frequency.value.set_value(ui_state.value);
+    let Some(info) = registry.get(ui_state.source) else {
+        return;
+    };
+    if info.kind.plays_track() {
+        mixer.set_track_source(info);
+    } else {
+        mixer.add(info);
+    }
+    // Live input starts unheard, so show its own tap rather than the silent master
+    if info.kind == SourceKind::LiveInput {
+        ui_state.analysis_tap = Some(info.id);
+    }
 }
 
-// System to handle audio source switching
-fn update_audio_source(
-    ui_state: Res<UiState>,
-    mut commands: Commands,
-    mut assets: ResMut<Assets<DspSource>>,
-    dsp_manager: Res<DspManager>,
+// The track being loaded for the playlist, and the one in use, kept so it
+// reloads when its file changes
+#[derive(Resource, Default)]
+struct TrackAssets {
+    current: Option<Handle<AudioTrack>>,
+    pending: Option<PendingTrack>,
+}
+
+struct PendingTrack {
+    handle: Handle<AudioTrack>,
+    index: usize,
+    path: String,
+    // The track the app starts with, which doesn't take the focus from
+    // another source chosen on the command line
+    first: bool,
+}
+
+// System to start loading the track the playlist asked for
+fn load_requested_track(
+    mut playlist: ResMut<Playlist>,
+    asset_server: Res<AssetServer>,
+    mut track_assets: ResMut<TrackAssets>,
+    mut loading: ResMut<LoadingAssets>,
+    mut started: Local<bool>,
+) {
+    let Some(index) = playlist.take_request() else {
+        return;
+    };
+    let track = playlist.tracks()[index].clone();
+    println!("[PLAYLIST] Loading track: {}", track.name);
+    
+    let handle: Handle<AudioTrack> = asset_server.load(track_asset::track_asset_path(&track.path));
+    // The app waits for its first track, but can start without it
+    if !*started {
+        loading.track(&track.name, handle.clone().untyped(), false);
+    }
+    // A newer request replaces one still loading
+    track_assets.pending = Some(PendingTrack {
+        handle,
+        index,
+        path: track.path,
+        first: !*started,
+    });
+    *started = true;
+}
+
+// Put a loaded track in its source's slot, returning the source
+fn set_track(track: &LoadedTrack, current_wave: &CurrentWave, midi_state: &MidiState, ui_state: &mut UiState) -> Uuid {
+    match track {
+        LoadedTrack::Wave(track) => {
+            *current_wave.slot.lock().unwrap() = track.clone();
+            ui_state.loaded_wav = true;
+            sources::WAVE_FILE
+        }
+        LoadedTrack::Midi(song) => {
+            *midi_state.song.lock().unwrap() = song.clone();
+            ui_state.loaded_midi = true;
+            sources::MIDI
+        }
+    }
+}
+
+// System to switch the wave file or MIDI source to a track once it has
+// loaded, and again when its file changes on disk
+fn use_loaded_track(
+    mut playlist: ResMut<Playlist>,
+    mut track_assets: ResMut<TrackAssets>,
+    mut asset_events: MessageReader<AssetEvent<AudioTrack>>,
+    asset_server: Res<AssetServer>,
+    audio_tracks: Res<Assets<AudioTrack>>,
+    current_wave: Res<CurrentWave>,
+    midi_state: Res<MidiState>,
+    mut ui_state: ResMut<UiState>,
+    registry: Res<SourceRegistry>,
+    mut mixer: ResMut<Mixer>,
     mut current_audio_player: ResMut<CurrentAudioPlayer>,
-    _audio_players: Query<Entity, With<AudioPlayer>>,
+    mut pause: ResMut<Pause>,
 ) {
-    // Check if the audio source has changed
-    if current_audio_player.current_source != ui_state.use_wave_file {
-        println!("[AUDIO] Switching audio source to: {}", if ui_state.use_wave_file { "Wave File" } else { "Sine Wave" });
-        
-        // Despawn the current audio player
-        if let Some(entity) = current_audio_player.entity {
-            commands.entity(entity).despawn();
+    let mut reloaded = false;
+    for event in asset_events.read() {
+        if let Some(current) = &track_assets.current {
+            reloaded |= event.is_modified(current);
         }
-        
-        // Create a new audio source based on the selection
-        let source = if ui_state.use_wave_file {
-            // Use wave file DSP
-            println!("[AUDIO] Creating wave file audio source");
-            assets.add(
-                dsp_manager
-                    .get_graph_by_id(&Uuid::from_u128(0xfedcba0987654321fedcba0987654321u128))
-                    .unwrap_or_else(|| panic!("Wave file DSP source not found!")),
-            )
-        } else {
-            // Use sine wave DSP
-            println!("[AUDIO] Creating sine wave audio source");
-            assets.add(
-                dsp_manager
-                    .get_graph_by_id(&Uuid::from_u128(0x1234567890abcdef1234567890abcdefu128))
-                    .unwrap_or_else(|| panic!("Sine wave DSP source not found!")),
-            )
+    }
+    // Swap the new contents in without moving the playhead
+    if let Some(asset) = track_assets.current.as_ref().filter(|_| reloaded).and_then(|h| audio_tracks.get(h)) {
+        println!("[ASSET] Track changed on disk, reloading");
+        let focused_track = mixer.track_source() == Some(ui_state.source);
+        let source = set_track(&asset.track, &current_wave, &midi_state, &mut ui_state);
+        if mixer.track_source().is_some() {
+            if let Some(info) = registry.get(source) {
+                mixer.set_track_source(info);
+            }
+            if focused_track {
+                ui_state.source = source;
+            }
+        }
+        current_audio_player.rebuild = true;
+    }
+    
+    let Some(pending) = track_assets.pending.take() else {
+        return;
+    };
+    let result = match asset_server.get_load_state(&pending.handle) {
+        Some(LoadState::Loaded) => match audio_tracks.get(&pending.handle) {
+            Some(asset) => Ok(asset.track.clone()),
+            None => {
+                track_assets.pending = Some(pending);
+                return;
+            }
+        },
+        Some(LoadState::Failed(error)) => Err(error.to_string()),
+        _ => {
+            track_assets.pending = Some(pending);
+            return;
+        }
+    };
+    
+    // The entry may have moved in the playlist while it loaded
+    let index = if playlist.tracks().get(pending.index).is_some_and(|t| t.path == pending.path) {
+        Some(pending.index)
+    } else {
+        playlist.tracks().iter().position(|t| t.path == pending.path)
+    };
+    
+    match result {
+        Ok(track) => {
+            let focused_track = mixer.track_source() == Some(ui_state.source);
+            let takes_over = !pending.first || mixer.track_source().is_some();
+            let source = set_track(&track, &current_wave, &midi_state, &mut ui_state);
+            if let Some(index) = index {
+                playlist.set_current(index);
+            }
+            ui_state.load_error = None;
+            track_assets.current = Some(pending.handle);
+            if takes_over {
+                if let Some(info) = registry.get(source) {
+                    mixer.set_track_source(info);
+                }
+            }
+            if !pending.first || focused_track {
+                ui_state.source = source;
+            }
+            // update_audio_source respawns the player with the new track
+            current_audio_player.restart = true;
+            // Picking a track means playing it
+            if pause.0 {
+                pause.0 = false;
+            }
+        }
+        Err(e) => {
+            eprintln!("[PLAYLIST] {}", e);
+            ui_state.load_error = Some(e);
+            // Don't leave an unplayable entry in the queue
+            if let Some(index) = index {
+                playlist.remove(index);
+            }
+            // Without a track to start on, start on the oscillator
+            if pending.first && ui_state.source == sources::WAVE_FILE {
+                mixer.remove(sources::WAVE_FILE);
+                if let Some(info) = registry.get(sources::OSCILLATOR) {
+                    mixer.add(info);
+                }
+                ui_state.source = sources::OSCILLATOR;
+                current_audio_player.rebuild = true;
+            }
+        }
+    }
+}
+
+// Dropped files still loading, and the sources made from those that loaded
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Resource, Default)]
+struct DroppedFiles {
+    pending: Vec<(Handle<AudioTrack>, String)>,
+    sources: Vec<(String, DroppedFileDsp)>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DroppedFiles {
+    fn get(&self, id: Uuid) -> Option<&(String, DroppedFileDsp)> {
+        self.sources.iter().find(|(_, dsp)| dsp.id == id)
+    }
+}
+
+// System to start loading audio files dropped onto the window
+#[cfg(not(target_arch = "wasm32"))]
+fn handle_file_drop(
+    mut drops: MessageReader<FileDragAndDrop>,
+    asset_server: Res<AssetServer>,
+    mut dropped_files: ResMut<DroppedFiles>,
+    mut ui_state: ResMut<UiState>,
+) {
+    for drop in drops.read() {
+        match drop {
+            FileDragAndDrop::HoveredFile { .. } => ui_state.file_hovered = true,
+            FileDragAndDrop::HoveredFileCanceled { .. } => ui_state.file_hovered = false,
+            FileDragAndDrop::DroppedFile { path_buf, .. } => {
+                ui_state.file_hovered = false;
+                let path = path_buf.to_string_lossy().into_owned();
+                println!("[DROP] {}", path);
+                let handle = asset_server.load(track_asset::track_asset_path(&path));
+                dropped_files.pending.push((handle, path));
+            }
+        }
+    }
+}
+
+// System to register each dropped file that has loaded as a new source and
+// switch to it in the mix
+#[cfg(not(target_arch = "wasm32"))]
+fn register_dropped_files(
+    mut dropped_files: ResMut<DroppedFiles>,
+    asset_server: Res<AssetServer>,
+    audio_tracks: Res<Assets<AudioTrack>>,
+    mut registry: ResMut<SourceRegistry>,
+    mut mixer: ResMut<Mixer>,
+    mut ui_state: ResMut<UiState>,
+    mut current_audio_player: ResMut<CurrentAudioPlayer>,
+    pause: Res<Pause>,
+) {
+    let pending = std::mem::take(&mut dropped_files.pending);
+    for (handle, path) in pending {
+        let track = match asset_server.get_load_state(&handle) {
+            Some(LoadState::Loaded) => match audio_tracks.get(&handle) {
+                Some(asset) => asset.track.clone(),
+                None => {
+                    dropped_files.pending.push((handle, path));
+                    continue;
+                }
+            },
+            Some(LoadState::Failed(error)) => {
+                eprintln!("[DROP] {}", error);
+                ui_state.load_error = Some(error.to_string());
+                continue;
+            }
+            _ => {
+                dropped_files.pending.push((handle, path));
+                continue;
+            }
         };
-        
-        // Spawn a new audio player with the selected source
-        let new_entity = commands.spawn(AudioPlayer {
-            0: source
-        }).id();
-        
-        // Update the current audio player resource
-        current_audio_player.entity = Some(new_entity);
-        current_audio_player.current_source = ui_state.use_wave_file;
+        let track = match track {
+            LoadedTrack::Wave(track) => track,
+            // MIDI only plays through the MIDI source's synth, which follows the playlist
+            LoadedTrack::Midi(_) => {
+                eprintln!("[DROP] Not playing MIDI file {}", path);
+                ui_state.load_error = Some(format!("{}: MIDI files can't be dropped, put them in the playlist instead", path));
+                continue;
+            }
+        };
+        ui_state.load_error = None;
+        let name = playlist::Track::new(&path).name;
+        let dsp = DroppedFileDsp {
+            id: sources::dropped_file(dropped_files.sources.len()),
+            track,
+            transport: Transport::default(),
+        };
+        dsp.transport.set_playing(!pause.0);
+        registry.register_source(dsp.clone(), &name, SourceKind::DroppedFile, &path);
+        if let Some(info) = registry.get(dsp.id) {
+            mixer.add(info);
+        }
+        ui_state.source = dsp.id;
+        current_audio_player.rebuild = true;
+        dropped_files.sources.push((name, dsp));
+    }
+}
+
+// System to move on to the next track when the current one has played out
+fn advance_playlist(
+    mut playlist: ResMut<Playlist>,
+    current_wave: Res<CurrentWave>,
+    midi_state: Res<MidiState>,
+    mixer: Res<Mixer>,
+    mut current_audio_player: ResMut<CurrentAudioPlayer>,
+    transport: Res<Transport>,
+    engine: Res<EngineSampleRate>,
+) {
+    if current_audio_player.entity.is_none() || current_audio_player.track_ended {
+        return;
+    }
+    
+    let track_len = match mixer.track_source() {
+        Some(sources::WAVE_FILE) => current_wave.slot.lock().unwrap().len(),
+        Some(sources::MIDI) => midi_state.length_frames(engine.0 as f64),
+        _ => return,
+    };
+    
+    // Going by the playhead means a paused track never "ends"
+    if transport.position_frames() >= track_len {
+        current_audio_player.track_ended = true;
+        if !playlist.track_finished() {
+            println!("[PLAYLIST] End of playlist");
+        }
+    }
+}
+
+// System to start and stop capture as the live input joins and leaves the mix
+fn update_live_input(
+    mixer: Res<Mixer>,
+    mut live_input: ResMut<LiveInput>,
+    engine: Res<EngineSampleRate>,
+    time: Res<Time<Real>>,
+) {
+    let active = mixer.contains(sources::LIVE_INPUT);
+    live_input.sync(active, engine.0, time.elapsed_secs_f64());
+}
+
+// System to connect and disconnect the network stream as it joins and leaves the mix
+#[cfg(not(target_arch = "wasm32"))]
+fn update_network_stream(
+    mixer: Res<Mixer>,
+    mut network_stream: ResMut<radio::NetworkStream>,
+    engine: Res<EngineSampleRate>,
+) {
+    let active = mixer.contains(sources::NETWORK_STREAM);
+    network_stream.sync(active, engine.0);
+}
+
+// System to poll the OSC server with a copy of the state the side panel
+// edits, then write back whatever the messages changed
+#[cfg(not(target_arch = "wasm32"))]
+fn handle_osc(
+    mut server: ResMut<osc::OscServer>,
+    mut ui_state: ResMut<UiState>,
+    mut mixer: ResMut<Mixer>,
+    registry: Res<SourceRegistry>,
+    mut playlist: ResMut<Playlist>,
+    mut pause: ResMut<Pause>,
+    transport: Res<Transport>,
+    mut current_audio_player: ResMut<CurrentAudioPlayer>,
+    engine: Res<EngineSampleRate>,
+    time: Res<Time<Real>>,
+) {
+    use osc::{OscSource, OscState, TransportRequest};
+    
+    let before = OscState {
+        sources: registry
+            .iter()
+            .map(|info| OscSource {
+                id: info.id,
+                name: info.name.clone(),
+                available: match info.kind {
+                    SourceKind::WaveFile => ui_state.loaded_wav,
+                    SourceKind::Midi => ui_state.loaded_midi,
+                    _ => true,
+                },
+                in_mix: mixer.contains(info.id),
+            })
+            .collect(),
+        source: ui_state.source,
+        frequency: ui_state.value,
+        amplitude: ui_state.amplitude,
+        waveform: ui_state.waveform,
+        pulse_width: ui_state.pulse_width,
+        raw_analysis: ui_state.use_raw_audio,
+        pre_effects: ui_state.analyze_pre_effects,
+        master_gain: mixer.master.value(),
+        paused: pause.0,
+        track: playlist.current_track().map_or(String::new(), |t| t.name.clone()),
+        requests: Vec::new(),
+    };
+    let mut state = before.clone();
+    server.poll(&mut state, time.elapsed_secs_f64());
+    // Only write on a change so change detection stays meaningful
+    if state == before {
+        return;
+    }
+    
+    for (source, old) in state.sources.iter().zip(&before.sources) {
+        if source.in_mix == old.in_mix {
+            continue;
+        }
+        let Some(info) = registry.get(source.id) else { continue };
+        if !source.in_mix {
+            mixer.remove(info.id);
+            if ui_state.analysis_tap == Some(info.id) {
+                ui_state.analysis_tap = None;
+            }
+        } else if info.kind.plays_track() {
+            mixer.set_track_source(info);
+        } else {
+            mixer.add(info);
+        }
+        current_audio_player.rebuild = true;
+    }
+    ui_state.source = state.source;
+    ui_state.value = state.frequency;
+    ui_state.amplitude = state.amplitude;
+    ui_state.waveform = state.waveform;
+    ui_state.pulse_width = state.pulse_width;
+    ui_state.use_raw_audio = state.raw_analysis;
+    ui_state.analyze_pre_effects = state.pre_effects;
+    mixer.master.set_value(state.master_gain);
+    if pause.0 != state.paused {
+        pause.0 = state.paused;
+    }
+    for request in state.requests {
+        match request {
+            TransportRequest::Rewind => transport.rewind(),
+            TransportRequest::Seek(seconds) => transport.seek(seconds as f64, engine.0 as f64),
+            TransportRequest::NextTrack => playlist.next(),
+            TransportRequest::PreviousTrack => playlist.previous(),
+        }
+    }
+}
+
+// System to play the keyboard synth from key presses
+fn play_keyboard(
+    keys: Res<ButtonInput<KeyCode>>,
+    mixer: Res<Mixer>,
+    mut synth: ResMut<KeyboardSynth>,
+    mut contexts: EguiContexts,
+) {
+    // Typing into a text box shouldn't play notes
+    let typing = contexts.ctx_mut().is_ok_and(|ctx| ctx.wants_keyboard_input());
+    if !mixer.contains(sources::KEYBOARD) || typing {
+        synth.all_notes_off();
+        return;
+    }
+    synth.handle_keys(&keys);
+}
+
+// System to send note messages as the MIDI playhead passes them
+fn send_midi_notes(
+    mixer: Res<Mixer>,
+    transport: Res<Transport>,
+    engine: Res<EngineSampleRate>,
+    mut midi_state: ResMut<MidiState>,
+    mut notes: MessageWriter<MidiNote>,
+) {
+    if !mixer.contains(sources::MIDI) {
+        return;
+    }
+    let time = transport.position_seconds(engine.0 as f64);
+    for event in midi_state.advance(time) {
+        trace!("MIDI note: {:?}", event);
+        notes.write(MidiNote(event));
     }
 }
 
@@ -194,9 +728,20 @@ fn update_audio_source(
 struct UiState {
     pub label: String,
     pub value: f32,
+    pub amplitude: f32,
+    pub waveform: Waveform,
+    pub pulse_width: f32,
     pub loaded_wav: bool,
-    pub use_wave_file: bool,
+    pub loaded_midi: bool,
+    pub source: Uuid, // mixer channel whose controls are shown, by SourceRegistry id
+    pub analysis_tap: Option<Uuid>, // mixer channel to analyze, the master bus if None
     pub use_raw_audio: bool, // New option to use raw audio data instead of FFT
+    pub analyze_pre_effects: bool, // analyze the source as it enters the effects chain
+    pub analysis: AnalysisSettings, // window, capture, FFT size and hop
+    pub bands: BandSettings, // band spectrum sent to the shaders
+    pub load_error: Option<String>,
+    pub new_track_path: String,
+    pub file_hovered: bool,
 }
 
 impl Default for UiState {
@@ -204,9 +749,20 @@ impl Default for UiState {
         Self {
             label: String::from("Hello World!"),
             value: 440.0,
+            amplitude: 1.0,
+            waveform: Waveform::Sine,
+            pulse_width: 0.5,
             loaded_wav: false,
-            use_wave_file: true, // Default to wave file
+            loaded_midi: false,
+            source: sources::WAVE_FILE, // Default to wave file
+            analysis_tap: None,
             use_raw_audio: true, // Default to raw audio processing
+            analyze_pre_effects: false,
+            analysis: AnalysisSettings::default(),
+            bands: BandSettings::default(),
+            load_error: None,
+            new_track_path: String::new(),
+            file_hovered: false,
         }
     }
 }
@@ -214,18 +770,40 @@ impl Default for UiState {
 #[derive(Resource, Default)]
 struct Pause(bool);
 
+// System to push the pause flag to the wave file's playhead, and those of
+// dropped files
+fn apply_pause(
+    pause: Res<Pause>,
+    transport: Res<Transport>,
+    #[cfg(not(target_arch = "wasm32"))]
+    dropped_files: Res<DroppedFiles>,
+) {
+    if pause.is_changed() {
+        transport.set_playing(!pause.0);
+        #[cfg(not(target_arch = "wasm32"))]
+        for (_, dsp) in &dropped_files.sources {
+            dsp.transport.set_playing(!pause.0);
+        }
+    }
+}
+
+// Settings shared by all test signal sources
+#[derive(Resource)]
+struct TestSignalSettings(Arc<Mutex<SignalSettings>>);
+
+// The rate DspPlugin was created with (--sample-rate), for anything that maps
+// samples to time or Hz; loaded files are resampled to it
+#[derive(Resource, Clone, Copy)]
+struct EngineSampleRate(f32);
+
 #[derive(Resource, Default)]
 struct CurrentAudioPlayer {
     entity: Option<Entity>,
-    current_source: bool, // true = wave file, false = sine wave
+    restart: bool,        // respawn from the top of a new track
+    rebuild: bool,        // respawn with new channels or effects, keeping the playhead
+    track_ended: bool,
 }
 
-#[derive(Resource, Default)]
-struct AssetLoadingState {
-    wasm_loaded: bool,
-    wav_loaded: bool,
-    assets_loaded: bool,
-}
 
 
 
@@ -234,28 +812,115 @@ fn main() {
     #[cfg(target_arch = "wasm32")]
     std::panic::set_hook(Box::new(console_error_panic_hook::hook));
 
-    let frequency = shared(440.0);
+    // Exits with usage on --help or invalid arguments
+    let cli = cli::parse_args();
+    let _ = FRAGMENT_SHADER.set(cli.shader.path());
+    
+    let frequency = shared(cli.frequency);
     let frequency_clone = frequency.clone();
+    let amplitude = shared(1.0);
+    let oscillator = Oscillator::new(Waveform::Sine, 0.5);
     
-    // Create snoop nodes for audio capture
-    let (snoop_frontend, snoop_backend) = fundsp::hacker32::snoop(1024);
-    let snoop_receiver = Arc::new(Mutex::new(snoop_frontend));
+    // Create snoop nodes for audio capture on the master bus, each side of the effects
+    let (post_reader, post_snoop) = mixer::stereo_snoop(spectrum::SNOOP_CAPACITY);
+    let (pre_reader, pre_snoop) = mixer::stereo_snoop(spectrum::SNOOP_CAPACITY);
+    let effect_slot: EffectSlot = Arc::new(Mutex::new(Vec::new()));
+    let mixer = Mixer::default();
+    let mixer_dsp = MixerDsp {
+        channels: mixer.slot(),
+        master: mixer.master.clone(),
+        effects: effect_slot.clone(),
+        pre: pre_snoop,
+        post: post_snoop,
+    };
     
-    // Load wave file
-    let wave_data = Arc::new(load_wave_file("assets/test.wav"));
+    let ui_state = UiState {
+        value: cli.frequency,
+        source: match cli.source {
+            // Piped audio is the point of --stdin, so it wins over --source
+            #[cfg(not(target_arch = "wasm32"))]
+            _ if cli.stdin => sources::STDIN,
+            cli::SourceArg::Sine => sources::OSCILLATOR,
+            cli::SourceArg::File => sources::WAVE_FILE,
+            cli::SourceArg::Input => sources::LIVE_INPUT,
+            cli::SourceArg::Keyboard => sources::KEYBOARD,
+            #[cfg(not(target_arch = "wasm32"))]
+            cli::SourceArg::Stream => sources::NETWORK_STREAM,
+            // The web build has no network stream and always gets the defaults
+            #[cfg(target_arch = "wasm32")]
+            cli::SourceArg::Stream => sources::WAVE_FILE,
+        },
+        use_raw_audio: cli.mode == cli::ModeArg::Raw,
+        ..default()
+    };
+    let engine = EngineSampleRate(cli.sample_rate as f32);
+    let mut playlist = Playlist::new(&cli.tracks());
+    // The first track loads in the background like any other
+    playlist.play(0);
+    let wave_data = Track::Memory(Arc::new(silent_wave(engine.0)));
+    let midi_song = Arc::new(MidiSong::empty());
+    let wave_slot: WaveSlot = Arc::new(Mutex::new(wave_data));
+    let midi_slot: MidiSlot = Arc::new(Mutex::new(midi_song));
+    let transport = Transport::default();
     let wave_dsp = WaveFileDsp {
-        wave_data: wave_data.clone(),
-        snoop_backend: snoop_backend.clone(),
+        wave_data: wave_slot.clone(),
+        transport: transport.clone(),
     };
+    
+    // About half a second of capture, playback starts once ~46 ms is queued
+    let live_input = LiveInput::new(pcm_queue::PcmQueue::new(engine.0 as usize / 2, 2048));
+    let live_dsp = LiveInputDsp {
+        queue: live_input.queue.clone(),
+    };
+    let keyboard_synth = KeyboardSynth::default();
+    let keyboard_dsp = KeyboardDsp {
+        voices: keyboard_synth.voice_params(),
+        adsr: keyboard_synth.adsr.clone(),
+        oscillator: oscillator.clone(),
+        amplitude: amplitude.clone(),
+    };
+    // MIDI plays through the keyboard synth's instrument
+    let midi_dsp = MidiDsp {
+        song: midi_slot.clone(),
+        transport: transport.clone(),
+        oscillator: oscillator.clone(),
+        adsr: keyboard_synth.adsr.clone(),
+        amplitude: amplitude.clone(),
+        sample_rate: engine.0 as f64,
+    };
+
+    let test_signal_settings = Arc::new(Mutex::new(SignalSettings::default()));
 
-    App::new()
+    let mut app = App::new();
+    #[cfg(not(target_arch = "wasm32"))]
+    track_asset::add_file_sources(&mut app, &cli.tracks());
+    app
         .init_resource::<Pause>()
-        .init_resource::<UiState>()
+        .insert_resource(ui_state)
+        .insert_resource(engine)
+        .insert_resource(playlist)
+        .insert_resource(CurrentWave { slot: wave_slot })
+        .insert_resource(transport)
+        .insert_resource(live_input)
+        .insert_resource(keyboard_synth)
+        .insert_resource(MidiState::new(midi_slot))
+        .insert_resource(TestSignalSettings(test_signal_settings.clone()))
+        .insert_resource(Effects { slot: effect_slot })
+        .insert_resource(mixer)
+        .add_message::<MidiNote>()
         .init_resource::<SampleBuffer>()
+        .insert_resource(SpectrumAnalyzer::new(AnalysisSettings::default()))
+        .init_resource::<BandAnalyzer>()
         .init_resource::<CurrentAudioPlayer>()
-        .init_resource::<AssetLoadingState>()
+        .init_resource::<TrackAssets>()
+        .init_resource::<LoadingAssets>()
         .insert_resource(AudioFrequency { value: frequency_clone })
-        .insert_resource(AudioSnoop { receiver: snoop_receiver })
+        .insert_resource(AudioAmplitude { value: amplitude.clone() })
+        .insert_resource(oscillator.clone())
+        .insert_resource(AudioSnoop {
+            post: post_reader,
+            pre: pre_reader,
+        })
         .insert_resource(ClearColor(Color::BLACK))
         .insert_resource(ShaderData {
             r: 0.1,
@@ -268,148 +933,146 @@ fn main() {
         })
         .add_plugins((
             DefaultPlugins
-                .build(),
+                .build()
+                .set(WindowPlugin {
+                    primary_window: Some(primary_window(&cli)),
+                    ..default()
+                })
+                .set(AssetPlugin {
+                    // Playlist entries can be absolute or outside the working directory
+                    unapproved_path_mode: UnapprovedPathMode::Allow,
+                    ..default()
+                }),
         ))
+        .init_state::<AssetLoadingState>()
+        .init_asset::<AudioTrack>()
+        .register_asset_loader(AudioTrackLoader { sample_rate: engine.0 })
         .add_plugins(MaterialPlugin::<CustomMaterial>::default())
         .add_plugins(EguiPlugin::default())
-        .add_plugins(DspPlugin::new(44100.0))
-        .add_dsp_source(SineWaveDsp { frequency, snoop_backend: snoop_backend.clone() }, SourceType::Dynamic)
-        .add_dsp_source(wave_dsp, SourceType::Dynamic)
+        .add_plugins(DspPlugin::new(engine.0))
+        .add_dsp_source(mixer_dsp, SourceType::Dynamic)
+        .register_source(
+            SineWaveDsp { frequency, amplitude, oscillator },
+            "Oscillator",
+            SourceKind::Oscillator,
+            "Synth oscillator at the frequency set in the side panel",
+        )
+        .register_source(wave_dsp, "Wave File", SourceKind::WaveFile, "Current audio track from the playlist")
+        .register_source(live_dsp, "Live Input", SourceKind::LiveInput, "Microphone or line-in capture")
+        .register_source(keyboard_dsp, "Keyboard", SourceKind::Keyboard, "Polyphonic synth played from the computer keyboard")
+        .register_source(midi_dsp, "MIDI", SourceKind::Midi, "Current MIDI track played through the synth")
         .add_systems(Startup, setup_scene)
-        .add_systems(Startup, check_asset_loading)
-        .add_systems(PostStartup, play_audio)
+        .add_systems(Startup, add_first_channel)
         .add_systems(Update, update_audio_frequency.after(ui_example_system))
-        .add_systems(Update, update_audio_source.after(ui_example_system))
+        .add_systems(Update, update_oscillator.after(ui_example_system))
+        // Nothing plays until the assets are in
+        .add_systems(Update, update_audio_source.after(ui_example_system).run_if(in_state(AssetLoadingState::Ready)))
+        .add_systems(Update, (advance_playlist, load_requested_track, use_loaded_track).chain().before(update_audio_source))
+        .add_systems(Update, loading::check_asset_loading.after(load_requested_track).run_if(in_state(AssetLoadingState::Loading)))
+        .add_systems(Update, apply_pause)
+        .add_systems(Update, update_live_input.after(ui_example_system).run_if(in_state(AssetLoadingState::Ready)))
+        .add_systems(Update, play_keyboard.after(ui_example_system).run_if(in_state(AssetLoadingState::Ready)))
         .add_systems(Update, quit_on_escape)
         .add_systems(EguiPrimaryContextPass, ui_example_system)
+        .add_systems(EguiPrimaryContextPass, loading::loading_screen.run_if(not(in_state(AssetLoadingState::Ready))))
         .add_systems(Update, read_snooped_audio)
-        .add_systems(Update, prepare_my_material.after(read_snooped_audio))
-        .run();
-}
-
-fn load_wave_file(path: &str) -> Wave {
-    // For WASM, we need to use the embedded asset system
-    #[cfg(target_arch = "wasm32")]
-    {
-        // Suppress unused variable warning for WASM build
-        #[allow(unused_variables)]
-        {
-            path;
-        }
-        
-        // Load the embedded asset as bytes using include_bytes! with a literal path
-        let asset_bytes = include_bytes!("../assets/test.wav");
-        
-        // Parse the WAV data from bytes
-        let cursor = std::io::Cursor::new(asset_bytes);
-        let reader = WavReader::new(cursor).expect("Failed to parse WAV data");
-        let spec = reader.spec();
-        
-        println!("[WAV] File properties: {} channels, {} Hz sample rate",
-                 spec.channels, spec.sample_rate);
-        
-        let samples = reader
-            .into_samples::<i16>()
-            .filter_map(|s| s.ok())
-            .map(|sample| sample as f32 / 32768.0) // Convert i16 to f32 in range [-1, 1]
-            .collect::<Vec<f32>>();
-        
-        println!("[WAV] Loaded {} samples", samples.len());
-        
-        let num_channels = spec.channels as usize;
-        let num_samples = samples.len() / num_channels;
-        
-        let mut wavefile = Wave::with_capacity(
-            num_channels,
-            spec.sample_rate as f64,
-            num_samples,
+        .add_systems(Update, send_midi_notes.run_if(in_state(AssetLoadingState::Ready)))
+        .add_systems(Update, analyze_spectrum.after(read_snooped_audio))
+        .add_systems(Update, analyze_bands.after(analyze_spectrum))
+        .add_systems(Update, prepare_my_material.after(analyze_bands).after(send_midi_notes));
+    
+    for signal in TestSignal::ALL {
+        app.register_source(
+            TestSignalDsp { signal, settings: test_signal_settings.clone(), sample_rate: engine.0 as f64 },
+            signal.name(),
+            SourceKind::TestSignal(signal),
+            "Calibration test signal",
         );
-        
-        wavefile.resize(num_samples);
-        
-        for channel in 0..num_channels {
-            for pos in 0..num_samples {
-                let sample = samples[pos * num_channels + channel];
-                wavefile.set(channel, pos, sample);
-            }
-        }
-        
-        wavefile
     }
     
-    // For native builds, use the original file system approach
+    // Dropped files have no path to read on the web
+    #[cfg(not(target_arch = "wasm32"))]
+    app.init_resource::<DroppedFiles>()
+        .add_systems(Update, (handle_file_drop, register_dropped_files).chain().before(update_audio_source));
+    
+    // About four seconds of buffering, playback starts once one is in
     #[cfg(not(target_arch = "wasm32"))]
     {
-        let reader = WavReader::open(path).expect("Failed to open WAV file");
-        let spec = reader.spec();
-        
-        println!("[WAV] File properties: {} channels, {} Hz sample rate",
-                 spec.channels, spec.sample_rate);
-        
-        let samples = reader
-            .into_samples::<i16>()
-            .filter_map(|s| s.ok())
-            .map(|sample| sample as f32 / 32768.0) // Convert i16 to f32 in range [-1, 1]
-            .collect::<Vec<f32>>();
-        
-        println!("[WAV] Loaded {} samples", samples.len());
-        
-        let num_channels = spec.channels as usize;
-        let num_samples = samples.len() / num_channels;
-        
-        let mut wavefile = Wave::with_capacity(
-            num_channels,
-            spec.sample_rate as f64,
-            num_samples,
+        let network_stream = radio::NetworkStream::new(
+            cli.url.clone(),
+            pcm_queue::PcmQueue::new(engine.0 as usize * 4, engine.0 as usize),
         );
+        let network_dsp = radio::NetworkStreamDsp {
+            queue: network_stream.queue.clone(),
+        };
+        app.insert_resource(network_stream)
+            .register_source(network_dsp, "Network Stream", SourceKind::NetworkStream, "HTTP/Icecast internet radio")
+            .add_systems(Update, update_network_stream.after(ui_example_system));
         
-        wavefile.resize(num_samples);
+        // A tenth of a second of jitter buffer; the reader keeps at most half
+        // a second queued
+        if cli.stdin {
+            let format = stdin_pcm::PcmFormat {
+                sample_format: match cli.stdin_format {
+                    cli::PcmFormatArg::S16le => stdin_pcm::SampleFormat::S16Le,
+                    cli::PcmFormatArg::F32le => stdin_pcm::SampleFormat::F32Le,
+                },
+                channels: cli.stdin_channels as usize,
+                sample_rate: cli.stdin_rate,
+            };
+            let stdin = stdin_pcm::StdinPcm::start(
+                format,
+                pcm_queue::PcmQueue::new(engine.0 as usize / 2, engine.0 as usize / 10),
+                engine.0,
+            );
+            let stdin_dsp = stdin_pcm::StdinDsp {
+                queue: stdin.queue.clone(),
+            };
+            app.insert_resource(stdin)
+                .register_source(stdin_dsp, "Stdin", SourceKind::Stdin, "Raw PCM piped into stdin");
+        }
         
-        for channel in 0..num_channels {
-            for pos in 0..num_samples {
-                let sample = samples[pos * num_channels + channel];
-                wavefile.set(channel, pos, sample);
+        // Remote control is opt-in; a port that's taken isn't worth failing over
+        if let Some(addr) = cli.osc {
+            match osc::OscServer::bind(addr) {
+                Ok(server) => {
+                    app.insert_resource(server)
+                        .add_systems(Update, handle_osc.after(ui_example_system).before(update_audio_source));
+                }
+                Err(e) => eprintln!("[OSC] Could not listen on {}: {}", addr, e),
             }
         }
-        
-        wavefile
     }
+    
+    app.run();
 }
 
-fn check_asset_loading(
-    mut asset_loading_state: ResMut<AssetLoadingState>,
-) {
-    // For WASM builds, we need to check if the WASM binary is loaded
-    #[cfg(target_arch = "wasm32")]
-    {
-        // Check if the WASM binary is loaded
-        if !asset_loading_state.wasm_loaded {
-            // For now, we'll assume the WASM binary is loaded when this system runs
-            // In a real application, you would check the actual loading state
-            asset_loading_state.wasm_loaded = true;
-            println!("[ASSET] WASM binary loaded");
-        }
-    }
-    
-    // For native builds, we'll assume the WASM binary is not needed
-    #[cfg(not(target_arch = "wasm32"))]
-    {
-        asset_loading_state.wasm_loaded = true;
-    }
-    
-    // Check if the WAV file is loaded
-    if !asset_loading_state.wav_loaded {
-        // For now, we'll assume the WAV file is loaded when this system runs
-        // In a real application, you would check the actual loading state
-        asset_loading_state.wav_loaded = true;
-        println!("[ASSET] WAV file loaded");
+fn primary_window(cli: &cli::Cli) -> Window {
+    let mut window = Window {
+        title: String::from("bevy_visualizer"),
+        ..default()
+    };
+    if let (Some(width), Some(height)) = (cli.width, cli.height) {
+        window.resolution = WindowResolution::new(width, height);
+    } else if cli.width.is_some() || cli.height.is_some() {
+        // Keep the default aspect when only one side is given
+        let default = window.resolution.clone();
+        window.resolution = WindowResolution::new(
+            cli.width.unwrap_or(default.physical_width()),
+            cli.height.unwrap_or(default.physical_height()),
+        );
     }
-    
-    // Check if all assets are loaded
-    if asset_loading_state.wasm_loaded && asset_loading_state.wav_loaded {
-        asset_loading_state.assets_loaded = true;
-        println!("[ASSET] All assets loaded");
+    if cli.fullscreen {
+        window.mode = WindowMode::BorderlessFullscreen(MonitorSelection::Current);
     }
+    window
+}
+
+// Stand-in until a track has loaded; wavech can't index an empty Wave
+fn silent_wave(sample_rate: f32) -> Wave {
+    let mut wave = Wave::new(1, sample_rate as f64);
+    wave.resize(1);
+    wave
 }
 
 fn quit_on_escape(input: Res<ButtonInput<KeyCode>>, mut exit_messages: ResMut<Messages<AppExit>>) {
@@ -424,14 +1087,28 @@ fn quit_on_escape(input: Res<ButtonInput<KeyCode>>, mut exit_messages: ResMut<Me
 struct CustomMaterial {
     #[uniform(0)]
     uniforms: ShaderData,
+    // Band levels, one texel per band; read with textureLoad
+    #[texture(1, sample_type = "float", filterable = false)]
+    bands: Handle<Image>,
 }
 
+// Texture every CustomMaterial reads the band spectrum from
+#[derive(Resource)]
+struct BandTexture(Handle<Image>);
+
 impl Material for CustomMaterial {
     fn fragment_shader() -> ShaderRef {
-        "shaders/enhanced_audio_visualizer.wgsl".into()
+        fragment_shader_path().into()
     }
 }
 
+fn fragment_shader_path() -> &'static str {
+    *FRAGMENT_SHADER.get().unwrap_or(&"shaders/enhanced_audio_visualizer.wgsl")
+}
+
+// Chosen on the command line before the app starts; Material only offers a static hook
+static FRAGMENT_SHADER: OnceLock<&'static str> = OnceLock::new();
+
 #[derive(Clone, Debug, TypePath, ShaderType, Component, Resource, Asset)]
 struct ShaderData {
     r: f32,
@@ -457,9 +1134,19 @@ fn setup_scene(
     mut meshes: ResMut<Assets<Mesh>>,
     mut s_materials: ResMut<Assets<StandardMaterial>>,
     mut c_materials: ResMut<Assets<CustomMaterial>>,
+    mut images: ResMut<Assets<Image>>,
     _shader_data: ResMut<ShaderData>,
-    _asset_server: Res<AssetServer>,
+    asset_server: Res<AssetServer>,
+    mut loading: ResMut<LoadingAssets>,
 ) {
+    // The material would load its shader anyway; holding the handle lets
+    // the loading screen wait for it and report a broken one
+    let shader_path = fragment_shader_path();
+    loading.track(shader_path, asset_server.load::<Shader>(shader_path).untyped(), true);
+    
+    let band_texture = images.add(bands::band_image(BandSettings::default().count));
+    commands.insert_resource(BandTexture(band_texture.clone()));
+
     // light
         commands.spawn((
             PointLight::default(),
@@ -483,6 +1170,7 @@ fn setup_scene(
                 b: 0.0,
                 time: 0.0,
             },
+            bands: band_texture,
         })),
         Transform::from_xyz(0.0, 0.5, 0.0),
     ));
@@ -495,10 +1183,39 @@ fn setup_scene(
     ));
 }
 
+// Resources behind the focused channel's controls, bundled to keep the UI
+// system under Bevy's parameter limit
+#[derive(SystemParam)]
+struct SourceControls<'w> {
+    live_input: ResMut<'w, LiveInput>,
+    keyboard_synth: ResMut<'w, KeyboardSynth>,
+    midi_state: Res<'w, MidiState>,
+    test_signal_settings: Res<'w, TestSignalSettings>,
+    #[cfg(not(target_arch = "wasm32"))]
+    network_stream: ResMut<'w, radio::NetworkStream>,
+    // Only there with --stdin
+    #[cfg(not(target_arch = "wasm32"))]
+    stdin: Option<Res<'w, stdin_pcm::StdinPcm>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    dropped_files: Res<'w, DroppedFiles>,
+}
+
 fn ui_example_system(
     mut contexts: EguiContexts,
     mut ui_state: ResMut<UiState>,
     mut shader_data: ResMut<ShaderData>,
+    engine: Res<EngineSampleRate>,
+    mut playlist: ResMut<Playlist>,
+    mut pause: ResMut<Pause>,
+    transport: Res<Transport>,
+    current_wave: Res<CurrentWave>,
+    mut controls: SourceControls,
+    analyzer: Res<SpectrumAnalyzer>,
+    band_analyzer: Res<BandAnalyzer>,
+    registry: Res<SourceRegistry>,
+    effects: Res<Effects>,
+    mut mixer: ResMut<Mixer>,
+    mut current_audio_player: ResMut<CurrentAudioPlayer>,
 ) {
     // Safely access the egui context with proper error handling
     let ctx_result = contexts.ctx_mut();
@@ -510,11 +1227,27 @@ fn ui_example_system(
                 .show(ctx, |ui| {
                     ui.heading("Side Panel");
 
-                    // Audio source selection
-                    ui.horizontal(|ui| {
-                        ui.label("Audio Source:");
-                        ui.radio_value(&mut ui_state.use_wave_file, false, "Sine Wave");
-                        ui.radio_value(&mut ui_state.use_wave_file, true, "Wave File");
+                    if ui_state.file_hovered {
+                        ui.label("Drop to play");
+                    }
+
+                    if let Some(error) = &ui_state.load_error {
+                        ui.colored_label(egui::Color32::RED, format!("Audio load failed: {}", error));
+                    }
+
+                    // Mixer strip, offering whatever is registered
+                    ui.collapsing("Mixer", |ui| {
+                        let ui_state = &mut *ui_state;
+                        // Nothing to play if the file didn't load
+                        let (loaded_wav, loaded_midi) = (ui_state.loaded_wav, ui_state.loaded_midi);
+                        let available = |info: &sources::SourceInfo| match info.kind {
+                            SourceKind::WaveFile => loaded_wav,
+                            SourceKind::Midi => loaded_midi,
+                            _ => true,
+                        };
+                        if mixer::mixer_panel(ui, &mut mixer, &registry, &mut ui_state.source, &mut ui_state.analysis_tap, available) {
+                            current_audio_player.rebuild = true;
+                        }
                     });
                     
                     // Audio processing method selection
@@ -523,16 +1256,105 @@ fn ui_example_system(
                         ui.radio_value(&mut ui_state.use_raw_audio, false, "FFT Frequency");
                         ui.radio_value(&mut ui_state.use_raw_audio, true, "Raw Audio");
                     });
+                    let fft_mode = !ui_state.use_raw_audio;
+                    spectrum::spectrum_panel(ui, &mut ui_state.analysis, &analyzer, engine.0, fft_mode);
+                    ui.add_enabled_ui(fft_mode, |ui| {
+                        bands::bands_panel(ui, &mut ui_state.bands, &band_analyzer);
+                    });
+                    // Channel taps are pre-fader; only the master tap has a side of the effects
+                    let tapping_master = ui_state.analysis_tap.is_none();
+                    ui.add_enabled_ui(tapping_master, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Analyze:");
+                            ui.radio_value(&mut ui_state.analyze_pre_effects, false, "Post-effects");
+                            ui.radio_value(&mut ui_state.analyze_pre_effects, true, "Pre-effects");
+                        });
+                    });
 
-                    if !ui_state.use_wave_file {
-                        ui.add(egui::Slider::new(&mut ui_state.value, 20.0..=24000.0).text("Audio Frequency (Hz)"));
-                        ui.label(format!("Current Frequency: {:.1} Hz", ui_state.value));
-                        if ui.button("Increment").clicked() {
-                            ui_state.value += 1.0;
+                    // Controls of the focused channel
+                    let focused = Some(ui_state.source).filter(|source| mixer.contains(*source));
+                    match focused.and_then(|source| registry.kind(source)) {
+                        Some(SourceKind::TestSignal(signal)) => {
+                            let mut settings = controls.test_signal_settings.0.lock().unwrap();
+                            // A new chord rebuilds the graph that plays it
+                            if calibration::calibration_panel(ui, signal, &mut settings) {
+                                current_audio_player.rebuild = true;
+                            }
+                        }
+                        Some(SourceKind::LiveInput) => {
+                            input::live_input_panel(ui, &mut controls.live_input);
+                        }
+                        #[cfg(not(target_arch = "wasm32"))]
+                        Some(SourceKind::NetworkStream) => {
+                            radio::network_stream_panel(ui, &mut controls.network_stream, engine.0);
+                        }
+                        #[cfg(not(target_arch = "wasm32"))]
+                        Some(SourceKind::Stdin) => {
+                            if let Some(stdin) = &controls.stdin {
+                                stdin_pcm::stdin_panel(ui, stdin, engine.0);
+                            }
                         }
-                    } else {
-                        ui.label("Playing: test.wav (44100 Hz)");
+                        #[cfg(not(target_arch = "wasm32"))]
+                        Some(SourceKind::DroppedFile) => {
+                            if let Some((name, dsp)) = controls.dropped_files.get(ui_state.source) {
+                                ui.label(format!("Playing: {} ({} Hz)", name, engine.0));
+                                if let Some(error) = dsp.track.error() {
+                                    ui.colored_label(egui::Color32::RED, format!("Stream failed: {}", error));
+                                }
+                                let mut paused = pause.0;
+                                let duration = dsp.track.duration(engine.0 as f64);
+                                transport::transport_panel(ui, &dsp.transport, &mut paused, duration, engine.0 as f64);
+                                if paused != pause.0 {
+                                    pause.0 = paused;
+                                }
+                            }
+                        }
+                        Some(SourceKind::Keyboard) => {
+                            keyboard::keyboard_panel(ui, &mut controls.keyboard_synth);
+                            let ui_state = &mut *ui_state;
+                            oscillator::oscillator_panel(ui, &mut ui_state.waveform, &mut ui_state.pulse_width, &mut ui_state.amplitude);
+                        }
+                        Some(SourceKind::Oscillator) => {
+                            ui.add(egui::Slider::new(&mut ui_state.value, 20.0..=24000.0).text("Audio Frequency (Hz)"));
+                            ui.label(format!("Current Frequency: {:.1} Hz", ui_state.value));
+                            if ui.button("Increment").clicked() {
+                                ui_state.value += 1.0;
+                            }
+                            let ui_state = &mut *ui_state;
+                            oscillator::oscillator_panel(ui, &mut ui_state.waveform, &mut ui_state.pulse_width, &mut ui_state.amplitude);
+                        }
+                        Some(kind) if kind.plays_track() => {
+                            let name = playlist.current_track().map_or("nothing", |t| t.name.as_str());
+                            ui.label(format!("Playing: {} ({} Hz)", name, engine.0));
+                            
+                            let duration = if kind == SourceKind::Midi {
+                                midi::midi_panel(ui, &controls.midi_state);
+                                controls.midi_state.duration()
+                            } else {
+                                let track = current_wave.slot.lock().unwrap();
+                                if let Some(error) = track.error() {
+                                    ui.colored_label(egui::Color32::RED, format!("Stream failed: {}", error));
+                                }
+                                track.duration(engine.0 as f64)
+                            };
+                            // Only write back on a click so change detection stays meaningful
+                            let mut paused = pause.0;
+                            transport::transport_panel(ui, &transport, &mut paused, duration, engine.0 as f64);
+                            if paused != pause.0 {
+                                pause.0 = paused;
+                            }
+                        }
+                        _ => {}
                     }
+                    
+                    ui.collapsing("Effects", |ui| {
+                        // Structural changes respawn the player with the new chain
+                        if effects::effects_panel(ui, &mut effects.slot.lock().unwrap()) {
+                            current_audio_player.rebuild = true;
+                        }
+                    });
+                    
+                    playlist::playlist_panel(ui, &mut playlist, &mut ui_state.new_track_path);
 
                     // these used to be plumbed directly to the shader data
                     // I'll set that up again later
@@ -557,13 +1379,54 @@ fn ui_example_system(
     }
 }
 
+// System to take the spectrum of the captured audio for whoever reads it
+fn analyze_spectrum(
+    mut analyzer: ResMut<SpectrumAnalyzer>,
+    sample_buffer: Res<SampleBuffer>,
+    ui_state: Res<UiState>,
+    engine: Res<EngineSampleRate>,
+) {
+    analyzer.configure(ui_state.analysis);
+    analyzer.process(&sample_buffer.history, sample_buffer.received, engine.0);
+}
+
+// System to fold the spectrum into the bands the shaders get
+fn analyze_bands(
+    mut bands: ResMut<BandAnalyzer>,
+    analyzer: Res<SpectrumAnalyzer>,
+    ui_state: Res<UiState>,
+) {
+    bands.configure(ui_state.bands);
+    bands.update(&analyzer);
+}
+
 fn prepare_my_material(
     mut material_assets: ResMut<Assets<CustomMaterial>>,
     mut shader_data: ResMut<ShaderData>,
     sample_buffer: Res<SampleBuffer>,
+    analyzer: Res<SpectrumAnalyzer>,
+    bands: Res<BandAnalyzer>,
+    band_texture: Res<BandTexture>,
+    mut images: ResMut<Assets<Image>>,
     time: Res<Time>,
     ui_state: Res<UiState>,
+    pause: Res<Pause>,
+    mut midi_notes: MessageReader<MidiNote>,
+    mut note_flash: Local<f32>,
 ) {
+    // Paused: leave the shader exactly where it was
+    if pause.0 {
+        return;
+    }
+    
+    // MIDI note-ons flash the colours by their velocity, on the exact frame they play
+    for MidiNote(note) in midi_notes.read() {
+        if note.is_on() {
+            *note_flash = note_flash.max(note.velocity as f32 / 127.0);
+        }
+    }
+    *note_flash *= (-time.delta_secs() * 8.0).exp();
+    
     // Process audio data and update shader_data resource
     
     // Debug: Log raw sample buffer data
@@ -604,54 +1467,16 @@ fn prepare_my_material(
         // Use FFT-based frequency analysis
         debug!("Using FFT-based frequency analysis");
         
-        // Convert the buffer to Complex numbers and process with FFT.
-        let mut complex_buffer: Vec<Complex<f32>> = sample_buffer.buffer.iter().map(|&x| Complex { re: x, im: 0.0 }).collect();
-            
-        let mut planner = FftPlanner::new();
-        let fft = planner.plan_fft_forward(complex_buffer.len());
-        fft.process(&mut complex_buffer);
-        
-        const BASS_MIN_FREQ: f32 = 20.0;    // Bass range
-        const BASS_MAX_FREQ: f32 = 250.0;
-        const MIDRANGE_MIN_FREQ: f32 = 250.0; // Midrange range
-        const MIDRANGE_MAX_FREQ: f32 = 4000.0;
-        const TREBLE_MIN_FREQ: f32 = 4000.0; // Treble range
-        const TREBLE_MAX_FREQ: f32 = 20000.0;
-        
-        let mut bass_sum = 0.0;
-        let mut midrange_sum = 0.0;
-        let mut treble_sum = 0.0;
-        let mut bass_count = 0;
-        let mut midrange_count = 0;
-        let mut treble_count = 0;
+        // Windowed, so a lone tone stays in its own band
+        let bass = analyzer.band_amplitude(BASS_MIN_FREQ, BASS_MAX_FREQ);
+        let mid = analyzer.band_amplitude(MIDRANGE_MIN_FREQ, MIDRANGE_MAX_FREQ);
+        let treble = analyzer.band_amplitude(TREBLE_MIN_FREQ, TREBLE_MAX_FREQ);
         
-        for (i, &result) in complex_buffer.iter().enumerate() {
-            // Calculate frequency in Hz
-            let frequency_in_hz = (i as f32 * SAMPLE_RATE) / BUFFER_SIZE as f32;
-            let magnitude = result.norm();
-        
-            if frequency_in_hz >= BASS_MIN_FREQ && frequency_in_hz <= BASS_MAX_FREQ {
-                bass_sum += magnitude;
-                bass_count += 1;
-            } else if frequency_in_hz >= MIDRANGE_MIN_FREQ && frequency_in_hz <= MIDRANGE_MAX_FREQ {
-                midrange_sum += magnitude;
-                midrange_count += 1;
-            } else if frequency_in_hz >= TREBLE_MIN_FREQ && frequency_in_hz <= TREBLE_MAX_FREQ {
-                treble_sum += magnitude;
-                treble_count += 1;
-            }
-        }
-        
-        // Calculate averages and amplify significantly for visualization
-        let bass_avg = if bass_count > 0 { bass_sum / bass_count as f32 } else { 0.0 };
-        let mid_avg = if midrange_count > 0 { midrange_sum / midrange_count as f32 } else { 0.0 };
-        let treble_avg = if treble_count > 0 { treble_sum / treble_count as f32 } else { 0.0 };
-           
-        // More realistic amplification with proper scaling for real audio
-        // FFT magnitudes need to be scaled appropriately for the frequency ranges
-        let bass_amplified = (bass_avg * 50.0).min(1.0);
-        let mid_amplified = (mid_avg * 100.0).min(1.0);
-        let treble_amplified = (treble_avg * 200.0).min(1.0);
+        // Band amplitudes are in full-scale units; real audio has less
+        // energy the higher the band, so the upper bands get more gain
+        let bass_amplified = (bass * 3.0).min(1.0);
+        let mid_amplified = (mid * 6.0).min(1.0);
+        let treble_amplified = (treble * 20.0).min(1.0);
           
         // Apply logarithmic scaling for better visual response
         let bass_final = (bass_amplified * 10.0).ln_1p() * 0.3;
@@ -663,12 +1488,17 @@ fn prepare_my_material(
     };
     
     // Update the shader data resource with the processed data and time
-    shader_data.r = bass_final;
-    shader_data.g = mid_final;
-    shader_data.b = treble_final;
-    shader_data.time = time.elapsed_secs() as f32;
+    shader_data.r = (bass_final + *note_flash * 0.3).min(1.0);
+    shader_data.g = (mid_final + *note_flash * 0.3).min(1.0);
+    shader_data.b = (treble_final + *note_flash * 0.3).min(1.0);
+    // Accumulate rather than read the clock so animation resumes where it paused
+    shader_data.time += time.delta_secs();
     shader_data.set_changed();
          
+    if let Some(image) = images.get_mut(&band_texture.0) {
+        bands.write_texture(image);
+    }
+         
     // Update all materials to use the new shader data
     for (_, material) in material_assets.iter_mut() {
         material.uniforms = shader_data.clone();
@@ -676,55 +1506,94 @@ fn prepare_my_material(
 }
 
 
-const BUFFER_SIZE: usize = 256;
+// Analysis bands; the calibration band noise uses the same edges
+const BASS_MIN_FREQ: f32 = 20.0;    // Bass range
+const BASS_MAX_FREQ: f32 = 250.0;
+const MIDRANGE_MIN_FREQ: f32 = 250.0; // Midrange range
+const MIDRANGE_MAX_FREQ: f32 = 4000.0;
+const TREBLE_MIN_FREQ: f32 = 4000.0; // Treble range
+const TREBLE_MAX_FREQ: f32 = 20000.0;
 
-#[derive(Resource)]
+// Captured audio, oldest sample first. Sized by the analysis settings on
+// every read, so changing them takes effect on the next frame.
+#[derive(Resource, Default)]
 pub struct SampleBuffer {
-    buffer: Vec<f32>,
+    buffer: Vec<f32>, // mono mix of left and right, the latest capture
+    left: Vec<f32>,
+    right: Vec<f32>,
+    // Mono mix of the capture plus whatever arrived since the previous
+    // read, for the analysis frames that ended in between
+    history: Vec<f32>,
+    received: usize,
+    // Snoop read last time, and how many samples it had seen then
+    last_read: Option<(usize, u64)>,
 }
 
-impl Default for SampleBuffer {
-    fn default() -> Self {
-        Self {
-            buffer: vec![0.0; BUFFER_SIZE],
-        }
-    }
+// Bring a snoop up to date; returns how many samples it has seen
+fn update_snoop(snoop: &Mutex<fundsp::hacker::Snoop>) -> u64 {
+    let mut snoop_guard = snoop.lock().unwrap();
+    snoop_guard.update();
+    snoop_guard.total()
+}
+
+// Copy the `len` most recent samples out of a snoop, oldest first, padding
+// the start with zeros
+fn read_snoop_channel(snoop: &Mutex<fundsp::hacker::Snoop>, len: usize, out: &mut Vec<f32>) {
+    let snoop_guard = snoop.lock().unwrap();
+    
+    // Get samples from the snoop buffer
+    let capacity = snoop_guard.capacity();
+    trace!("Snoop buffer capacity: {}", capacity);
+    
+    let available = capacity.min(len);
+    out.clear();
+    out.resize(len - available, 0.0);
+    out.extend((0..available).rev().map(|i| snoop_guard.at(i)));
+    
+    // Log how many samples we actually got
+    trace!("Samples collected: {}", available);
 }
 
 fn read_snooped_audio(
     mut sample_buffer: ResMut<SampleBuffer>,
     audio_snoop: Res<AudioSnoop>,
+    mixer: Res<Mixer>,
+    ui_state: Res<UiState>,
 ) {
-    // Read real audio data from the snoop receiver
-    let mut snoop_guard = audio_snoop.receiver.lock().unwrap();
+    let sample_buffer = &mut *sample_buffer;
+    let capture = ui_state.analysis.capture_length;
     
-    // Update the snoop to get the latest audio data
-    snoop_guard.update();
-    
-    // Clear the buffer and fill it with real audio data
-    sample_buffer.buffer.clear();
-    
-    // Get samples from the snoop buffer
-    let capacity = snoop_guard.capacity();
-    trace!("Snoop buffer capacity: {}", capacity);
+    // Read real audio data from both snoop receivers of the chosen tap: a
+    // mixer channel, or the master bus on either side of the effects
+    let reader = match ui_state.analysis_tap.and_then(|source| mixer.reader(source)) {
+        Some(reader) => reader,
+        None if ui_state.analyze_pre_effects => audio_snoop.pre.clone(),
+        None => audio_snoop.post.clone(),
+    };
     
-    for i in 0..capacity {
-        let sample = snoop_guard.at(i);
-        sample_buffer.buffer.push(sample);
-        
-        // Keep the buffer at a fixed size
-        if sample_buffer.buffer.len() >= BUFFER_SIZE {
-            break;
+    // New samples since the last read; none when the tap has just changed
+    let snoop_id = Arc::as_ptr(&reader.left) as usize;
+    let total = update_snoop(&reader.left);
+    update_snoop(&reader.right);
+    sample_buffer.received = match sample_buffer.last_read {
+        Some((id, last_total)) if id == snoop_id => {
+            (total.saturating_sub(last_total) as usize).min(spectrum::SNOOP_CAPACITY - capture)
         }
-    }
+        _ => 0,
+    };
+    sample_buffer.last_read = Some((snoop_id, total));
     
-    // Log how many samples we actually got
-    trace!("Samples collected: {}", sample_buffer.buffer.len());
+    let len = capture + sample_buffer.received;
+    read_snoop_channel(&reader.left, len, &mut sample_buffer.left);
+    read_snoop_channel(&reader.right, len, &mut sample_buffer.right);
     
-    // If we don't have enough samples, pad with zeros
-    while sample_buffer.buffer.len() < BUFFER_SIZE {
-        sample_buffer.buffer.push(0.0);
-    }
+    // Existing analysis works on the mono mix
+    sample_buffer.history.clear();
+    sample_buffer.history.extend(
+        sample_buffer.left.iter().zip(&sample_buffer.right).map(|(l, r)| 0.5 * (l + r)),
+    );
+    sample_buffer.buffer.clear();
+    sample_buffer.buffer.extend_from_slice(&sample_buffer.history[sample_buffer.received..]);
     
     // Log some sample values for debugging
     if sample_buffer.buffer.len() > 0 {
@@ -732,6 +1601,3 @@ fn read_snooped_audio(
     }
 }
 
-
-
-const SAMPLE_RATE: f32 = 44100.0; // Replace with your sample rate
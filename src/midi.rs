@@ -0,0 +1,594 @@
+// Standard MIDI File playback
+//
+// A .mid file is parsed once into a flat list of note events with times in
+// seconds, tempo changes already applied. `MidiPlayer` walks that list on
+// the audio thread, so notes start on the exact sample, and drives a pool of
+// voices built from the same oscillator bank and envelope as the keyboard
+// synth. The ECS side replays the list against the transport's playhead and
+// sends a `MidiNote` message for every note-on and note-off, so visuals can
+// react to the notes themselves rather than to FFT estimates.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_fundsp::prelude::*;
+use midly::{Format, MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+
+use crate::keyboard::{note_to_hz, Adsr, AdsrParams};
+use crate::oscillator::Oscillator;
+use crate::transport::{PlayheadState, Transport};
+
+// Voices in the player's pool, each driven by four control signals
+pub const MIDI_VOICES: usize = 16;
+type MidiVoices = U16;
+type VoiceControls = U64;
+
+// Time after the last event for releases to ring out before the song ends
+const TAIL_SECONDS: f64 = 2.0;
+
+// General MIDI percussion; there is no drum kit, so these notes only reach
+// the visuals
+const PERCUSSION_CHANNEL: u8 = 9;
+
+// Until the file says otherwise, a beat is 500000 microseconds (120 bpm)
+const DEFAULT_TEMPO: u32 = 500_000;
+
+#[derive(Debug)]
+pub enum MidiError {
+    Parse(midly::Error),
+    NoNotes,
+}
+
+impl fmt::Display for MidiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiError::Parse(e) => write!(f, "MIDI error: {}", e),
+            MidiError::NoNotes => write!(f, "MIDI file contains no notes"),
+        }
+    }
+}
+
+impl std::error::Error for MidiError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MidiError::Parse(e) => Some(e),
+            MidiError::NoNotes => None,
+        }
+    }
+}
+
+impl From<midly::Error> for MidiError {
+    fn from(e: midly::Error) -> Self {
+        MidiError::Parse(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoteEvent {
+    pub time: f64, // seconds from the start of the song
+    // Index among the tracks that contain notes
+    pub track: usize,
+    pub channel: u8,
+    pub key: u8,
+    pub velocity: u8, // 0 is a note-off
+}
+
+impl NoteEvent {
+    pub fn is_on(&self) -> bool {
+        self.velocity > 0
+    }
+}
+
+pub struct MidiSong {
+    pub events: Vec<NoteEvent>, // sorted by time
+    pub tracks: usize,
+    pub duration: f64, // time of the last event
+}
+
+impl MidiSong {
+    pub fn empty() -> Self {
+        Self { events: Vec::new(), tracks: 0, duration: 0.0 }
+    }
+
+    // Playback length including the release tail
+    pub fn length_frames(&self, sample_rate: f64) -> u64 {
+        ((self.duration + TAIL_SECONDS) * sample_rate) as u64
+    }
+
+    // Index of the first event at or after `time`
+    pub fn event_at(&self, time: f64) -> usize {
+        self.events.partition_point(|e| e.time < time)
+    }
+}
+
+pub fn is_midi(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"MThd")
+}
+
+// Piecewise-linear mapping from ticks to seconds
+struct TempoMap {
+    // (start tick, start time in seconds, seconds per tick)
+    segments: Vec<(u64, f64, f64)>,
+}
+
+impl TempoMap {
+    fn new(timing: Timing, mut changes: Vec<(u64, u32)>) -> Self {
+        let ticks_per_beat = match timing {
+            Timing::Metrical(tpb) => tpb.as_int().max(1) as f64,
+            // Timecode files count absolute time and ignore tempo events
+            Timing::Timecode(fps, subframes) => {
+                let per_tick = 1.0 / (fps.as_f32() as f64 * subframes.max(1) as f64);
+                return Self { segments: vec![(0, 0.0, per_tick)] };
+            }
+        };
+        let per_tick = |tempo: u32| tempo as f64 / 1_000_000.0 / ticks_per_beat;
+
+        changes.sort_by_key(|&(tick, _)| tick);
+        let mut segments = vec![(0, 0.0, per_tick(DEFAULT_TEMPO))];
+        for (tick, tempo) in changes {
+            let time = Self::time_in(&segments, tick);
+            // A change at the same tick replaces the previous one
+            if segments.last().is_some_and(|s| s.0 == tick) {
+                segments.pop();
+            }
+            segments.push((tick, time, per_tick(tempo)));
+        }
+        Self { segments }
+    }
+
+    fn time_in(segments: &[(u64, f64, f64)], tick: u64) -> f64 {
+        let index = segments.partition_point(|s| s.0 <= tick).saturating_sub(1);
+        let (start, time, per_tick) = segments[index];
+        time + (tick - start) as f64 * per_tick
+    }
+
+    fn seconds(&self, tick: u64) -> f64 {
+        Self::time_in(&self.segments, tick)
+    }
+}
+
+pub fn parse_midi(bytes: &[u8]) -> Result<MidiSong, MidiError> {
+    let smf = Smf::parse(bytes)?;
+
+    // (tick, source track, channel, key, velocity)
+    let mut notes: Vec<(u64, usize, u8, u8, u8)> = Vec::new();
+    let mut tempos: Vec<(u64, u32)> = Vec::new();
+    // Tracks of a sequential file play one after another
+    let mut offset = 0;
+    for (track, events) in smf.tracks.iter().enumerate() {
+        let mut tick = offset;
+        for event in events {
+            tick += event.delta.as_int() as u64;
+            match event.kind {
+                TrackEventKind::Midi { channel, message } => {
+                    let (key, velocity) = match message {
+                        MidiMessage::NoteOn { key, vel } => (key.as_int(), vel.as_int()),
+                        MidiMessage::NoteOff { key, .. } => (key.as_int(), 0),
+                        _ => continue,
+                    };
+                    notes.push((tick, track, channel.as_int(), key, velocity));
+                }
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => tempos.push((tick, tempo.as_int())),
+                _ => {}
+            }
+        }
+        if smf.header.format == Format::Sequential {
+            offset = tick;
+        }
+    }
+
+    if !notes.iter().any(|n| n.4 > 0) {
+        return Err(MidiError::NoNotes);
+    }
+
+    // Note-offs first at equal ticks so a repeated key retriggers
+    notes.sort_by_key(|n| (n.0, n.4 > 0));
+
+    // Number the tracks that actually have notes, skipping e.g. a conductor track
+    let mut note_tracks: Vec<usize> = notes.iter().map(|n| n.1).collect();
+    note_tracks.sort_unstable();
+    note_tracks.dedup();
+
+    let tempo_map = TempoMap::new(smf.header.timing, tempos);
+    let events: Vec<NoteEvent> = notes
+        .iter()
+        .map(|&(tick, track, channel, key, velocity)| NoteEvent {
+            time: tempo_map.seconds(tick),
+            track: note_tracks.binary_search(&track).unwrap(),
+            channel,
+            key,
+            velocity,
+        })
+        .collect();
+
+    let duration = events.last().map_or(0.0, |e| e.time);
+    println!("[MIDI] Parsed {} note events on {} tracks, {:.1} s",
+             events.len(), note_tracks.len(), duration);
+    Ok(MidiSong { events, tracks: note_tracks.len(), duration })
+}
+
+#[derive(Clone, Copy, Default)]
+struct VoiceState {
+    // (track, channel, key) holding the voice
+    owner: Option<(usize, u8, u8)>,
+    pitch: f32,
+    gate: f32,
+    note: f32,
+    velocity: f32,
+    age: u64,
+}
+
+// Sequencer node. Outputs pitch, gate, note counter and velocity for each
+// voice, in that order; the graph turns those into sound.
+#[derive(Clone)]
+pub struct MidiPlayer {
+    song: Arc<MidiSong>,
+    state: Arc<PlayheadState>,
+    frames: Arc<Vec<u64>>, // event times at the current sample rate
+    sample_rate: f64,
+    next: usize,
+    expected: u64,
+    voices: [VoiceState; MIDI_VOICES],
+    clock: u64,
+}
+
+impl MidiPlayer {
+    pub fn new(song: Arc<MidiSong>, state: Arc<PlayheadState>, sample_rate: f64) -> An<Self> {
+        let mut player = Self {
+            song,
+            state,
+            frames: Arc::new(Vec::new()),
+            sample_rate: 0.0,
+            next: 0,
+            expected: 0,
+            voices: [VoiceState::default(); MIDI_VOICES],
+            clock: 0,
+        };
+        player.set_sample_rate(sample_rate);
+        An(player)
+    }
+
+    // Voices a track may use; tracks get disjoint ranges while there are
+    // enough voices to go round
+    fn voice_range(&self, track: usize) -> std::ops::Range<usize> {
+        let tracks = self.song.tracks.max(1);
+        let start = (track * MIDI_VOICES / tracks).min(MIDI_VOICES - 1);
+        let end = ((track + 1) * MIDI_VOICES / tracks).clamp(start + 1, MIDI_VOICES);
+        start..end
+    }
+
+    fn release_all(&mut self) {
+        for voice in &mut self.voices {
+            voice.owner = None;
+            voice.gate = 0.0;
+        }
+    }
+
+    fn apply(&mut self, event: NoteEvent) {
+        if event.channel == PERCUSSION_CHANNEL {
+            return;
+        }
+        let owner = Some((event.track, event.channel, event.key));
+        self.clock += 1;
+
+        if !event.is_on() {
+            if let Some(voice) = self.voices.iter_mut().find(|v| v.owner == owner) {
+                voice.owner = None;
+                voice.gate = 0.0;
+                voice.age = self.clock;
+            }
+            return;
+        }
+
+        // Longest-released free voice in the track's range, otherwise the oldest held one
+        let range = self.voice_range(event.track);
+        let index = range
+            .clone()
+            .filter(|&i| self.voices[i].owner.is_none())
+            .min_by_key(|&i| self.voices[i].age)
+            .or_else(|| range.min_by_key(|&i| self.voices[i].age))
+            .unwrap();
+
+        let voice = &mut self.voices[index];
+        voice.owner = owner;
+        voice.pitch = note_to_hz(event.key as i32);
+        voice.gate = 1.0;
+        voice.note = self.clock as f32;
+        voice.velocity = event.velocity as f32 / 127.0;
+        voice.age = self.clock;
+    }
+}
+
+impl AudioNode for MidiPlayer {
+    const ID: u64 = 0x6d69_6469;
+    type Inputs = U0;
+    type Outputs = VoiceControls;
+
+    fn reset(&mut self) {
+        self.next = 0;
+        self.expected = 0;
+        self.release_all();
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.frames = Arc::new(self.song.events.iter().map(|e| (e.time * sample_rate) as u64).collect());
+            self.expected = u64::MAX; // resync on the next tick
+        }
+    }
+
+    fn tick(&mut self, _input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        match self.state.next_frame(self.song.length_frames(self.sample_rate)) {
+            Some(pos) => {
+                if pos != self.expected {
+                    // Seeked, restarted or resumed elsewhere: drop held notes and find our place
+                    self.release_all();
+                    self.next = self.frames.partition_point(|&f| f < pos);
+                }
+                self.expected = pos + 1;
+
+                while self.next < self.frames.len() && self.frames[self.next] <= pos {
+                    let event = self.song.events[self.next];
+                    self.apply(event);
+                    self.next += 1;
+                }
+            }
+            None => {
+                // Paused or finished; let everything ring out
+                self.release_all();
+                self.expected = u64::MAX;
+            }
+        }
+
+        Frame::generate(|i| {
+            let voice = &self.voices[i / 4];
+            match i % 4 {
+                0 => voice.pitch,
+                1 => voice.gate,
+                2 => voice.note,
+                _ => voice.velocity,
+            }
+        })
+    }
+}
+
+// The song the MIDI source plays; swapped by the playlist like the wave slot
+pub type MidiSlot = Arc<Mutex<Arc<MidiSong>>>;
+
+// Graph for the MIDI source
+pub struct MidiDsp {
+    pub song: MidiSlot,
+    pub transport: Transport,
+    pub oscillator: Oscillator,
+    pub adsr: AdsrParams,
+    pub amplitude: Shared,
+    pub sample_rate: f64,
+}
+
+impl DspGraph for MidiDsp {
+    fn id(&self) -> Uuid {
+        crate::sources::MIDI
+    }
+
+    fn generate_graph(&self) -> Box<dyn AudioUnit> {
+        let song = self.song.lock().unwrap().clone();
+        let player = MidiPlayer::new(song, self.transport.state.clone(), self.sample_rate);
+        // pitch -> oscillator, gate and note counter -> envelope, times velocity
+        let voice = |_| {
+            (self.oscillator.bank_in() | Adsr::new(self.adsr.clone()) | pass()) >> (pass() * pass() * pass())
+        };
+        // join averages, so scale back up and leave some headroom for chords
+        let voices = player >> stack::<MidiVoices, _, _>(voice) >> join::<MidiVoices>() * (MIDI_VOICES as f32 * 0.3);
+        let audio = voices * var(&self.amplitude);
+        Box::new(audio >> split::<U2>())
+    }
+}
+
+// Sent for each note event as the playhead passes it
+#[derive(Message, Clone, Copy, Debug)]
+pub struct MidiNote(pub NoteEvent);
+
+// ECS-side follower of the song, for the messages and the UI
+#[derive(Resource)]
+pub struct MidiState {
+    pub song: MidiSlot,
+    followed: Option<Arc<MidiSong>>,
+    next: usize,
+    last_time: f64,
+    // Notes currently held, as (track, channel, key)
+    pub held: Vec<(usize, u8, u8)>,
+    pub last_note: Option<NoteEvent>,
+}
+
+impl MidiState {
+    pub fn new(song: MidiSlot) -> Self {
+        Self {
+            song,
+            followed: None,
+            next: 0,
+            last_time: 0.0,
+            held: Vec::new(),
+            last_note: None,
+        }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.song.lock().unwrap().duration + TAIL_SECONDS
+    }
+
+    pub fn length_frames(&self, sample_rate: f64) -> u64 {
+        self.song.lock().unwrap().length_frames(sample_rate)
+    }
+
+    // Events between the last call and `time`. Jumps (seeks, a new song)
+    // resync without replaying what was skipped.
+    pub fn advance(&mut self, time: f64) -> Vec<NoteEvent> {
+        let song = self.song.lock().unwrap().clone();
+        let same_song = self.followed.as_ref().is_some_and(|s| Arc::ptr_eq(s, &song));
+        // More than a second forward is a seek, not a slow frame
+        if !same_song || time < self.last_time || time - self.last_time > 1.0 {
+            self.followed = Some(song.clone());
+            self.next = song.event_at(time);
+            self.held.clear();
+            self.last_time = time;
+            return Vec::new();
+        }
+        self.last_time = time;
+
+        let start = self.next;
+        while self.next < song.events.len() && song.events[self.next].time <= time {
+            let event = song.events[self.next];
+            let note = (event.track, event.channel, event.key);
+            self.held.retain(|&n| n != note);
+            if event.is_on() {
+                self.held.push(note);
+                self.last_note = Some(event);
+            }
+            self.next += 1;
+        }
+        song.events[start..self.next].to_vec()
+    }
+}
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+
+fn note_name(key: u8) -> String {
+    format!("{}{}", NOTE_NAMES[key as usize % 12], key as i32 / 12 - 1)
+}
+
+// Side panel section for the MIDI source
+pub fn midi_panel(ui: &mut egui::Ui, state: &MidiState) {
+    let tracks = state.song.lock().unwrap().tracks;
+    ui.label(format!("{} tracks, {} notes held", tracks, state.held.len()));
+    if let Some(note) = &state.last_note {
+        ui.label(format!("Last note: {} (track {}, velocity {})", note_name(note.key), note.track + 1, note.velocity));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use midly::num::u15;
+    use midly::Fps;
+
+    const TEMPO_60_BPM: [u8; 6] = [0xff, 0x51, 0x03, 0x0f, 0x42, 0x40];
+    const END_OF_TRACK: [u8; 3] = [0xff, 0x2f, 0x00];
+
+    // Standard MIDI File with 96 ticks per beat; track events are given with
+    // their single-byte deltas
+    fn smf(format: u16, tracks: &[&[u8]]) -> Vec<u8> {
+        let mut bytes = b"MThd".to_vec();
+        bytes.extend(6u32.to_be_bytes());
+        bytes.extend(format.to_be_bytes());
+        bytes.extend((tracks.len() as u16).to_be_bytes());
+        bytes.extend(96u16.to_be_bytes());
+        for track in tracks {
+            bytes.extend(b"MTrk");
+            bytes.extend((track.len() as u32 + 4).to_be_bytes());
+            bytes.extend(*track);
+            bytes.push(0);
+            bytes.extend(END_OF_TRACK);
+        }
+        bytes
+    }
+
+    fn metrical(ticks_per_beat: u16, changes: Vec<(u64, u32)>) -> TempoMap {
+        TempoMap::new(Timing::Metrical(u15::new(ticks_per_beat)), changes)
+    }
+
+    fn assert_near(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn tempo_defaults_to_120_bpm() {
+        let map = metrical(480, Vec::new());
+        assert_near(map.seconds(0), 0.0);
+        assert_near(map.seconds(480), 0.5);
+        assert_near(map.seconds(4800), 5.0);
+    }
+
+    #[test]
+    fn tempo_changes_apply_from_their_tick() {
+        // 120 bpm, then 60 from beat 2, then 240 from beat 4; given out of order
+        let map = metrical(480, vec![(1920, 250_000), (960, 1_000_000)]);
+        assert_near(map.seconds(480), 0.5);
+        assert_near(map.seconds(960), 1.0);
+        assert_near(map.seconds(1440), 2.0);
+        assert_near(map.seconds(1920), 3.0);
+        assert_near(map.seconds(2400), 3.25);
+    }
+
+    #[test]
+    fn later_tempo_at_the_same_tick_wins() {
+        let map = metrical(480, vec![(0, 1_000_000), (0, 250_000), (480, 1_000_000), (480, 500_000)]);
+        assert_near(map.seconds(480), 0.25);
+        assert_near(map.seconds(960), 0.75);
+    }
+
+    #[test]
+    fn timecode_ignores_tempo() {
+        // 25 frames of 40 ticks is a millisecond per tick
+        let map = TempoMap::new(Timing::Timecode(Fps::Fps25, 40), vec![(0, 1_000_000)]);
+        assert_near(map.seconds(1000), 1.0);
+        assert_near(map.seconds(2500), 2.5);
+    }
+
+    #[test]
+    fn parses_notes_at_the_file_tempo() {
+        let track = [
+            &[0x00][..],
+            &TEMPO_60_BPM,
+            &[0x00, 0x90, 60, 100],
+            // The repeated key is listed before its own note-off
+            &[0x60, 0x90, 60, 80],
+            &[0x00, 0x80, 60, 64],
+            // A note-on with no velocity is a note-off
+            &[0x30, 0x90, 60, 0],
+        ]
+        .concat();
+        let bytes = smf(0, &[&track]);
+        assert!(is_midi(&bytes));
+
+        let song = parse_midi(&bytes).unwrap();
+        let notes: Vec<(f64, u8, u8)> = song.events.iter().map(|e| (e.time, e.key, e.velocity)).collect();
+        assert_eq!(notes, [(0.0, 60, 100), (1.0, 60, 0), (1.0, 60, 80), (1.5, 60, 0)]);
+        assert_eq!(song.tracks, 1);
+        assert_near(song.duration, 1.5);
+        assert_eq!(song.length_frames(1000.0), 3500);
+        assert_eq!(song.event_at(1.0), 1);
+    }
+
+    #[test]
+    fn conductor_track_sets_the_tempo_for_the_rest() {
+        let conductor = [&[0x00][..], &TEMPO_60_BPM].concat();
+        let bass = [0x60, 0x91, 36, 90, 0x60, 0x81, 36, 0];
+        let lead = [0x30, 0x92, 72, 90, 0x30, 0x82, 72, 0];
+        let song = parse_midi(&smf(1, &[&conductor, &bass, &lead])).unwrap();
+
+        let notes: Vec<(f64, usize, u8)> = song.events.iter().map(|e| (e.time, e.track, e.channel)).collect();
+        assert_eq!(notes, [(0.5, 1, 2), (1.0, 1, 2), (1.0, 0, 1), (2.0, 0, 1)]);
+        // The conductor has no notes, so it isn't counted
+        assert_eq!(song.tracks, 2);
+    }
+
+    #[test]
+    fn sequential_tracks_follow_each_other() {
+        let first = [0x00, 0x90, 60, 90, 0x60, 0x80, 60, 0];
+        let second = [0x00, 0x90, 64, 90, 0x60, 0x80, 64, 0];
+        let song = parse_midi(&smf(2, &[&first, &second])).unwrap();
+        let times: Vec<f64> = song.events.iter().map(|e| e.time).collect();
+        assert_eq!(times, [0.0, 0.5, 0.5, 1.0]);
+    }
+
+    #[test]
+    fn rejects_files_without_notes() {
+        let tempo_only = [&[0x00][..], &TEMPO_60_BPM].concat();
+        assert!(matches!(parse_midi(&smf(0, &[&tempo_only])), Err(MidiError::NoNotes)));
+        assert!(matches!(parse_midi(b"MThd"), Err(MidiError::Parse(_))));
+        assert!(!is_midi(b"RIFF"));
+    }
+}
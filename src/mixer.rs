@@ -0,0 +1,419 @@
+// Mixer for playing several sources at once
+//
+// Each channel plays one registered source through a pre-fader snoop, then
+// its gain and pan. The channels are summed into the master bus, which runs
+// the effects chain between the pre- and post-effects snoops. The whole mix
+// is one DspGraph, so there is a single AudioPlayer however many channels
+// there are.
+//
+// Gain, pan, mute and solo are folded into a left and right level per
+// channel on the ECS side and read by the running graph; adding or removing
+// a channel rebuilds the graph.
+
+use std::sync::{Arc, Mutex};
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_fundsp::prelude::*;
+use fundsp::hacker32::{Snoop, SnoopBackend};
+
+use crate::effects::{self, EffectSlot};
+use crate::sources::{SourceInfo, SourceKind, SourceRegistry};
+use crate::spectrum::SNOOP_CAPACITY;
+
+pub const MIXER: Uuid = Uuid::from_u128(0x6d697865_725f_6275_7300_000000000001u128);
+
+// How long level changes take to settle, in seconds
+const FADE_TIME: f32 = 0.02;
+
+// Snoop backends for both channels of a stereo signal
+#[derive(Clone)]
+pub struct StereoSnoop {
+    left: An<SnoopBackend>,
+    right: An<SnoopBackend>,
+}
+
+impl StereoSnoop {
+    // Stereo pass-through that records both channels
+    pub fn tap(&self) -> An<impl AudioNode<Inputs = U2, Outputs = U2>> {
+        self.left.clone() | self.right.clone()
+    }
+}
+
+// The ECS end of a StereoSnoop
+#[derive(Clone)]
+pub struct SnoopReader {
+    pub left: Arc<Mutex<Snoop>>,
+    pub right: Arc<Mutex<Snoop>>,
+}
+
+pub fn stereo_snoop(capacity: usize) -> (SnoopReader, StereoSnoop) {
+    let (left_frontend, left_backend) = fundsp::hacker32::snoop(capacity);
+    let (right_frontend, right_backend) = fundsp::hacker32::snoop(capacity);
+    (
+        SnoopReader {
+            left: Arc::new(Mutex::new(left_frontend)),
+            right: Arc::new(Mutex::new(right_frontend)),
+        },
+        StereoSnoop { left: left_backend, right: right_backend },
+    )
+}
+
+fn level(shared: &Shared) -> An<impl AudioNode<Inputs = U0, Outputs = U1>> {
+    var(shared) >> follow(FADE_TIME)
+}
+
+pub struct Channel {
+    pub source: Uuid,
+    pub kind: SourceKind,
+    graph: Arc<dyn DspGraph + Send + Sync>,
+    pub gain: f32,
+    pub pan: f32, // -1 is hard left, 1 hard right
+    pub mute: bool,
+    pub solo: bool,
+    // Output levels after gain, pan, mute and solo
+    left: Shared,
+    right: Shared,
+    tap: StereoSnoop,
+    reader: SnoopReader,
+}
+
+impl Channel {
+    fn new(info: &SourceInfo) -> Self {
+        let (reader, tap) = stereo_snoop(SNOOP_CAPACITY);
+        Self {
+            source: info.id,
+            kind: info.kind,
+            graph: info.graph.clone(),
+            // A microphone starts unheard so it can't feed back; its tap still sees it
+            gain: if info.kind == SourceKind::LiveInput { 0.0 } else { 1.0 },
+            pan: 0.0,
+            mute: false,
+            solo: false,
+            left: shared(0.0),
+            right: shared(0.0),
+            tap,
+            reader,
+        }
+    }
+
+    fn graph(&self) -> Net {
+        let fader = (pass() * level(&self.left)) | (pass() * level(&self.right));
+        Net::wrap(self.graph.generate_graph()) >> self.tap.tap() >> fader
+    }
+}
+
+pub type ChannelSlot = Arc<Mutex<Vec<Channel>>>;
+
+#[derive(Resource)]
+pub struct Mixer {
+    channels: ChannelSlot,
+    pub master: Shared,
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self {
+            channels: Arc::new(Mutex::new(Vec::new())),
+            master: shared(1.0),
+        }
+    }
+}
+
+impl Mixer {
+    pub fn slot(&self) -> ChannelSlot {
+        self.channels.clone()
+    }
+
+    pub fn contains(&self, source: Uuid) -> bool {
+        self.channels.lock().unwrap().iter().any(|c| c.source == source)
+    }
+
+    // The channel playing the playlist's track, if there is one
+    pub fn track_source(&self) -> Option<Uuid> {
+        self.channels.lock().unwrap().iter().find(|c| c.kind.plays_track()).map(|c| c.source)
+    }
+
+    // Pre-fader tap of a channel
+    pub fn reader(&self, source: Uuid) -> Option<SnoopReader> {
+        self.channels.lock().unwrap().iter().find(|c| c.source == source).map(|c| c.reader.clone())
+    }
+
+    pub fn add(&mut self, info: &SourceInfo) {
+        if self.contains(info.id) {
+            return;
+        }
+        println!("[MIXER] Adding channel: {}", info.name);
+        self.channels.lock().unwrap().push(Channel::new(info));
+        self.update_levels();
+    }
+
+    pub fn remove(&mut self, source: Uuid) {
+        self.channels.lock().unwrap().retain(|c| c.source != source);
+        self.update_levels();
+    }
+
+    // Put a track source in the mix. Only one plays the playlist at a time,
+    // so it takes over the channel of any other, keeping its settings.
+    pub fn set_track_source(&mut self, info: &SourceInfo) {
+        {
+            let mut channels = self.channels.lock().unwrap();
+            if let Some(channel) = channels.iter_mut().find(|c| c.kind.plays_track()) {
+                if channel.source != info.id {
+                    let mut replacement = Channel::new(info);
+                    replacement.gain = channel.gain;
+                    replacement.pan = channel.pan;
+                    replacement.mute = channel.mute;
+                    replacement.solo = channel.solo;
+                    *channel = replacement;
+                }
+            } else {
+                channels.push(Channel::new(info));
+            }
+        }
+        self.update_levels();
+    }
+
+    fn update_levels(&self) {
+        let channels = self.channels.lock().unwrap();
+        let soloing = channels.iter().any(|c| c.solo);
+        for channel in channels.iter() {
+            let audible = !channel.mute && (!soloing || channel.solo);
+            let level = if audible { channel.gain } else { 0.0 };
+            channel.left.set_value(level * (1.0 - channel.pan).min(1.0));
+            channel.right.set_value(level * (1.0 + channel.pan).min(1.0));
+        }
+    }
+}
+
+// Graph for the whole mix; the only graph that is played directly
+pub struct MixerDsp {
+    pub channels: ChannelSlot,
+    pub master: Shared,
+    pub effects: EffectSlot,
+    pub pre: StereoSnoop,
+    pub post: StereoSnoop,
+}
+
+impl DspGraph for MixerDsp {
+    fn id(&self) -> Uuid {
+        MIXER
+    }
+
+    fn generate_graph(&self) -> Box<dyn AudioUnit> {
+        let channels = self.channels.lock().unwrap();
+        let silence = Net::wrap(Box::new(zero() | zero()));
+        let sum = channels.iter().fold(silence, |sum, channel| sum + channel.graph());
+        let chain = effects::build_chain(&self.effects.lock().unwrap());
+        let master = (pass() * level(&self.master)) | (pass() * level(&self.master));
+        Box::new(sum >> self.pre.tap() >> chain >> master >> self.post.tap())
+    }
+}
+
+// Side panel mixer strip. `focus` is the channel whose own controls are
+// shown, `tap` the channel analysed (None for the master bus), and
+// `available` says which sources can be added. Returns true when the graph
+// needs rebuilding.
+pub fn mixer_panel(
+    ui: &mut egui::Ui,
+    mixer: &mut Mixer,
+    registry: &SourceRegistry,
+    focus: &mut Uuid,
+    tap: &mut Option<Uuid>,
+    available: impl Fn(&SourceInfo) -> bool,
+) -> bool {
+    let mut rebuild = false;
+    let mut levels_changed = false;
+    let mut remove = None;
+
+    for channel in mixer.channels.lock().unwrap().iter_mut() {
+        ui.push_id(channel.source, |ui| {
+            ui.horizontal(|ui| {
+                if ui.selectable_label(*focus == channel.source, registry.name(channel.source)).clicked() {
+                    *focus = channel.source;
+                }
+                levels_changed |= ui.toggle_value(&mut channel.mute, "M").changed();
+                levels_changed |= ui.toggle_value(&mut channel.solo, "S").changed();
+                if ui.radio(*tap == Some(channel.source), "Tap").clicked() {
+                    *tap = Some(channel.source);
+                }
+                if ui.small_button("Remove").clicked() {
+                    remove = Some(channel.source);
+                }
+            });
+            levels_changed |= ui.add(egui::Slider::new(&mut channel.gain, 0.0..=2.0).text("Gain")).changed();
+            levels_changed |= ui.add(egui::Slider::new(&mut channel.pan, -1.0..=1.0).text("Pan")).changed();
+        });
+    }
+
+    if let Some(source) = remove {
+        mixer.remove(source);
+        if *tap == Some(source) {
+            *tap = None;
+        }
+        rebuild = true;
+    } else if levels_changed {
+        mixer.update_levels();
+    }
+
+    ui.horizontal(|ui| {
+        ui.label("Master");
+        if ui.radio(tap.is_none(), "Tap").clicked() {
+            *tap = None;
+        }
+    });
+    let mut master = mixer.master.value();
+    if ui.add(egui::Slider::new(&mut master, 0.0..=2.0).text("Master Gain")).changed() {
+        mixer.master.set_value(master);
+    }
+
+    egui::ComboBox::from_id_salt("add_channel")
+        .selected_text("Add source...")
+        .show_ui(ui, |ui| {
+            for info in registry.iter() {
+                if mixer.contains(info.id) {
+                    continue;
+                }
+                let clicked = ui
+                    .add_enabled_ui(available(info), |ui| {
+                        ui.selectable_label(false, &info.name).on_hover_text(&info.description).clicked()
+                    })
+                    .inner;
+                if clicked {
+                    if info.kind.plays_track() {
+                        mixer.set_track_source(info);
+                    } else {
+                        mixer.add(info);
+                    }
+                    *focus = info.id;
+                    rebuild = true;
+                }
+            }
+        });
+
+    rebuild
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Silence(Uuid);
+
+    impl DspGraph for Silence {
+        fn id(&self) -> Uuid {
+            self.0
+        }
+
+        fn generate_graph(&self) -> Box<dyn AudioUnit> {
+            Box::new(zero() | zero())
+        }
+    }
+
+    fn info(id: Uuid, kind: SourceKind) -> SourceInfo {
+        SourceInfo {
+            id,
+            name: format!("{:?}", kind),
+            kind,
+            description: String::new(),
+            graph: Arc::new(Silence(id)),
+        }
+    }
+
+    fn mixer(sources: &[(Uuid, SourceKind)]) -> Mixer {
+        let mut mixer = Mixer::default();
+        for &(id, kind) in sources {
+            mixer.add(&info(id, kind));
+        }
+        mixer
+    }
+
+    // Change a channel's settings the way the panel does
+    fn edit(mixer: &Mixer, source: Uuid, change: impl FnOnce(&mut Channel)) {
+        change(mixer.channels.lock().unwrap().iter_mut().find(|c| c.source == source).unwrap());
+        mixer.update_levels();
+    }
+
+    fn levels(mixer: &Mixer, source: Uuid) -> (f32, f32) {
+        let channels = mixer.channels.lock().unwrap();
+        let channel = channels.iter().find(|c| c.source == source).unwrap();
+        (channel.left.value(), channel.right.value())
+    }
+
+    const OSCILLATOR: Uuid = crate::sources::OSCILLATOR;
+    const KEYBOARD: Uuid = crate::sources::KEYBOARD;
+
+    #[test]
+    fn gain_and_pan_set_channel_levels() {
+        let mixer = mixer(&[(OSCILLATOR, SourceKind::Oscillator)]);
+        assert_eq!(levels(&mixer, OSCILLATOR), (1.0, 1.0));
+
+        edit(&mixer, OSCILLATOR, |c| c.gain = 0.5);
+        assert_eq!(levels(&mixer, OSCILLATOR), (0.5, 0.5));
+        // Panning turns the far side down and leaves the near side at the gain
+        edit(&mixer, OSCILLATOR, |c| c.pan = -1.0);
+        assert_eq!(levels(&mixer, OSCILLATOR), (0.5, 0.0));
+        edit(&mixer, OSCILLATOR, |c| c.pan = 0.5);
+        assert_eq!(levels(&mixer, OSCILLATOR), (0.25, 0.5));
+        edit(&mixer, OSCILLATOR, |c| c.pan = 1.0);
+        assert_eq!(levels(&mixer, OSCILLATOR), (0.0, 0.5));
+        edit(&mixer, OSCILLATOR, |c| (c.gain, c.pan) = (2.0, 0.0));
+        assert_eq!(levels(&mixer, OSCILLATOR), (2.0, 2.0));
+    }
+
+    #[test]
+    fn mute_and_solo() {
+        let live = crate::sources::LIVE_INPUT;
+        let mixer = mixer(&[
+            (OSCILLATOR, SourceKind::Oscillator),
+            (KEYBOARD, SourceKind::Keyboard),
+            (live, SourceKind::LiveInput),
+        ]);
+        // The microphone starts unheard
+        assert_eq!(levels(&mixer, live), (0.0, 0.0));
+        edit(&mixer, live, |c| c.gain = 1.0);
+
+        edit(&mixer, OSCILLATOR, |c| c.mute = true);
+        assert_eq!(levels(&mixer, OSCILLATOR), (0.0, 0.0));
+        assert_eq!(levels(&mixer, KEYBOARD), (1.0, 1.0));
+
+        edit(&mixer, KEYBOARD, |c| c.solo = true);
+        assert_eq!(levels(&mixer, KEYBOARD), (1.0, 1.0));
+        assert_eq!(levels(&mixer, live), (0.0, 0.0));
+        // Mute wins over solo
+        edit(&mixer, OSCILLATOR, |c| c.solo = true);
+        assert_eq!(levels(&mixer, OSCILLATOR), (0.0, 0.0));
+
+        edit(&mixer, KEYBOARD, |c| c.solo = false);
+        edit(&mixer, OSCILLATOR, |c| (c.mute, c.solo) = (false, false));
+        for source in [OSCILLATOR, KEYBOARD, live] {
+            assert_eq!(levels(&mixer, source), (1.0, 1.0));
+        }
+    }
+
+    #[test]
+    fn removing_a_solo_channel_unmutes_the_rest() {
+        let mut mixer = mixer(&[(OSCILLATOR, SourceKind::Oscillator), (KEYBOARD, SourceKind::Keyboard)]);
+        edit(&mixer, KEYBOARD, |c| c.solo = true);
+        assert_eq!(levels(&mixer, OSCILLATOR), (0.0, 0.0));
+        mixer.remove(KEYBOARD);
+        assert!(!mixer.contains(KEYBOARD));
+        assert_eq!(levels(&mixer, OSCILLATOR), (1.0, 1.0));
+    }
+
+    #[test]
+    fn track_source_takes_over_the_track_channel() {
+        let wave = crate::sources::WAVE_FILE;
+        let midi = crate::sources::MIDI;
+        let mut mixer = mixer(&[(OSCILLATOR, SourceKind::Oscillator)]);
+        mixer.set_track_source(&info(wave, SourceKind::WaveFile));
+        assert_eq!(mixer.track_source(), Some(wave));
+        edit(&mixer, wave, |c| (c.gain, c.pan) = (0.5, -0.5));
+
+        mixer.set_track_source(&info(midi, SourceKind::Midi));
+        assert_eq!(mixer.track_source(), Some(midi));
+        assert!(!mixer.contains(wave));
+        assert_eq!(mixer.channels.lock().unwrap().len(), 2);
+        assert_eq!(levels(&mixer, midi), (0.5, 0.25));
+    }
+}
@@ -0,0 +1,623 @@
+// OSC control over UDP
+//
+// Address space. Values can be sent as int, float or double; a value
+// address sent with no arguments is a query and is answered with its
+// current value.
+//
+//   /source s                  put a registered source in the mix and show its
+//                              controls ("Oscillator", "wave_file", ...)
+//   /source/remove s           take a source out of the mix
+//   /sine/frequency f          oscillator frequency in Hz, 20-24000
+//   /sine/amplitude f          0-1
+//   /sine/waveform s           sine, saw, square, triangle, pulse, white_noise, pink_noise
+//   /sine/pulse_width f        0-1
+//   /analysis/mode s           raw or fft
+//   /analysis/pre_effects i    1 to analyse before the effects chain
+//   /mixer/master f            master gain, 0-2
+//   /transport/play, /transport/pause, /transport/stop
+//   /transport/seek f          seconds into the track
+//   /transport/paused i        query only
+//   /playlist/next, /playlist/previous
+//   /playlist/track s          query only, the current track's name
+//   /state                     answered with every value above, as a bundle
+//   /subscribe, /unsubscribe   start or stop state broadcasts to the sender
+//
+// Every change is answered with the new value at the same address, and
+// errors with `/error s`. Subscribers get whatever changed, at most ten
+// times a second. A subscription lapses after a minute without a message
+// from the subscriber, and at most MAX_SUBSCRIBERS are kept.
+
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+use bevy::prelude::*;
+use rosc::{OscBundle, OscMessage, OscPacket, OscType};
+use uuid::Uuid;
+
+use crate::oscillator::Waveform;
+
+// Largest datagram read
+const MAX_PACKET: usize = 65536;
+const BROADCAST_INTERVAL: f64 = 0.1;
+const MAX_SUBSCRIBERS: usize = 16;
+// Seconds without a message before a subscriber is dropped
+const SUBSCRIBER_TIMEOUT: f64 = 60.0;
+
+pub enum OscCommand {
+    Source(String),
+    RemoveSource(String),
+    Frequency(f32),
+    Amplitude(f32),
+    Waveform(Waveform),
+    PulseWidth(f32),
+    RawAnalysis(bool),
+    PreEffects(bool),
+    MasterGain(f32),
+    Play,
+    Pause,
+    Stop,
+    Seek(f32),
+    NextTrack,
+    PreviousTrack,
+    // Answer with the current value at this address, or everything for /state
+    Query(String),
+    Subscribe,
+    Unsubscribe,
+}
+
+// Registry names, waveforms and modes compare without case, spaces or underscores
+pub fn name_matches(name: &str, wanted: &str) -> bool {
+    let simplify = |s: &str| -> String {
+        s.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+    };
+    simplify(name) == simplify(wanted)
+}
+
+fn float_arg(msg: &OscMessage) -> Result<Option<f32>, String> {
+    match msg.args.first() {
+        None => Ok(None),
+        Some(OscType::Float(v)) => Ok(Some(*v)),
+        Some(OscType::Double(v)) => Ok(Some(*v as f32)),
+        Some(OscType::Int(v)) => Ok(Some(*v as f32)),
+        Some(OscType::Long(v)) => Ok(Some(*v as f32)),
+        Some(other) => Err(format!("{} expects a number, got {:?}", msg.addr, other)),
+    }
+}
+
+fn string_arg(msg: &OscMessage) -> Result<Option<String>, String> {
+    match msg.args.first() {
+        None => Ok(None),
+        Some(OscType::String(s)) => Ok(Some(s.clone())),
+        Some(other) => Err(format!("{} expects a string, got {:?}", msg.addr, other)),
+    }
+}
+
+fn bool_arg(msg: &OscMessage) -> Result<Option<bool>, String> {
+    match msg.args.first() {
+        Some(OscType::Bool(b)) => Ok(Some(*b)),
+        _ => Ok(float_arg(msg)?.map(|v| v >= 0.5)),
+    }
+}
+
+pub fn parse(msg: &OscMessage) -> Result<OscCommand, String> {
+    let query = OscCommand::Query(msg.addr.clone());
+    let required = |value: Option<f32>| value.ok_or_else(|| format!("{} needs a value", msg.addr));
+    let command = match msg.addr.as_str() {
+        "/source" => string_arg(msg)?.map_or(query, OscCommand::Source),
+        "/source/remove" => OscCommand::RemoveSource(string_arg(msg)?.ok_or("/source/remove needs a source name")?),
+        "/sine/frequency" => float_arg(msg)?.map_or(query, |hz| OscCommand::Frequency(hz.clamp(20.0, 24000.0))),
+        "/sine/amplitude" => float_arg(msg)?.map_or(query, |v| OscCommand::Amplitude(v.clamp(0.0, 1.0))),
+        "/sine/waveform" => match string_arg(msg)? {
+            Some(name) => OscCommand::Waveform(
+                Waveform::ALL
+                    .into_iter()
+                    .find(|w| name_matches(w.name(), &name))
+                    .ok_or_else(|| format!("unknown waveform {}", name))?,
+            ),
+            None => query,
+        },
+        "/sine/pulse_width" => float_arg(msg)?.map_or(query, |v| OscCommand::PulseWidth(v.clamp(0.0, 1.0))),
+        "/analysis/mode" => match string_arg(msg)? {
+            Some(mode) if name_matches(&mode, "raw") => OscCommand::RawAnalysis(true),
+            Some(mode) if name_matches(&mode, "fft") => OscCommand::RawAnalysis(false),
+            Some(mode) => return Err(format!("unknown analysis mode {}, expected raw or fft", mode)),
+            None => query,
+        },
+        "/analysis/pre_effects" => bool_arg(msg)?.map_or(query, OscCommand::PreEffects),
+        "/mixer/master" => float_arg(msg)?.map_or(query, |v| OscCommand::MasterGain(v.clamp(0.0, 2.0))),
+        "/transport/play" => OscCommand::Play,
+        "/transport/pause" => OscCommand::Pause,
+        "/transport/stop" => OscCommand::Stop,
+        "/transport/seek" => OscCommand::Seek(required(float_arg(msg)?)?.max(0.0)),
+        "/playlist/next" => OscCommand::NextTrack,
+        "/playlist/previous" => OscCommand::PreviousTrack,
+        "/transport/paused" | "/playlist/track" | "/state" => query,
+        "/subscribe" => OscCommand::Subscribe,
+        "/unsubscribe" => OscCommand::Unsubscribe,
+        other => return Err(format!("unknown address {}", other)),
+    };
+    Ok(command)
+}
+
+pub fn message(addr: &str, arg: OscType) -> OscMessage {
+    OscMessage {
+        addr: addr.to_string(),
+        args: vec![arg],
+    }
+}
+
+// A registered source as OSC sees it
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscSource {
+    pub id: Uuid,
+    pub name: String,
+    // File and MIDI sources have nothing to play until a track loaded
+    pub available: bool,
+    pub in_mix: bool,
+}
+
+// Transport and playlist actions for the app to carry out after polling
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransportRequest {
+    Rewind,
+    Seek(f32),
+    NextTrack,
+    PreviousTrack,
+}
+
+// Everything the address space reads and writes. The app copies it out of
+// its resources before polling and writes back whatever changed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscState {
+    pub sources: Vec<OscSource>,
+    pub source: Uuid, // whose controls the side panel shows
+    pub frequency: f32,
+    pub amplitude: f32,
+    pub waveform: Waveform,
+    pub pulse_width: f32,
+    pub raw_analysis: bool,
+    pub pre_effects: bool,
+    pub master_gain: f32,
+    pub paused: bool,
+    pub track: String,
+    pub requests: Vec<TransportRequest>,
+}
+
+impl OscState {
+    // Sources are named as in the side panel
+    fn find(&mut self, name: &str) -> Result<&mut OscSource, String> {
+        self.sources
+            .iter_mut()
+            .find(|source| name_matches(&source.name, name))
+            .ok_or_else(|| format!("unknown source {}", name))
+    }
+
+    pub fn apply(&mut self, command: &OscCommand) -> Result<(), String> {
+        match command {
+            OscCommand::Source(name) => {
+                let source = self.find(name)?;
+                // Same rule as the mixer's menu
+                if !source.in_mix && !source.available {
+                    return Err(format!("{} has nothing loaded", source.name));
+                }
+                source.in_mix = true;
+                self.source = source.id;
+            }
+            OscCommand::RemoveSource(name) => self.find(name)?.in_mix = false,
+            OscCommand::Frequency(hz) => self.frequency = *hz,
+            OscCommand::Amplitude(amplitude) => self.amplitude = *amplitude,
+            OscCommand::Waveform(waveform) => self.waveform = *waveform,
+            OscCommand::PulseWidth(width) => self.pulse_width = *width,
+            OscCommand::RawAnalysis(raw) => self.raw_analysis = *raw,
+            OscCommand::PreEffects(pre) => self.pre_effects = *pre,
+            OscCommand::MasterGain(gain) => self.master_gain = *gain,
+            OscCommand::Play => self.paused = false,
+            OscCommand::Pause => self.paused = true,
+            OscCommand::Stop => {
+                self.paused = true;
+                self.requests.push(TransportRequest::Rewind);
+            }
+            OscCommand::Seek(seconds) => self.requests.push(TransportRequest::Seek(*seconds)),
+            OscCommand::NextTrack => self.requests.push(TransportRequest::NextTrack),
+            OscCommand::PreviousTrack => self.requests.push(TransportRequest::PreviousTrack),
+            // Handled by the server
+            OscCommand::Query(_) | OscCommand::Subscribe | OscCommand::Unsubscribe => {}
+        }
+        Ok(())
+    }
+
+    // Every value in the address space, as answered to /state
+    pub fn messages(&self) -> Vec<OscMessage> {
+        let source = self.sources.iter().find(|s| s.id == self.source).map_or("", |s| s.name.as_str());
+        vec![
+            message("/source", OscType::String(source.to_string())),
+            message("/sine/frequency", OscType::Float(self.frequency)),
+            message("/sine/amplitude", OscType::Float(self.amplitude)),
+            message("/sine/waveform", OscType::String(self.waveform.name().to_string())),
+            message("/sine/pulse_width", OscType::Float(self.pulse_width)),
+            message("/analysis/mode", OscType::String(String::from(if self.raw_analysis { "raw" } else { "fft" }))),
+            message("/analysis/pre_effects", OscType::Int(self.pre_effects as i32)),
+            message("/mixer/master", OscType::Float(self.master_gain)),
+            message("/transport/paused", OscType::Int(self.paused as i32)),
+            message("/playlist/track", OscType::String(self.track.clone())),
+        ]
+    }
+}
+
+struct Subscriber {
+    addr: SocketAddr,
+    last_seen: f64,
+}
+
+// Non-blocking UDP socket polled once a frame
+#[derive(Resource)]
+pub struct OscServer {
+    socket: UdpSocket,
+    subscribers: Vec<Subscriber>,
+    // State as last broadcast
+    broadcast: Vec<OscMessage>,
+    last_broadcast: f64,
+}
+
+impl OscServer {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        println!("[OSC] Listening on {}", socket.local_addr()?);
+        Ok(Self {
+            socket,
+            subscribers: Vec::new(),
+            broadcast: Vec::new(),
+            last_broadcast: 0.0,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    // Apply every message received since the last call to `state`, answer
+    // each one, then send subscribers whatever changed
+    pub fn poll(&mut self, state: &mut OscState, now: f64) {
+        for (sender, msg) in self.receive() {
+            debug!("OSC {} from {}: {:?}", msg.addr, sender, msg.args);
+            // Any message keeps a subscription alive
+            if let Some(subscriber) = self.subscribers.iter_mut().find(|s| s.addr == sender) {
+                subscriber.last_seen = now;
+            }
+            let command = parse(&msg).and_then(|command| {
+                match command {
+                    OscCommand::Subscribe => self.subscribe(sender, now)?,
+                    OscCommand::Unsubscribe => self.unsubscribe(sender),
+                    _ => state.apply(&command)?,
+                }
+                Ok(command)
+            });
+
+            let reply = match command {
+                Err(e) => {
+                    eprintln!("[OSC] {}", e);
+                    vec![message("/error", OscType::String(e))]
+                }
+                // A new subscriber starts from the full state
+                Ok(OscCommand::Subscribe) => state.messages(),
+                Ok(OscCommand::Query(addr)) if addr == "/state" => state.messages(),
+                Ok(OscCommand::Unsubscribe) => Vec::new(),
+                // Anything else is answered with its address's value, if it has one
+                Ok(_) => state.messages().into_iter().filter(|reply| reply.addr == msg.addr).collect(),
+            };
+            self.send(sender, reply);
+        }
+
+        self.broadcast(state.messages(), now);
+    }
+
+    // Every message received since the last call, bundles unpacked
+    fn receive(&mut self) -> Vec<(SocketAddr, OscMessage)> {
+        let mut messages = Vec::new();
+        let mut buf = vec![0u8; MAX_PACKET];
+        loop {
+            let (len, sender) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    // On some platforms an unreachable subscriber shows up
+                    // here; whatever is still queued is read next frame
+                    debug!("OSC receive error: {}", e);
+                    break;
+                }
+            };
+            match rosc::decoder::decode_udp(&buf[..len]) {
+                Ok((_, packet)) => unpack(packet, &mut |msg| messages.push((sender, msg))),
+                Err(e) => eprintln!("[OSC] Bad packet from {}: {:?}", sender, e),
+            }
+        }
+        messages
+    }
+
+    // One message as is, several as a bundle
+    fn send(&self, to: SocketAddr, mut messages: Vec<OscMessage>) {
+        let packet = match messages.len() {
+            0 => return,
+            1 => OscPacket::Message(messages.remove(0)),
+            _ => OscPacket::Bundle(OscBundle {
+                timetag: (0, 1).into(), // immediately
+                content: messages.into_iter().map(OscPacket::Message).collect(),
+            }),
+        };
+        match rosc::encoder::encode(&packet) {
+            Ok(bytes) => {
+                if let Err(e) = self.socket.send_to(&bytes, to) {
+                    debug!("OSC send to {} failed: {}", to, e);
+                }
+            }
+            Err(e) => eprintln!("[OSC] Could not encode reply: {:?}", e),
+        }
+    }
+
+    fn subscribe(&mut self, addr: SocketAddr, now: f64) -> Result<(), String> {
+        if self.subscribers.iter().any(|s| s.addr == addr) {
+            return Ok(());
+        }
+        if self.subscribers.len() >= MAX_SUBSCRIBERS {
+            return Err(format!("already {} subscribers", MAX_SUBSCRIBERS));
+        }
+        println!("[OSC] {} subscribed", addr);
+        self.subscribers.push(Subscriber { addr, last_seen: now });
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, addr: SocketAddr) {
+        self.subscribers.retain(|s| s.addr != addr);
+    }
+
+    // Send subscribers whatever changed since the last broadcast
+    fn broadcast(&mut self, state: Vec<OscMessage>, now: f64) {
+        if now - self.last_broadcast < BROADCAST_INTERVAL {
+            return;
+        }
+        self.last_broadcast = now;
+        self.subscribers.retain(|s| {
+            let alive = now - s.last_seen < SUBSCRIBER_TIMEOUT;
+            if !alive {
+                println!("[OSC] {} unsubscribed after {} s without a message", s.addr, SUBSCRIBER_TIMEOUT);
+            }
+            alive
+        });
+        let changed: Vec<OscMessage> = state.iter().filter(|msg| !self.broadcast.contains(msg)).cloned().collect();
+        self.broadcast = state;
+        for subscriber in &self.subscribers {
+            self.send(subscriber.addr, changed.clone());
+        }
+    }
+}
+
+fn unpack(packet: OscPacket, out: &mut impl FnMut(OscMessage)) {
+    match packet {
+        OscPacket::Message(msg) => out(msg),
+        OscPacket::Bundle(bundle) => {
+            for packet in bundle.content {
+                unpack(packet, out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn state() -> OscState {
+        OscState {
+            sources: vec![
+                OscSource { id: Uuid::from_u128(1), name: String::from("Oscillator"), available: true, in_mix: true },
+                OscSource { id: Uuid::from_u128(2), name: String::from("Wave File"), available: false, in_mix: false },
+            ],
+            source: Uuid::from_u128(1),
+            frequency: 440.0,
+            amplitude: 1.0,
+            waveform: Waveform::Sine,
+            pulse_width: 0.5,
+            raw_analysis: true,
+            pre_effects: false,
+            master_gain: 1.0,
+            paused: false,
+            track: String::from("test.wav"),
+            requests: Vec::new(),
+        }
+    }
+
+    fn server() -> OscServer {
+        OscServer::bind("127.0.0.1:0".parse().unwrap()).unwrap()
+    }
+
+    fn client() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        socket
+    }
+
+    fn send(client: &UdpSocket, server: &OscServer, addr: &str, args: Vec<OscType>) {
+        let packet = OscPacket::Message(OscMessage { addr: addr.to_string(), args });
+        client.send_to(&rosc::encoder::encode(&packet).unwrap(), server.local_addr().unwrap()).unwrap();
+    }
+
+    // The next packet for `client`, unpacked; polls until it arrives
+    fn reply(client: &UdpSocket, server: &mut OscServer, state: &mut OscState, now: f64) -> Vec<OscMessage> {
+        let mut buf = vec![0u8; MAX_PACKET];
+        for _ in 0..50 {
+            server.poll(state, now);
+            if let Ok(len) = client.recv(&mut buf) {
+                let (_, packet) = rosc::decoder::decode_udp(&buf[..len]).unwrap();
+                let mut messages = Vec::new();
+                unpack(packet, &mut |msg| messages.push(msg));
+                return messages;
+            }
+        }
+        panic!("no reply");
+    }
+
+    // Nothing arrives within a few polls
+    fn silent(client: &UdpSocket, server: &mut OscServer, state: &mut OscState, now: f64) -> bool {
+        let mut buf = vec![0u8; MAX_PACKET];
+        (0..5).all(|_| {
+            server.poll(state, now);
+            client.recv(&mut buf).is_err()
+        })
+    }
+
+    #[test]
+    fn change_is_applied_and_answered() {
+        let mut server = server();
+        let mut state = state();
+        let client = client();
+
+        send(&client, &server, "/sine/frequency", vec![OscType::Int(220)]);
+        let messages = reply(&client, &mut server, &mut state, 0.0);
+        assert_eq!(state.frequency, 220.0);
+        assert_eq!(messages, vec![message("/sine/frequency", OscType::Float(220.0))]);
+
+        // Out of range values are clamped, not rejected
+        send(&client, &server, "/sine/amplitude", vec![OscType::Double(3.0)]);
+        reply(&client, &mut server, &mut state, 0.0);
+        assert_eq!(state.amplitude, 1.0);
+
+        send(&client, &server, "/sine/waveform", vec![OscType::String(String::from("Pink Noise"))]);
+        reply(&client, &mut server, &mut state, 0.0);
+        assert_eq!(state.waveform, Waveform::PinkNoise);
+    }
+
+    #[test]
+    fn query_leaves_state_alone() {
+        let mut server = server();
+        let mut state = state();
+        let client = client();
+
+        send(&client, &server, "/playlist/track", vec![]);
+        let messages = reply(&client, &mut server, &mut state, 0.0);
+        assert_eq!(messages, vec![message("/playlist/track", OscType::String(String::from("test.wav")))]);
+        assert_eq!(state, self::state());
+    }
+
+    #[test]
+    fn state_is_a_bundle_of_every_value() {
+        let mut server = server();
+        let mut state = state();
+        let client = client();
+
+        send(&client, &server, "/state", vec![]);
+        let messages = reply(&client, &mut server, &mut state, 0.0);
+        assert_eq!(messages, state.messages());
+        assert!(messages.contains(&message("/source", OscType::String(String::from("Oscillator")))));
+        assert!(messages.contains(&message("/analysis/mode", OscType::String(String::from("raw")))));
+    }
+
+    #[test]
+    fn errors_are_answered_with_error() {
+        let mut server = server();
+        let mut state = state();
+        let client = client();
+
+        let error = |messages: Vec<OscMessage>| match &messages[..] {
+            [OscMessage { addr, args }] if addr == "/error" => match &args[..] {
+                [OscType::String(text)] => text.clone(),
+                _ => panic!("bad /error arguments {:?}", args),
+            },
+            _ => panic!("expected /error, got {:?}", messages),
+        };
+
+        send(&client, &server, "/nope", vec![]);
+        assert!(error(reply(&client, &mut server, &mut state, 0.0)).contains("/nope"));
+
+        send(&client, &server, "/sine/frequency", vec![OscType::String(String::from("high"))]);
+        assert!(error(reply(&client, &mut server, &mut state, 0.0)).contains("expects a number"));
+
+        send(&client, &server, "/source", vec![OscType::String(String::from("wave_file"))]);
+        assert!(error(reply(&client, &mut server, &mut state, 0.0)).contains("nothing loaded"));
+        assert!(!state.sources[1].in_mix);
+
+        assert_eq!(state, self::state());
+    }
+
+    #[test]
+    fn transport_becomes_requests() {
+        let mut server = server();
+        let mut state = state();
+        let client = client();
+
+        send(&client, &server, "/transport/stop", vec![]);
+        send(&client, &server, "/transport/seek", vec![OscType::Float(12.5)]);
+        send(&client, &server, "/playlist/next", vec![]);
+        // None of these has a value to answer with
+        assert!(silent(&client, &mut server, &mut state, 0.0));
+        assert!(state.paused);
+        assert_eq!(
+            state.requests,
+            vec![TransportRequest::Rewind, TransportRequest::Seek(12.5), TransportRequest::NextTrack]
+        );
+    }
+
+    #[test]
+    fn subscribers_only_get_changes() {
+        let mut server = server();
+        let mut state = state();
+        let subscriber = client();
+        let other = client();
+
+        // Subscribing is answered with everything
+        send(&subscriber, &server, "/subscribe", vec![]);
+        assert_eq!(reply(&subscriber, &mut server, &mut state, 1.0), state.messages());
+        // The first broadcast goes out with everything too; after that,
+        // nothing while nothing changes
+        reply(&subscriber, &mut server, &mut state, 1.0);
+        assert!(silent(&subscriber, &mut server, &mut state, 2.0));
+
+        // A change from another client reaches the subscriber on its own
+        send(&other, &server, "/mixer/master", vec![OscType::Float(0.5)]);
+        reply(&other, &mut server, &mut state, 3.0);
+        assert_eq!(
+            reply(&subscriber, &mut server, &mut state, 3.0),
+            vec![message("/mixer/master", OscType::Float(0.5))]
+        );
+        assert!(silent(&other, &mut server, &mut state, 4.0));
+
+        // So does one the app made
+        state.paused = true;
+        assert_eq!(
+            reply(&subscriber, &mut server, &mut state, 5.0),
+            vec![message("/transport/paused", OscType::Int(1))]
+        );
+
+        send(&subscriber, &server, "/unsubscribe", vec![]);
+        server.poll(&mut state, 6.0);
+        state.frequency = 100.0;
+        assert!(silent(&subscriber, &mut server, &mut state, 7.0));
+    }
+
+    #[test]
+    fn subscribers_are_capped_and_expire() {
+        let mut server = server();
+        let mut state = state();
+        let clients: Vec<UdpSocket> = (0..MAX_SUBSCRIBERS + 1).map(|_| client()).collect();
+
+        for client in &clients[..MAX_SUBSCRIBERS] {
+            send(client, &server, "/subscribe", vec![]);
+            reply(client, &mut server, &mut state, 0.0);
+        }
+        let late = &clients[MAX_SUBSCRIBERS];
+        send(late, &server, "/subscribe", vec![]);
+        let messages = reply(late, &mut server, &mut state, 0.0);
+        assert_eq!(messages[0].addr, "/error");
+
+        // Only the first client keeps talking; the rest lapse
+        send(&clients[0], &server, "/state", vec![]);
+        reply(&clients[0], &mut server, &mut state, SUBSCRIBER_TIMEOUT - 1.0);
+        server.poll(&mut state, SUBSCRIBER_TIMEOUT + 1.0);
+        assert_eq!(server.subscribers.len(), 1);
+        assert_eq!(server.subscribers[0].addr, clients[0].local_addr().unwrap());
+
+        send(late, &server, "/subscribe", vec![]);
+        assert_eq!(reply(late, &mut server, &mut state, SUBSCRIBER_TIMEOUT + 2.0), state.messages());
+    }
+}
@@ -0,0 +1,175 @@
+// Oscillator waveforms for the synth source
+//
+// Every waveform is built into the graph up front, but only the selected one
+// runs. Selecting another crossfades from the old waveform to the new one,
+// with just those two running for the length of the fade, instead of
+// rebuilding the graph and clicking.
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_fundsp::prelude::*;
+
+// How long a waveform switch takes to fade across, in seconds
+const CROSSFADE_TIME: f32 = 0.02;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Saw,
+    Square,
+    Triangle,
+    Pulse,
+    WhiteNoise,
+    PinkNoise,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 7] = [
+        Waveform::Sine,
+        Waveform::Saw,
+        Waveform::Square,
+        Waveform::Triangle,
+        Waveform::Pulse,
+        Waveform::WhiteNoise,
+        Waveform::PinkNoise,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Waveform::Sine => "Sine",
+            Waveform::Saw => "Saw",
+            Waveform::Square => "Square",
+            Waveform::Triangle => "Triangle",
+            Waveform::Pulse => "Pulse",
+            Waveform::WhiteNoise => "White Noise",
+            Waveform::PinkNoise => "Pink Noise",
+        }
+    }
+
+    fn index(&self) -> usize {
+        Waveform::ALL.iter().position(|w| w == self).unwrap()
+    }
+}
+
+// Shared controls for the running oscillator bank
+#[derive(Resource, Clone)]
+pub struct Oscillator {
+    waveform: Shared, // index into Waveform::ALL
+    pub pulse_width: Shared,
+}
+
+impl Oscillator {
+    pub fn new(waveform: Waveform, pulse_width: f32) -> Self {
+        Self {
+            waveform: shared(waveform.index() as f32),
+            pulse_width: shared(pulse_width),
+        }
+    }
+
+    pub fn select(&self, waveform: Waveform) {
+        self.waveform.set_value(waveform.index() as f32);
+    }
+
+    // One waveform, with the frequency taken from the node's input
+    fn unit(&self, waveform: Waveform) -> Box<dyn AudioUnit> {
+        match waveform {
+            Waveform::Sine => Box::new(sine()),
+            Waveform::Saw => Box::new(saw()),
+            Waveform::Square => Box::new(square()),
+            Waveform::Triangle => Box::new(triangle()),
+            Waveform::Pulse => Box::new((pass() | var(&self.pulse_width)) >> pulse()),
+            Waveform::WhiteNoise => Box::new(sink() | noise()),
+            Waveform::PinkNoise => Box::new(sink() | pink()),
+        }
+    }
+
+    // Mono signal of the selected waveform at `frequency`, scaled by `amplitude`
+    pub fn graph(&self, frequency: &Shared, amplitude: &Shared) -> An<impl AudioNode<Inputs = U0, Outputs = U1>> {
+        self.bank(frequency) * (var(amplitude) >> follow(CROSSFADE_TIME))
+    }
+
+    // The selected waveform at full level; the keyboard and MIDI synths run one per voice
+    pub fn bank(&self, frequency: &Shared) -> An<impl AudioNode<Inputs = U0, Outputs = U1>> {
+        var(frequency) >> self.bank_in()
+    }
+
+    // Same as `bank`, with the frequency taken from the node's input
+    pub fn bank_in(&self) -> An<impl AudioNode<Inputs = U1, Outputs = U1>> {
+        let current = self.waveform.value() as usize;
+        An(WaveBank {
+            units: Waveform::ALL.iter().map(|&waveform| self.unit(waveform)).collect(),
+            selected: self.waveform.clone(),
+            current,
+            previous: current,
+            fade: 1.0,
+            fade_step: 1.0 / (CROSSFADE_TIME * 44100.0),
+        })
+    }
+}
+
+// Plays the selected waveform of the bank. A switch fades from the old
+// waveform to the new one; one that comes mid-fade waits for it to finish,
+// so neither side ever jumps.
+#[derive(Clone)]
+struct WaveBank {
+    units: Vec<Box<dyn AudioUnit>>, // one per entry in Waveform::ALL
+    selected: Shared,
+    current: usize,
+    previous: usize,
+    fade: f32, // share of `current` in the output, the rest is `previous`
+    fade_step: f32,
+}
+
+impl AudioNode for WaveBank {
+    const ID: u64 = 0x7761_7665_6261_6e6b;
+    type Inputs = U1;
+    type Outputs = U1;
+
+    fn reset(&mut self) {
+        for unit in &mut self.units {
+            unit.reset();
+        }
+        self.previous = self.current;
+        self.fade = 1.0;
+    }
+
+    fn set_sample_rate(&mut self, sample_rate: f64) {
+        for unit in &mut self.units {
+            unit.set_sample_rate(sample_rate);
+        }
+        self.fade_step = 1.0 / (CROSSFADE_TIME * sample_rate as f32);
+    }
+
+    fn tick(&mut self, input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let selected = (self.selected.value() as usize).min(self.units.len() - 1);
+        if self.fade >= 1.0 && selected != self.current {
+            self.previous = self.current;
+            self.current = selected;
+            self.fade = 0.0;
+        }
+
+        let mut output = self.units[self.current].filter_mono(input[0]);
+        if self.fade < 1.0 {
+            let old = self.units[self.previous].filter_mono(input[0]);
+            output = output * self.fade + old * (1.0 - self.fade);
+            self.fade = (self.fade + self.fade_step).min(1.0);
+        }
+        [output].into()
+    }
+}
+
+// Side panel section for the oscillator's waveform and level
+pub fn oscillator_panel(ui: &mut egui::Ui, waveform: &mut Waveform, pulse_width: &mut f32, amplitude: &mut f32) {
+    egui::ComboBox::from_label("Waveform")
+        .selected_text(waveform.name())
+        .show_ui(ui, |ui| {
+            for w in Waveform::ALL {
+                ui.selectable_value(waveform, w, w.name());
+            }
+        });
+    if *waveform == Waveform::Pulse {
+        ui.add(egui::Slider::new(pulse_width, 0.01..=0.99).text("Pulse Width"));
+    }
+    ui.add(egui::Slider::new(amplitude, 0.0..=1.0).text("Amplitude"));
+}
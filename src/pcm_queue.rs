@@ -0,0 +1,348 @@
+// PCM queue between producers running outside the audio graph (capture
+// callbacks, decoder threads, per-frame systems) and the graph itself.
+//
+// Producers push stereo frames at the engine rate; `QueuePlayer` pops one
+// frame per sample. The queue is bounded: when the producer runs ahead the
+// oldest audio is dropped, and after an underrun playback waits for a small
+// jitter buffer to refill before resuming.
+//
+// It's a lock-free ring for one producer and the audio thread. Each slot is
+// an AtomicU64 holding both channels' bits, and the read and write indices
+// only ever grow. Dropping old audio, on overflow or `clear`, moves a
+// separate `discard` index forward, which the reader skips to; the producer
+// never touches the read index.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bevy_fundsp::prelude::*;
+
+struct Ring {
+    slots: Box<[AtomicU64]>,
+    // Next frame to read; only the audio thread moves it
+    head: AtomicUsize,
+    // Next frame to write; only the producer moves it
+    tail: AtomicUsize,
+    // Frames before this are dropped
+    discard: AtomicUsize,
+    prefill: usize,
+    primed: AtomicBool,
+    underruns: AtomicU64,
+    overflows: AtomicU64,
+}
+
+impl Ring {
+    // First frame still to be played
+    fn start(&self) -> usize {
+        self.head.load(Ordering::Acquire).max(self.discard.load(Ordering::Acquire))
+    }
+}
+
+// A stereo frame as one atomic word, for rings of frames
+pub fn pack([left, right]: [f32; 2]) -> u64 {
+    ((left.to_bits() as u64) << 32) | right.to_bits() as u64
+}
+
+pub fn unpack(bits: u64) -> [f32; 2] {
+    [f32::from_bits((bits >> 32) as u32), f32::from_bits(bits as u32)]
+}
+
+#[derive(Clone)]
+pub struct PcmQueue {
+    ring: Arc<Ring>,
+}
+
+impl PcmQueue {
+    // `capacity` and `prefill` are in frames
+    pub fn new(capacity: usize, prefill: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            ring: Arc::new(Ring {
+                slots: (0..capacity).map(|_| AtomicU64::new(0)).collect(),
+                head: AtomicUsize::new(0),
+                tail: AtomicUsize::new(0),
+                discard: AtomicUsize::new(0),
+                prefill: prefill.min(capacity),
+                primed: AtomicBool::new(false),
+                underruns: AtomicU64::new(0),
+                overflows: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    // Producer side; one producer at a time
+    pub fn push_frames(&self, frames: impl IntoIterator<Item = [f32; 2]>) {
+        let ring = &*self.ring;
+        let capacity = ring.slots.len();
+        let mut tail = ring.tail.load(Ordering::Relaxed);
+        let mut start = ring.start();
+        let mut dropped = 0;
+        for frame in frames {
+            if tail - start.min(tail) >= capacity {
+                // Full: drop the oldest frame to make room
+                start = tail + 1 - capacity;
+                ring.discard.fetch_max(start, Ordering::AcqRel);
+                dropped += 1;
+            }
+            ring.slots[tail % capacity].store(pack(frame), Ordering::Relaxed);
+            tail += 1;
+        }
+        ring.tail.store(tail, Ordering::Release);
+        if dropped > 0 {
+            ring.overflows.fetch_add(dropped, Ordering::Relaxed);
+        }
+    }
+
+    // Drops everything queued so far. Safe from any thread, but whatever a
+    // producer pushes meanwhile may survive it.
+    pub fn clear(&self) {
+        let ring = &*self.ring;
+        ring.discard.fetch_max(ring.tail.load(Ordering::Acquire), Ordering::AcqRel);
+        ring.primed.store(false, Ordering::Relaxed);
+    }
+
+    pub fn len(&self) -> usize {
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        tail.saturating_sub(self.ring.start())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+
+    pub fn prefill(&self) -> usize {
+        self.ring.prefill
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.ring.underruns.load(Ordering::Relaxed)
+    }
+
+    // Frames dropped because the queue was full
+    pub fn overflows(&self) -> u64 {
+        self.ring.overflows.load(Ordering::Relaxed)
+    }
+
+    // Audio thread side. Never blocks or allocates.
+    fn pop(&self) -> Option<[f32; 2]> {
+        let ring = &*self.ring;
+        let tail = ring.tail.load(Ordering::Acquire);
+        let head = ring.start();
+        let len = tail.saturating_sub(head);
+        if !ring.primed.load(Ordering::Relaxed) {
+            if len < ring.prefill.max(1) {
+                return None;
+            }
+            ring.primed.store(true, Ordering::Relaxed);
+        }
+        if len == 0 {
+            ring.primed.store(false, Ordering::Relaxed);
+            ring.underruns.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        let frame = unpack(ring.slots[head % ring.slots.len()].load(Ordering::Relaxed));
+        ring.head.store(head + 1, Ordering::Release);
+        Some(frame)
+    }
+}
+
+// Turns interleaved audio of any channel count and rate into stereo frames
+// at the engine rate. Linear interpolation keeps it cheap enough for
+// capture callbacks; files get the proper resampler at load time instead.
+pub struct PcmConverter {
+    channels: usize,
+    step: f64, // input frames per output frame
+    frac: f64,
+    prev: [f32; 2],
+    frames: Vec<[f32; 2]>,
+}
+
+impl PcmConverter {
+    pub fn new(channels: usize, input_rate: f32, output_rate: f32) -> Self {
+        Self {
+            channels: channels.max(1),
+            step: input_rate as f64 / output_rate as f64,
+            frac: 0.0,
+            prev: [0.0; 2],
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn push_interleaved(&mut self, samples: &[f32], queue: &PcmQueue) {
+        self.frames.clear();
+        for frame in samples.chunks_exact(self.channels) {
+            // Mono is duplicated; anything wider keeps its front pair
+            let cur = if self.channels == 1 { [frame[0], frame[0]] } else { [frame[0], frame[1]] };
+            while self.frac < 1.0 {
+                let t = self.frac as f32;
+                self.frames.push([
+                    self.prev[0] + (cur[0] - self.prev[0]) * t,
+                    self.prev[1] + (cur[1] - self.prev[1]) * t,
+                ]);
+                self.frac += self.step;
+            }
+            self.frac -= 1.0;
+            self.prev = cur;
+        }
+        queue.push_frames(self.frames.drain(..));
+    }
+}
+
+// Graph node playing whatever is in a PcmQueue, silence when it runs dry
+#[derive(Clone)]
+pub struct QueuePlayer {
+    queue: PcmQueue,
+}
+
+impl QueuePlayer {
+    pub fn new(queue: PcmQueue) -> An<Self> {
+        An(Self { queue })
+    }
+}
+
+impl AudioNode for QueuePlayer {
+    const ID: u64 = 0x7063_6d71;
+    type Inputs = U0;
+    type Outputs = U2;
+
+    fn tick(&mut self, _input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        match self.queue.pop() {
+            Some([left, right]) => [left, right].into(),
+            None => Frame::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::{AudioInput, SyntheticInput};
+
+    const RATE: f32 = 1000.0;
+
+    // Synthetic sine capture into `queue`, started at time zero
+    fn capture(queue: &PcmQueue) -> SyntheticInput {
+        let mut input = SyntheticInput::default();
+        input.start("Sine 440 Hz", queue.clone(), RATE).unwrap();
+        input.poll(0.0);
+        input
+    }
+
+    // What the synthetic sine produces, frame by frame
+    fn sine(frames: usize) -> Vec<[f32; 2]> {
+        let mut phase = 0.0f64;
+        (0..frames)
+            .map(|_| {
+                phase = (phase + 440.0 / RATE as f64).fract();
+                let value = (phase * std::f64::consts::TAU).sin() as f32 * 0.5;
+                [value, value]
+            })
+            .collect()
+    }
+
+    fn drain(queue: &PcmQueue) -> Vec<[f32; 2]> {
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    // Times are binary fractions so the synthetic input's frame counts are exact
+    #[test]
+    fn waits_for_prefill() {
+        let queue = PcmQueue::new(1000, 200);
+        let mut input = capture(&queue);
+
+        input.poll(0.125);
+        assert_eq!(queue.len(), 125);
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.len(), 125);
+
+        input.poll(0.25);
+        assert_eq!(drain(&queue), sine(250));
+        // Running dry after playing counts, waiting to start doesn't
+        assert_eq!(queue.underruns(), 1);
+    }
+
+    #[test]
+    fn refills_after_underrun() {
+        let queue = PcmQueue::new(1000, 100);
+        let mut input = capture(&queue);
+
+        input.poll(0.25);
+        assert_eq!(drain(&queue).len(), 250);
+        assert_eq!(queue.underruns(), 1);
+
+        // Less than the prefill isn't played...
+        input.poll(0.3125);
+        assert_eq!(queue.pop(), None);
+        assert_eq!(queue.underruns(), 1);
+        // ...until the jitter buffer is back
+        input.poll(0.375);
+        assert_eq!(drain(&queue), sine(375)[250..]);
+        assert_eq!(queue.underruns(), 2);
+    }
+
+    #[test]
+    fn overflow_drops_the_oldest() {
+        let queue = PcmQueue::new(1000, 0);
+        let mut input = capture(&queue);
+
+        input.poll(1.5);
+        assert_eq!(queue.len(), 1000);
+        assert_eq!(queue.overflows(), 500);
+        assert_eq!(drain(&queue), sine(1500)[500..]);
+
+        // And again once the indices have wrapped around the ring
+        input.poll(3.25);
+        assert_eq!(queue.overflows(), 1250);
+        assert_eq!(drain(&queue), sine(3250)[2250..]);
+    }
+
+    #[test]
+    fn clear_drops_everything_queued() {
+        let queue = PcmQueue::new(1000, 100);
+        let mut input = capture(&queue);
+
+        input.poll(0.5);
+        queue.clear();
+        assert!(queue.is_empty());
+        assert_eq!(queue.pop(), None);
+
+        input.poll(0.625);
+        assert_eq!(drain(&queue), sine(625)[500..]);
+    }
+
+    #[test]
+    fn frames_cross_threads_in_order() {
+        const FRAMES: usize = 200_000;
+        let queue = PcmQueue::new(4096, 0);
+        let producer = {
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                let mut next = 0;
+                while next < FRAMES {
+                    // Stay under the capacity so nothing is dropped
+                    if queue.len() > 3000 {
+                        std::thread::yield_now();
+                        continue;
+                    }
+                    let end = (next + 500).min(FRAMES);
+                    queue.push_frames((next..end).map(|i| [i as f32, -(i as f32)]));
+                    next = end;
+                }
+            })
+        };
+
+        let mut expected = 0;
+        while expected < FRAMES {
+            if let Some(frame) = queue.pop() {
+                assert_eq!(frame, [expected as f32, -(expected as f32)]);
+                expected += 1;
+            }
+        }
+        producer.join().unwrap();
+        assert_eq!(queue.overflows(), 0);
+    }
+}
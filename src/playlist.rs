@@ -0,0 +1,401 @@
+// Playlist: a queue of tracks with next/previous, shuffle and repeat
+//
+// The playlist only decides *which* track should play. Loading it into the
+// wave file source and respawning the player is done by the systems in
+// main.rs, so there is a single switching path for every source change.
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use rand::seq::SliceRandom;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RepeatMode {
+    Off,
+    One,
+    All,
+}
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub path: String,
+    pub name: String,
+}
+
+impl Track {
+    pub fn new(path: &str) -> Self {
+        let name = std::path::Path::new(path)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string());
+        Self { path: path.to_string(), name }
+    }
+}
+
+#[derive(Resource)]
+pub struct Playlist {
+    tracks: Vec<Track>,
+    // Play order as indices into `tracks`; identity unless shuffled
+    order: Vec<usize>,
+    // Track currently loaded in the wave file source
+    current: Option<usize>,
+    // Track that should be loaded next frame
+    requested: Option<usize>,
+    shuffle: bool,
+    pub repeat: RepeatMode,
+}
+
+impl Playlist {
+    pub fn new<S: AsRef<str>>(paths: &[S]) -> Self {
+        let tracks: Vec<Track> = paths.iter().map(|p| Track::new(p.as_ref())).collect();
+        Self {
+            order: (0..tracks.len()).collect(),
+            tracks,
+            current: None,
+            requested: None,
+            shuffle: false,
+            repeat: RepeatMode::All,
+        }
+    }
+
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn current_track(&self) -> Option<&Track> {
+        self.current.map(|i| &self.tracks[i])
+    }
+
+    pub fn shuffle(&self) -> bool {
+        self.shuffle
+    }
+
+    pub fn add(&mut self, path: &str) {
+        self.tracks.push(Track::new(path));
+        let index = self.tracks.len() - 1;
+        if self.shuffle {
+            // Slot the new track somewhere in the unplayed part of the order
+            let start = self.position().map_or(0, |p| p + 1);
+            let at = rand::Rng::gen_range(&mut rand::thread_rng(), start..=self.order.len());
+            self.order.insert(at, index);
+        } else {
+            self.order.push(index);
+        }
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index >= self.tracks.len() {
+            return;
+        }
+        self.tracks.remove(index);
+        self.order.retain(|&i| i != index);
+        for i in self.order.iter_mut().filter(|i| **i > index) {
+            *i -= 1;
+        }
+        let shift = |slot: Option<usize>| match slot {
+            Some(i) if i == index => None,
+            Some(i) if i > index => Some(i - 1),
+            other => other,
+        };
+        self.current = shift(self.current);
+        self.requested = shift(self.requested);
+    }
+
+    // Ask for a specific track to be loaded
+    pub fn play(&mut self, index: usize) {
+        if index < self.tracks.len() {
+            self.requested = Some(index);
+        }
+    }
+
+    pub fn next(&mut self) {
+        if let Some(index) = self.step(1, self.repeat != RepeatMode::Off) {
+            self.requested = Some(index);
+        }
+    }
+
+    pub fn previous(&mut self) {
+        if let Some(index) = self.step(-1, self.repeat != RepeatMode::Off) {
+            self.requested = Some(index);
+        }
+    }
+
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+        self.order = (0..self.tracks.len()).collect();
+        if shuffle {
+            self.order.shuffle(&mut rand::thread_rng());
+            // Keep the playing track first so "next" continues into the new order
+            if let Some(current) = self.current {
+                self.order.retain(|&i| i != current);
+                self.order.insert(0, current);
+            }
+        }
+    }
+
+    // Called when the current track has played to its end. Returns false
+    // when playback should stop.
+    pub fn track_finished(&mut self) -> bool {
+        let next = match self.repeat {
+            RepeatMode::One => self.current,
+            RepeatMode::All => self.step(1, true),
+            RepeatMode::Off => self.step(1, false),
+        };
+        self.requested = next;
+        next.is_some()
+    }
+
+    // Hand the requested track to the loader
+    pub fn take_request(&mut self) -> Option<usize> {
+        self.requested.take()
+    }
+
+    // The loader succeeded in switching to `index`
+    pub fn set_current(&mut self, index: usize) {
+        self.current = Some(index);
+    }
+
+    fn position(&self) -> Option<usize> {
+        let current = self.current?;
+        self.order.iter().position(|&i| i == current)
+    }
+
+    fn step(&self, delta: isize, wrap: bool) -> Option<usize> {
+        let len = self.order.len() as isize;
+        if len == 0 {
+            return None;
+        }
+        let target = match self.position() {
+            Some(pos) => pos as isize + delta,
+            None => 0,
+        };
+        let target = if wrap {
+            target.rem_euclid(len)
+        } else if (0..len).contains(&target) {
+            target
+        } else {
+            return None;
+        };
+        Some(self.order[target as usize])
+    }
+}
+
+// Side panel section for the playlist
+pub fn playlist_panel(ui: &mut egui::Ui, playlist: &mut Playlist, new_track_path: &mut String) {
+    ui.separator();
+    ui.label("Playlist");
+
+    ui.horizontal(|ui| {
+        if ui.button("Previous").clicked() {
+            playlist.previous();
+        }
+        if ui.button("Next").clicked() {
+            playlist.next();
+        }
+        let mut shuffle = playlist.shuffle();
+        if ui.checkbox(&mut shuffle, "Shuffle").changed() {
+            playlist.set_shuffle(shuffle);
+        }
+    });
+
+    ui.horizontal(|ui| {
+        ui.label("Repeat:");
+        ui.radio_value(&mut playlist.repeat, RepeatMode::Off, "Off");
+        ui.radio_value(&mut playlist.repeat, RepeatMode::One, "One");
+        ui.radio_value(&mut playlist.repeat, RepeatMode::All, "All");
+    });
+
+    let current = playlist.current();
+    let mut clicked = None;
+    let mut removed = None;
+    for (index, track) in playlist.tracks().iter().enumerate() {
+        ui.horizontal(|ui| {
+            if ui.selectable_label(current == Some(index), &track.name).clicked() {
+                clicked = Some(index);
+            }
+            if ui.small_button("x").clicked() {
+                removed = Some(index);
+            }
+        });
+    }
+    if let Some(index) = clicked {
+        playlist.play(index);
+    }
+    if let Some(index) = removed {
+        playlist.remove(index);
+    }
+
+    // The web build only has the embedded test file to play
+    #[cfg(not(target_arch = "wasm32"))]
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(new_track_path);
+        if ui.button("Add").clicked() && !new_track_path.is_empty() {
+            playlist.add(new_track_path);
+            new_track_path.clear();
+        }
+    });
+    #[cfg(target_arch = "wasm32")]
+    let _ = new_track_path;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(len: usize) -> Playlist {
+        let paths: Vec<String> = (0..len).map(|i| format!("music/track{}.wav", i)).collect();
+        Playlist::new(&paths)
+    }
+
+    // Load whatever was asked for, the way the loader does
+    fn load(playlist: &mut Playlist) -> Option<usize> {
+        let index = playlist.take_request()?;
+        playlist.set_current(index);
+        Some(index)
+    }
+
+    fn names(playlist: &Playlist) -> Vec<&str> {
+        playlist.tracks().iter().map(|t| t.name.as_str()).collect()
+    }
+
+    #[test]
+    fn track_names_are_file_names() {
+        assert_eq!(Track::new("music/a song.flac").name, "a song.flac");
+        assert_eq!(Track::new("embedded://track.wav").name, "track.wav");
+    }
+
+    #[test]
+    fn next_and_previous_wrap_with_repeat() {
+        let mut playlist = playlist(3);
+        playlist.play(0);
+        assert_eq!(load(&mut playlist), Some(0));
+
+        playlist.next();
+        assert_eq!(load(&mut playlist), Some(1));
+        playlist.next();
+        playlist.next();
+        // Only the last request counts; the current track hasn't moved
+        assert_eq!(load(&mut playlist), Some(2));
+        playlist.next();
+        assert_eq!(load(&mut playlist), Some(0));
+        playlist.previous();
+        assert_eq!(load(&mut playlist), Some(2));
+    }
+
+    #[test]
+    fn ends_without_repeat() {
+        let mut playlist = playlist(2);
+        playlist.repeat = RepeatMode::Off;
+        playlist.play(1);
+        load(&mut playlist);
+        playlist.next();
+        assert_eq!(load(&mut playlist), None);
+        assert!(!playlist.track_finished());
+
+        playlist.play(0);
+        load(&mut playlist);
+        playlist.previous();
+        assert_eq!(load(&mut playlist), None);
+        assert!(playlist.track_finished());
+        assert_eq!(load(&mut playlist), Some(1));
+    }
+
+    #[test]
+    fn repeat_one_replays_the_track() {
+        let mut playlist = playlist(3);
+        playlist.repeat = RepeatMode::One;
+        playlist.play(1);
+        load(&mut playlist);
+        assert!(playlist.track_finished());
+        assert_eq!(load(&mut playlist), Some(1));
+        // Skipping still moves on
+        playlist.next();
+        assert_eq!(load(&mut playlist), Some(2));
+    }
+
+    #[test]
+    fn shuffle_plays_every_track_once_from_the_current_one() {
+        let mut playlist = playlist(20);
+        playlist.play(7);
+        load(&mut playlist);
+        playlist.set_shuffle(true);
+        assert!(playlist.shuffle());
+
+        let mut played = vec![7];
+        for _ in 1..20 {
+            playlist.next();
+            played.push(load(&mut playlist).unwrap());
+        }
+        let mut sorted = played.clone();
+        sorted.sort();
+        assert_eq!(sorted, (0..20).collect::<Vec<_>>());
+        // Then the same order comes round again
+        playlist.next();
+        assert_eq!(load(&mut playlist), Some(7));
+
+        playlist.set_shuffle(false);
+        playlist.next();
+        assert_eq!(load(&mut playlist), Some(8));
+    }
+
+    #[test]
+    fn tracks_added_while_shuffled_get_played() {
+        let mut playlist = playlist(3);
+        playlist.play(0);
+        load(&mut playlist);
+        playlist.set_shuffle(true);
+        playlist.add("music/new.wav");
+
+        let mut played = Vec::new();
+        for _ in 0..3 {
+            playlist.next();
+            played.push(load(&mut playlist).unwrap());
+        }
+        assert!(played.contains(&3), "{:?}", played);
+    }
+
+    #[test]
+    fn remove_shifts_later_tracks() {
+        let mut playlist = playlist(4);
+        playlist.play(2);
+        load(&mut playlist);
+        playlist.play(3);
+
+        playlist.remove(0);
+        assert_eq!(names(&playlist), ["track1.wav", "track2.wav", "track3.wav"]);
+        assert_eq!(playlist.current_track().unwrap().name, "track2.wav");
+        assert_eq!(playlist.current(), Some(1));
+        // The pending request follows its track too
+        assert_eq!(playlist.take_request(), Some(2));
+
+        // Out of range is ignored
+        playlist.remove(10);
+        assert_eq!(playlist.tracks().len(), 3);
+    }
+
+    #[test]
+    fn remove_current_track() {
+        let mut playlist = playlist(3);
+        playlist.play(1);
+        load(&mut playlist);
+        playlist.remove(1);
+        assert_eq!(playlist.current(), None);
+        assert_eq!(names(&playlist), ["track0.wav", "track2.wav"]);
+
+        // With nothing current, next starts at the top
+        playlist.next();
+        assert_eq!(load(&mut playlist), Some(0));
+        playlist.next();
+        assert_eq!(load(&mut playlist), Some(1));
+
+        playlist.remove(1);
+        playlist.remove(0);
+        assert!(playlist.tracks().is_empty());
+        playlist.next();
+        assert_eq!(load(&mut playlist), None);
+        assert!(!playlist.track_finished());
+    }
+}
@@ -0,0 +1,558 @@
+// Internet radio: MP3 or Ogg Vorbis over HTTP (Icecast, Shoutcast v2)
+//
+// A connection thread fetches the stream, strips the ICY metadata blocks
+// out of it, decodes it with symphonia and pushes the audio into a
+// PcmQueue at the engine rate, which `NetworkStreamDsp` plays like the live
+// input. The queue gives a few seconds of buffering against network jitter.
+// When the connection drops or the stream ends the thread reconnects after
+// a pause that doubles with each failed attempt.
+//
+// Native only: the web build has no threads or sockets to do this with.
+
+use std::fmt;
+use std::io::{self, Read};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_fundsp::prelude::*;
+use symphonia::core::io::{MediaSourceStream, ReadOnlySource};
+
+use crate::decode::{self, AudioFormat, DecodeError};
+use crate::pcm_queue::{PcmQueue, QueuePlayer};
+use crate::resample::ResampleError;
+use crate::stream::{PacketSource, StreamResampler, SymphoniaSource};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// A stream that sends nothing for this long is treated as dropped
+const READ_TIMEOUT: Duration = Duration::from_secs(15);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// A connection that lasted this long resets the backoff
+const STABLE_TIME: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug)]
+pub enum RadioError {
+    Connect(String),
+    Unsupported(String),
+    Decode(DecodeError),
+    Resample(ResampleError),
+}
+
+impl fmt::Display for RadioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RadioError::Connect(msg) => write!(f, "could not connect: {}", msg),
+            RadioError::Unsupported(content_type) => write!(f, "unsupported stream type: {}", content_type),
+            RadioError::Decode(e) => write!(f, "stream decode error: {}", e),
+            RadioError::Resample(e) => write!(f, "sample rate conversion failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RadioError {}
+
+impl From<DecodeError> for RadioError {
+    fn from(e: DecodeError) -> Self {
+        RadioError::Decode(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnectionState {
+    #[default]
+    Idle,
+    Connecting,
+    Playing,
+    // Seconds until the next attempt
+    Waiting(u64),
+}
+
+// What the connection thread reports back to the UI
+#[derive(Clone, Debug, Default)]
+pub struct StreamStatus {
+    pub state: ConnectionState,
+    pub station: Option<String>,
+    pub title: Option<String>,
+    pub reconnects: u32,
+    pub error: Option<String>,
+}
+
+// Pulls the ICY metadata blocks out of a stream. With `icy-metaint: N` the
+// server sends N bytes of audio, then one length byte (in 16 byte units)
+// and that much metadata, then N bytes of audio again.
+struct IcyReader<R> {
+    inner: R,
+    metaint: Option<usize>,
+    until_meta: usize,
+    status: Arc<Mutex<StreamStatus>>,
+    stop: StopFlag,
+}
+
+impl<R: Read> IcyReader<R> {
+    fn read_metadata(&mut self) -> io::Result<()> {
+        let mut length = [0u8];
+        self.inner.read_exact(&mut length)?;
+        let mut metadata = vec![0u8; length[0] as usize * 16];
+        self.inner.read_exact(&mut metadata)?;
+        if let Some(title) = stream_title(&metadata) {
+            println!("[RADIO] Now playing: {}", title);
+            self.status.lock().unwrap().title = Some(title);
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for IcyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.stop.is_set() {
+            return Err(io::Error::other("stream stopped"));
+        }
+        let Some(metaint) = self.metaint else {
+            return self.inner.read(buf);
+        };
+        if self.until_meta == 0 {
+            self.read_metadata()?;
+            self.until_meta = metaint;
+        }
+        let len = buf.len().min(self.until_meta);
+        let read = self.inner.read(&mut buf[..len])?;
+        self.until_meta -= read;
+        Ok(read)
+    }
+}
+
+// `StreamTitle='Artist - Title';StreamUrl='...';`, padded with zeros
+fn stream_title(metadata: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(metadata);
+    let start = text.find("StreamTitle='")? + "StreamTitle='".len();
+    let rest = &text[start..];
+    // Titles may contain quotes, so the field ends at the quote before `;`
+    let end = rest.find("';").unwrap_or(rest.len());
+    let title = rest[..end].trim_end_matches('\0').trim_end_matches('\'').trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+// Container from the Content-Type, or the URL when the server doesn't say
+fn stream_format(content_type: &str, url: &str) -> Option<AudioFormat> {
+    match content_type {
+        "audio/mpeg" | "audio/mp3" | "audio/x-mpeg" => Some(AudioFormat::Mp3),
+        "application/ogg" | "audio/ogg" | "audio/vorbis" | "audio/x-vorbis+ogg" => Some(AudioFormat::OggVorbis),
+        _ => {
+            let path = url.split(['?', '#']).next().unwrap_or(url).to_ascii_lowercase();
+            if path.ends_with(".mp3") {
+                Some(AudioFormat::Mp3)
+            } else if path.ends_with(".ogg") || path.ends_with(".oga") {
+                Some(AudioFormat::OggVorbis)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+// One connection, from the request to the end of the stream
+fn play_connection(
+    agent: &ureq::Agent,
+    url: &str,
+    queue: &PcmQueue,
+    sample_rate: f32,
+    status: &Arc<Mutex<StreamStatus>>,
+    stop: &StopFlag,
+) -> Result<(), RadioError> {
+    let response = agent
+        .get(url)
+        .set("Icy-MetaData", "1")
+        .call()
+        .map_err(|e| RadioError::Connect(e.to_string()))?;
+
+    let metaint = response.header("icy-metaint").and_then(|v| v.trim().parse::<usize>().ok()).filter(|&n| n > 0);
+    let station = response.header("icy-name").map(str::to_string);
+    let content_type = response.content_type().to_string();
+    let format = stream_format(&content_type, url).ok_or_else(|| RadioError::Unsupported(content_type.clone()))?;
+    println!("[RADIO] Connected to {} ({}, metadata every {:?} bytes)", url, content_type, metaint);
+
+    let reader = IcyReader {
+        inner: response.into_reader(),
+        metaint,
+        until_meta: metaint.unwrap_or(0),
+        status: status.clone(),
+        stop: stop.clone(),
+    };
+    let stream = MediaSourceStream::new(Box::new(ReadOnlySource::new(reader)), Default::default());
+    let mut source = SymphoniaSource::new(format, stream)?;
+    let mut resampler = StreamResampler::new(source.sample_rate(), sample_rate as f64)?;
+    let channels = source.channels();
+    {
+        let mut status = status.lock().unwrap();
+        status.state = ConnectionState::Playing;
+        status.station = station;
+        status.error = None;
+    }
+
+    while let Some(samples) = source.next_block()? {
+        let frames = resampler.process(&decode::stereo_frames(&samples, channels)).map_err(RadioError::Resample)?;
+        // Servers send a burst up front; let the queue drain rather than drop it
+        while queue.len() + frames.len() > queue.capacity() {
+            if stop.is_set() {
+                return Ok(());
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        if !stop.push(queue, frames) {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+// The connection thread: connect, play, and reconnect until stopped
+fn run_stream(url: String, queue: PcmQueue, sample_rate: f32, status: Arc<Mutex<StreamStatus>>, stop: StopFlag) {
+    let agent = ureq::AgentBuilder::new()
+        .timeout_connect(CONNECT_TIMEOUT)
+        .timeout_read(READ_TIMEOUT)
+        .build();
+    let mut backoff = MIN_BACKOFF;
+
+    while !stop.is_set() {
+        status.lock().unwrap().state = ConnectionState::Connecting;
+        let connected_at = Instant::now();
+        let result = play_connection(&agent, &url, &queue, sample_rate, &status, &stop);
+        // Stopping breaks the connection, which isn't worth reporting
+        if stop.is_set() {
+            break;
+        }
+        match result {
+            Ok(()) => println!("[RADIO] Stream ended"),
+            Err(e) => {
+                eprintln!("[RADIO] {}", e);
+                status.lock().unwrap().error = Some(e.to_string());
+            }
+        }
+
+        if connected_at.elapsed() >= STABLE_TIME {
+            backoff = MIN_BACKOFF;
+        }
+        let retry_at = Instant::now() + backoff;
+        status.lock().unwrap().reconnects += 1;
+        while !stop.is_set() {
+            let remaining = retry_at.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            status.lock().unwrap().state = ConnectionState::Waiting(remaining.as_secs() + 1);
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+    status.lock().unwrap().state = ConnectionState::Idle;
+}
+
+// Tells a connection thread to stop. Its pushes to the queue happen under
+// the same lock, so once `set` returns the thread can't add any more audio
+// and the queue can be cleared for the next connection.
+#[derive(Clone, Default)]
+struct StopFlag(Arc<Mutex<bool>>);
+
+impl StopFlag {
+    fn set(&self) {
+        *self.0.lock().unwrap() = true;
+    }
+
+    fn is_set(&self) -> bool {
+        *self.0.lock().unwrap()
+    }
+
+    // False, without pushing, once stopped
+    fn push(&self, queue: &PcmQueue, frames: Vec<[f32; 2]>) -> bool {
+        let stopped = self.0.lock().unwrap();
+        if !*stopped {
+            queue.push_frames(frames);
+        }
+        !*stopped
+    }
+}
+
+// A running connection thread; dropping it stops the thread
+struct Connection {
+    url: String,
+    stop: StopFlag,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.stop.set();
+    }
+}
+
+// Graph for the network stream source
+pub struct NetworkStreamDsp {
+    pub queue: PcmQueue,
+}
+
+impl DspGraph for NetworkStreamDsp {
+    fn id(&self) -> Uuid {
+        crate::sources::NETWORK_STREAM
+    }
+
+    fn generate_graph(&self) -> Box<dyn AudioUnit> {
+        Box::new(QueuePlayer::new(self.queue.clone()))
+    }
+}
+
+#[derive(Resource)]
+pub struct NetworkStream {
+    // Stream to play while the source is in the mix; empty for none
+    pub url: String,
+    // URL being edited in the panel
+    draft: String,
+    running: Option<Connection>,
+    status: Arc<Mutex<StreamStatus>>,
+    pub queue: PcmQueue,
+}
+
+impl NetworkStream {
+    pub fn new(url: Option<String>, queue: PcmQueue) -> Self {
+        let url = url.unwrap_or_default();
+        Self {
+            draft: url.clone(),
+            url,
+            running: None,
+            status: Arc::new(Mutex::new(StreamStatus::default())),
+            queue,
+        }
+    }
+
+    // Connect or disconnect so it matches whether the source is in the mix
+    pub fn sync(&mut self, active: bool, sample_rate: f32) {
+        let wanted = (active && !self.url.is_empty()).then_some(self.url.as_str());
+        if wanted == self.running.as_ref().map(|c| c.url.as_str()) {
+            return;
+        }
+
+        if self.running.take().is_some() {
+            self.queue.clear();
+            println!("[RADIO] Disconnected");
+        }
+        let Some(url) = wanted.map(str::to_string) else {
+            return;
+        };
+
+        // A fresh status so the old thread's last words don't linger
+        self.status = Arc::new(Mutex::new(StreamStatus::default()));
+        let stop = StopFlag::default();
+        let (thread_url, queue, status, thread_stop) = (url.clone(), self.queue.clone(), self.status.clone(), stop.clone());
+        let spawned = std::thread::Builder::new()
+            .name(String::from("radio"))
+            .spawn(move || run_stream(thread_url, queue, sample_rate, status, thread_stop));
+        match spawned {
+            Ok(_) => self.running = Some(Connection { url, stop }),
+            Err(e) => {
+                eprintln!("[RADIO] Could not start the connection thread: {}", e);
+                self.status.lock().unwrap().error = Some(e.to_string());
+                // Don't retry every frame, wait for a new URL
+                self.url.clear();
+            }
+        }
+    }
+}
+
+// Side panel section for the network stream source
+pub fn network_stream_panel(ui: &mut egui::Ui, stream: &mut NetworkStream, sample_rate: f32) {
+    ui.separator();
+    ui.label("Network Stream");
+
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(&mut stream.draft);
+        if ui.button("Connect").clicked() {
+            stream.url = stream.draft.trim().to_string();
+        }
+        if ui.button("Disconnect").clicked() {
+            stream.url.clear();
+        }
+    });
+
+    let status = stream.status.lock().unwrap().clone();
+    let state = match status.state {
+        ConnectionState::Idle => String::from("Not connected"),
+        ConnectionState::Connecting => String::from("Connecting..."),
+        ConnectionState::Playing => String::from("Playing"),
+        ConnectionState::Waiting(seconds) => format!("Reconnecting in {} s", seconds),
+    };
+    ui.label(format!("Status: {} ({} reconnects)", state, status.reconnects));
+    if let Some(station) = &status.station {
+        ui.label(format!("Station: {}", station));
+    }
+    if let Some(title) = &status.title {
+        ui.label(format!("Now playing: {}", title));
+    }
+
+    ui.label(format!("Buffered: {:.1} s, {} underruns",
+                     stream.queue.len() as f32 / sample_rate, stream.queue.underruns()));
+
+    if let Some(error) = &status.error {
+        ui.colored_label(egui::Color32::RED, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    const METAINT: usize = 4096;
+
+    // MPEG-1 Layer III frames at 128 kb/s, 44.1 kHz stereo. All-zero side
+    // info decodes as silence.
+    fn silent_mp3(frames: usize) -> Vec<u8> {
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+        frame.repeat(frames)
+    }
+
+    // Audio with a metadata block after every `metaint` bytes: the title
+    // first, then empty blocks as servers send while it doesn't change
+    fn with_metadata(audio: &[u8], metaint: usize, title: &str) -> Vec<u8> {
+        let mut text = format!("StreamTitle='{}';StreamUrl='';", title).into_bytes();
+        text.resize(text.len().div_ceil(16) * 16, 0);
+        let mut out = Vec::new();
+        for (i, chunk) in audio.chunks(metaint).enumerate() {
+            out.extend_from_slice(chunk);
+            if chunk.len() < metaint {
+                break;
+            }
+            if i == 0 {
+                out.push((text.len() / 16) as u8);
+                out.extend_from_slice(&text);
+            } else {
+                out.push(0);
+            }
+        }
+        out
+    }
+
+    fn wait_for(status: &Arc<Mutex<StreamStatus>>, what: &str, done: impl Fn(&StreamStatus) -> bool) -> StreamStatus {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let status = status.lock().unwrap().clone();
+            if done(&status) {
+                return status;
+            }
+            assert!(Instant::now() < deadline, "timed out waiting for {}: {:?}", what, status);
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn icy_reader_strips_metadata() {
+        let audio: Vec<u8> = (0..3 * METAINT + 100).map(|i| (i % 251) as u8).collect();
+        let status = Arc::new(Mutex::new(StreamStatus::default()));
+        let mut reader = IcyReader {
+            inner: io::Cursor::new(with_metadata(&audio, METAINT, "Artist - Title")),
+            metaint: Some(METAINT),
+            until_meta: METAINT,
+            status: status.clone(),
+            stop: StopFlag::default(),
+        };
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).unwrap();
+        assert_eq!(out, audio);
+        assert_eq!(status.lock().unwrap().title.as_deref(), Some("Artist - Title"));
+    }
+
+    #[test]
+    fn stream_title_keeps_quotes() {
+        let title = |metadata: &[u8]| stream_title(metadata);
+        assert_eq!(
+            title(b"StreamTitle='Guns N' Roses - Sweet Child O' Mine';StreamUrl='http://example.com';\0\0").as_deref(),
+            Some("Guns N' Roses - Sweet Child O' Mine")
+        );
+        assert_eq!(title(b"StreamTitle='The \"Best\" Song';").as_deref(), Some("The \"Best\" Song"));
+        // Some servers leave off the final `;`
+        assert_eq!(title(b"StreamTitle='Rock 'n' Roll'\0\0\0\0").as_deref(), Some("Rock 'n' Roll"));
+        assert_eq!(title(b"StreamTitle='';StreamUrl='';"), None);
+        assert_eq!(title(b"StreamUrl='http://example.com';"), None);
+    }
+
+    #[test]
+    fn plays_and_reconnects_with_backoff() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/stream.mp3", listener.local_addr().unwrap());
+        let (finish, finished) = mpsc::channel::<()>();
+
+        // Serves "First", drops the next connection without answering, then
+        // serves "Second". Returns when each connection came in and ended.
+        let server = std::thread::spawn(move || {
+            let mut times = Vec::new();
+            for title in ["First", "", "Second"] {
+                let (mut socket, _) = listener.accept().unwrap();
+                let accepted = Instant::now();
+                let mut request = String::new();
+                let mut lines = BufReader::new(socket.try_clone().unwrap());
+                // Headers up to the blank line
+                while lines.read_line(&mut request).unwrap() > 2 {}
+                assert!(request.to_ascii_lowercase().contains("icy-metadata: 1"), "{}", request);
+                if title.is_empty() {
+                    times.push((accepted, Instant::now()));
+                    continue;
+                }
+
+                write!(
+                    socket,
+                    "HTTP/1.1 200 OK\r\nContent-Type: audio/mpeg\r\nicy-name: Test FM\r\nicy-metaint: {}\r\nTransfer-Encoding: chunked\r\n\r\n",
+                    METAINT
+                )
+                .unwrap();
+                for chunk in with_metadata(&silent_mp3(60), METAINT, title).chunks(1000) {
+                    write!(socket, "{:x}\r\n", chunk.len()).unwrap();
+                    socket.write_all(chunk).unwrap();
+                    socket.write_all(b"\r\n").unwrap();
+                }
+                socket.flush().unwrap();
+                // Hold the stream open until the test has looked at it
+                finished.recv().unwrap();
+                socket.write_all(b"0\r\n\r\n").unwrap();
+                drop(socket);
+                times.push((accepted, Instant::now()));
+            }
+            times
+        });
+
+        let queue = PcmQueue::new(441000, 0);
+        let status = Arc::new(Mutex::new(StreamStatus::default()));
+        let stop = StopFlag::default();
+        let client = {
+            let (queue, status, stop) = (queue.clone(), status.clone(), stop.clone());
+            std::thread::spawn(move || run_stream(url, queue, 44100.0, status, stop))
+        };
+
+        let first = wait_for(&status, "the first title", |s| s.state == ConnectionState::Playing && s.title.as_deref() == Some("First"));
+        assert_eq!(first.station.as_deref(), Some("Test FM"));
+        assert_eq!(first.reconnects, 0);
+        while queue.len() < 44100 {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        finish.send(()).unwrap();
+
+        wait_for(&status, "a reconnect", |s| matches!(s.state, ConnectionState::Waiting(_)));
+        let second = wait_for(&status, "the second title", |s| s.state == ConnectionState::Playing && s.title.as_deref() == Some("Second"));
+        assert_eq!(second.reconnects, 2);
+        assert_eq!(second.error, None);
+
+        stop.set();
+        finish.send(()).unwrap();
+        client.join().unwrap();
+        assert_eq!(status.lock().unwrap().state, ConnectionState::Idle);
+
+        // The pause before each attempt doubles
+        let times = server.join().unwrap();
+        let first_wait = times[1].0 - times[0].1;
+        let second_wait = times[2].0 - times[1].1;
+        assert!(first_wait >= MIN_BACKOFF - Duration::from_millis(100) && first_wait < MIN_BACKOFF * 2, "{:?}", first_wait);
+        assert!(second_wait >= MIN_BACKOFF * 2 - Duration::from_millis(100) && second_wait < MIN_BACKOFF * 4, "{:?}", second_wait);
+    }
+}
@@ -0,0 +1,117 @@
+// Load-time sample rate conversion
+//
+// The DSP graphs run at a single engine rate, so a track recorded at any
+// other rate is converted once when it is loaded instead of playing back at
+// the wrong pitch and speed.
+
+use fundsp::wave::Wave;
+use rubato::{FftFixedIn, Resampler};
+
+pub type ResampleError = Box<dyn std::error::Error + Send + Sync>;
+
+// Frames fed to the resampler per call
+const CHUNK_SIZE: usize = 1024;
+
+// Convert `wave` to `target_rate`, returning it untouched if it already matches
+pub fn resample_wave(wave: Wave, target_rate: f64) -> Result<Wave, ResampleError> {
+    let source_rate = wave.sample_rate();
+    if source_rate.round() == target_rate.round() {
+        return Ok(wave);
+    }
+
+    println!("[RESAMPLE] Converting {} Hz -> {} Hz", source_rate, target_rate);
+
+    let num_channels = wave.channels();
+    let len = wave.len();
+
+    // FFT based synchronous resampler: band-limited and exact for the fixed
+    // integer ratios audio files use
+    let mut resampler = FftFixedIn::<f32>::new(
+        source_rate.round() as usize,
+        target_rate.round() as usize,
+        CHUNK_SIZE,
+        2,
+        num_channels,
+    )?;
+
+    let input: Vec<Vec<f32>> = (0..num_channels)
+        .map(|channel| (0..len).map(|pos| wave.at(channel, pos)).collect())
+        .collect();
+
+    let expected_len = (len as f64 * target_rate / source_rate).round() as usize;
+    let delay = resampler.output_delay();
+    let mut output: Vec<Vec<f32>> = vec![Vec::with_capacity(expected_len + delay); num_channels];
+
+    let mut pos = 0;
+    while pos + CHUNK_SIZE <= len {
+        let chunk: Vec<&[f32]> = input.iter().map(|c| &c[pos..pos + CHUNK_SIZE]).collect();
+        append_channels(&mut output, resampler.process(&chunk, None)?);
+        pos += CHUNK_SIZE;
+    }
+
+    // Zero-pad the tail, then keep flushing until the filter delay is out
+    if pos < len {
+        let chunk: Vec<&[f32]> = input.iter().map(|c| &c[pos..]).collect();
+        append_channels(&mut output, resampler.process_partial(Some(&chunk), None)?);
+    }
+    while output[0].len() < expected_len + delay {
+        append_channels(&mut output, resampler.process_partial::<&[f32]>(None, None)?);
+    }
+
+    let mut resampled = Wave::with_capacity(num_channels, target_rate, expected_len);
+    resampled.resize(expected_len);
+    for (channel, samples) in output.iter().enumerate() {
+        // The first `delay` frames are the filter's latency, not signal
+        for (pos, &sample) in samples[delay..delay + expected_len].iter().enumerate() {
+            resampled.set(channel, pos, sample);
+        }
+    }
+
+    Ok(resampled)
+}
+
+fn append_channels(output: &mut [Vec<f32>], chunk: Vec<Vec<f32>>) {
+    for (out, samples) in output.iter_mut().zip(chunk) {
+        out.extend_from_slice(&samples);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustfft::num_complex::Complex;
+    use rustfft::FftPlanner;
+
+    fn sine(frequency: f64, sample_rate: f64, len: usize) -> Vec<f32> {
+        (0..len).map(|i| (std::f64::consts::TAU * frequency * i as f64 / sample_rate).sin() as f32).collect()
+    }
+
+    #[test]
+    fn sine_44k1_to_48k() {
+        let mut wave = Wave::new(0, 44100.0);
+        wave.push_channel(&sine(1000.0, 44100.0, 44100));
+        let resampled = resample_wave(wave, 48000.0).unwrap();
+        assert_eq!((resampled.sample_rate(), resampled.len()), (48000.0, 48000));
+
+        // A second at 48 kHz puts the bins 1 Hz apart
+        let mut spectrum: Vec<Complex<f32>> = (0..resampled.len()).map(|i| Complex { re: resampled.at(0, i), im: 0.0 }).collect();
+        FftPlanner::new().plan_fft_forward(spectrum.len()).process(&mut spectrum);
+        let peak = (0..spectrum.len() / 2).max_by(|&a, &b| spectrum[a].norm().total_cmp(&spectrum[b].norm())).unwrap();
+        assert_eq!(peak, 1000);
+
+        // The filter delay is taken out, so the sine is where it started
+        let expected = sine(1000.0, 48000.0, 48000);
+        for (i, &expected) in expected.iter().enumerate().take(47000).skip(1000) {
+            assert!((resampled.at(0, i) - expected).abs() < 1e-2, "frame {}: {} != {}", i, resampled.at(0, i), expected);
+        }
+    }
+
+    #[test]
+    fn matching_rate_is_untouched() {
+        let mut wave = Wave::new(0, 48000.0);
+        wave.push_channel(&[0.5; 100]);
+        let same = resample_wave(wave, 48000.0).unwrap();
+        assert_eq!(same.len(), 100);
+        assert_eq!(same.at(0, 99), 0.5);
+    }
+}
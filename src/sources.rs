@@ -0,0 +1,151 @@
+// Registry of playable DSP sources
+//
+// Every DspGraph is added through `register_source`, which records it with
+// its id, display name and metadata here. The mixer builds its channels
+// from the registered graphs, the UI lists whatever is registered and a
+// selection is just an id, so a new source is one `register_source` call.
+
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy_fundsp::prelude::*;
+
+use crate::calibration::TestSignal;
+
+// Ids of the sources there is exactly one of
+pub const OSCILLATOR: Uuid = Uuid::from_u128(0x1234567890abcdef1234567890abcdefu128);
+pub const WAVE_FILE: Uuid = Uuid::from_u128(0xfedcba0987654321fedcba0987654321u128);
+pub const LIVE_INPUT: Uuid = Uuid::from_u128(0x6c697665_696e_7075_7400_000000000001u128);
+pub const KEYBOARD: Uuid = Uuid::from_u128(0x6b657962_6f61_7264_0000_000000000001u128);
+pub const MIDI: Uuid = Uuid::from_u128(0x6d696469_706c_6179_0000_000000000001u128);
+// Native-only sources, like the modules that implement them
+#[cfg(not(target_arch = "wasm32"))]
+pub const NETWORK_STREAM: Uuid = Uuid::from_u128(0x72616469_6f5f_7374_7265_616d00000001u128);
+#[cfg(not(target_arch = "wasm32"))]
+pub const STDIN: Uuid = Uuid::from_u128(0x73746469_6e5f_7063_6d00_000000000001u128);
+// Files dropped onto the window count up from here
+#[cfg(not(target_arch = "wasm32"))]
+const DROPPED_FILES: u128 = 0x64726f70_7065_645f_6669_6c6500000000u128;
+
+// Id of the `index`th file dropped onto the window
+#[cfg(not(target_arch = "wasm32"))]
+pub fn dropped_file(index: usize) -> Uuid {
+    Uuid::from_u128(DROPPED_FILES + index as u128)
+}
+
+// What a source is, for the parts of the app that treat sources differently
+// (side panel controls, availability, the playlist)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceKind {
+    Oscillator,
+    WaveFile,
+    LiveInput,
+    Keyboard,
+    Midi,
+    #[cfg(not(target_arch = "wasm32"))]
+    NetworkStream,
+    #[cfg(not(target_arch = "wasm32"))]
+    Stdin,
+    TestSignal(TestSignal),
+    #[cfg(not(target_arch = "wasm32"))]
+    DroppedFile,
+}
+
+impl SourceKind {
+    // Sources that play a playlist track and follow the transport
+    pub fn plays_track(&self) -> bool {
+        matches!(self, SourceKind::WaveFile | SourceKind::Midi)
+    }
+}
+
+#[derive(Clone)]
+pub struct SourceInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub kind: SourceKind,
+    pub description: String,
+    pub graph: Arc<dyn DspGraph + Send + Sync>,
+}
+
+#[derive(Resource, Default)]
+pub struct SourceRegistry {
+    sources: Vec<SourceInfo>,
+}
+
+impl SourceRegistry {
+    // A source registered again replaces the earlier entry in its place
+    pub fn register(&mut self, info: SourceInfo) {
+        match self.sources.iter_mut().find(|s| s.id == info.id) {
+            Some(existing) => {
+                eprintln!("[SOURCE] {} ({}) registered twice, replacing {}", info.name, info.id, existing.name);
+                *existing = info;
+            }
+            None => self.sources.push(info),
+        }
+    }
+
+    pub fn get(&self, id: Uuid) -> Option<&SourceInfo> {
+        self.sources.iter().find(|s| s.id == id)
+    }
+
+    pub fn kind(&self, id: Uuid) -> Option<SourceKind> {
+        self.get(id).map(|s| s.kind)
+    }
+
+    pub fn name(&self, id: Uuid) -> &str {
+        self.get(id).map_or("unknown", |s| s.name.as_str())
+    }
+
+    // In registration order
+    pub fn iter(&self) -> impl Iterator<Item = &SourceInfo> {
+        self.sources.iter()
+    }
+}
+
+pub trait RegisterSourceExt {
+    fn register_source<G: DspGraph + Send + Sync + 'static>(
+        &mut self,
+        graph: G,
+        name: &str,
+        kind: SourceKind,
+        description: &str,
+    ) -> &mut Self;
+}
+
+// Sources can be registered at startup on the App, or later, like dropped
+// files, straight on the registry
+impl RegisterSourceExt for SourceRegistry {
+    fn register_source<G: DspGraph + Send + Sync + 'static>(
+        &mut self,
+        graph: G,
+        name: &str,
+        kind: SourceKind,
+        description: &str,
+    ) -> &mut Self {
+        let info = SourceInfo {
+            id: graph.id(),
+            name: name.to_string(),
+            kind,
+            description: description.to_string(),
+            graph: Arc::new(graph),
+        };
+        println!("[SOURCE] Registered {} ({})", info.name, info.id);
+        self.register(info);
+        self
+    }
+}
+
+impl RegisterSourceExt for App {
+    fn register_source<G: DspGraph + Send + Sync + 'static>(
+        &mut self,
+        graph: G,
+        name: &str,
+        kind: SourceKind,
+        description: &str,
+    ) -> &mut Self {
+        self.world_mut()
+            .get_resource_or_init::<SourceRegistry>()
+            .register_source(graph, name, kind, description);
+        self
+    }
+}
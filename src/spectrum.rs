@@ -0,0 +1,488 @@
+// Spectrum analysis of the captured audio
+//
+// `SpectrumAnalyzer` keeps its FFT plan, window and buffers between frames
+// and only rebuilds them when the `AnalysisSettings` change. Analysis frames
+// of `capture_length` samples start every `hop` samples of audio; each
+// update windows the frames that ended since the last one, zero-pads them
+// to the FFT size, transforms them and averages their power. It keeps the
+// half of the spectrum that carries information, DC to Nyquist, for other
+// systems to read.
+//
+// Magnitudes are scaled so a sine centred on a bin reads as its peak
+// amplitude whatever the window or FFT size: a full-scale sine is 1.0, or
+// 0 dB. Between bins the reading dips by the window's scalloping loss,
+// which the flat-top window all but removes at the cost of wide peaks.
+
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+// Floor of the dB spectrum, where silence ends up
+pub const MIN_DB: f32 = -120.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum WindowFunction {
+    // No window, the way the analysis used to run
+    Rectangular,
+    #[default]
+    Hann,
+    Hamming,
+    BlackmanHarris,
+    FlatTop,
+}
+
+impl WindowFunction {
+    pub const ALL: [WindowFunction; 5] = [
+        WindowFunction::Rectangular,
+        WindowFunction::Hann,
+        WindowFunction::Hamming,
+        WindowFunction::BlackmanHarris,
+        WindowFunction::FlatTop,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WindowFunction::Rectangular => "Rectangular",
+            WindowFunction::Hann => "Hann",
+            WindowFunction::Hamming => "Hamming",
+            WindowFunction::BlackmanHarris => "Blackman-Harris",
+            WindowFunction::FlatTop => "Flat-top",
+        }
+    }
+
+    // Every window here is a sum of cosines with alternating signs
+    fn coefficients(&self) -> &'static [f32] {
+        match self {
+            WindowFunction::Rectangular => &[1.0],
+            WindowFunction::Hann => &[0.5, 0.5],
+            WindowFunction::Hamming => &[0.54, 0.46],
+            WindowFunction::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+            WindowFunction::FlatTop => &[0.215_578_95, 0.416_631_58, 0.277_263_16, 0.083_578_95, 0.006_947_37],
+        }
+    }
+
+    // Periodic form, which is what an FFT frame wants
+    fn build(&self, len: usize) -> Vec<f32> {
+        let coefficients = self.coefficients();
+        (0..len)
+            .map(|n| {
+                let x = std::f32::consts::TAU * n as f32 / len as f32;
+                coefficients
+                    .iter()
+                    .enumerate()
+                    .map(|(k, a)| {
+                        let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                        sign * a * (k as f32 * x).cos()
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+}
+
+// Analysis parameters, all changeable while the audio plays
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnalysisSettings {
+    pub window: WindowFunction,
+    // Samples in each analysis frame, a power of two
+    pub capture_length: usize,
+    // Power of two, at least the capture length; the difference is zero-padding
+    pub fft_size: usize,
+    // Samples from one frame to the next
+    pub hop: usize,
+}
+
+impl Default for AnalysisSettings {
+    fn default() -> Self {
+        Self {
+            window: WindowFunction::default(),
+            capture_length: 1024,
+            fft_size: 1024,
+            hop: 512,
+        }
+    }
+}
+
+impl AnalysisSettings {
+    // The nearest settings that hang together
+    fn sanitized(self) -> Self {
+        let capture_length = self.capture_length.clamp(MIN_FFT_SIZE, MAX_FFT_SIZE).next_power_of_two();
+        let fft_size = self.fft_size.clamp(capture_length, MAX_FFT_SIZE).next_power_of_two();
+        Self {
+            window: self.window,
+            capture_length,
+            fft_size,
+            hop: self.hop.clamp(1, capture_length),
+        }
+    }
+
+    pub fn zero_padding(&self) -> usize {
+        self.fft_size - self.capture_length
+    }
+
+    // Hz between bins
+    pub fn bin_spacing(&self, sample_rate: f32) -> f32 {
+        sample_rate / self.fft_size as f32
+    }
+
+    // Hz two tones need between them to be told apart; zero-padding
+    // interpolates between bins but only a longer capture improves this
+    pub fn resolution(&self, sample_rate: f32) -> f32 {
+        sample_rate / self.capture_length as f32
+    }
+
+    // Seconds from a sound to the analysis reflecting it: half a capture
+    // to the middle of the frame, and up to a hop for the frame to end
+    pub fn latency(&self, sample_rate: f32) -> f32 {
+        (self.capture_length / 2 + self.hop) as f32 / sample_rate
+    }
+}
+
+pub const MIN_FFT_SIZE: usize = 64;
+pub const MAX_FFT_SIZE: usize = 16384;
+// Snoops hold the longest capture plus the audio of a slow frame
+pub const SNOOP_CAPACITY: usize = 2 * MAX_FFT_SIZE;
+// Frames analysed at most per update; with a tiny hop the oldest are skipped
+const MAX_HOPS_PER_UPDATE: usize = 32;
+
+#[derive(Resource)]
+pub struct SpectrumAnalyzer {
+    planner: FftPlanner<f32>,
+    fft: Arc<dyn Fft<f32>>,
+    settings: AnalysisSettings,
+    window: Vec<f32>,
+    // Turns |X| into a sine's peak amplitude
+    amplitude_scale: f32,
+    // Noise bandwidth of the window in bins, to turn band power into amplitude
+    noise_bandwidth: f32,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    // Summed |X|² of the frames in this update
+    power: Vec<f32>,
+    magnitudes: Vec<f32>,
+    db: Vec<f32>,
+    sample_rate: f32,
+    // Samples still to come before the next frame ends
+    until_hop: usize,
+}
+
+impl SpectrumAnalyzer {
+    pub fn new(settings: AnalysisSettings) -> Self {
+        let settings = settings.sanitized();
+        let mut planner = FftPlanner::new();
+        let fft = planner.plan_fft_forward(settings.fft_size);
+        let mut analyzer = Self {
+            planner,
+            fft,
+            settings,
+            window: Vec::new(),
+            amplitude_scale: 0.0,
+            noise_bandwidth: 1.0,
+            buffer: Vec::new(),
+            scratch: Vec::new(),
+            power: Vec::new(),
+            magnitudes: Vec::new(),
+            db: Vec::new(),
+            sample_rate: 0.0,
+            until_hop: 0,
+        };
+        analyzer.allocate();
+        analyzer
+    }
+
+    pub fn size(&self) -> usize {
+        self.buffer.len()
+    }
+
+    // Size the buffers for the FFT and build the window for the capture
+    fn allocate(&mut self) {
+        let size = self.settings.fft_size;
+        let bins = size / 2 + 1;
+        self.buffer = vec![Complex::default(); size];
+        self.scratch = vec![Complex::default(); self.fft.get_inplace_scratch_len()];
+        self.power = vec![0.0; bins];
+        self.magnitudes = vec![0.0; bins];
+        self.db = vec![MIN_DB; bins];
+
+        self.window = self.settings.window.build(self.settings.capture_length);
+        let sum: f32 = self.window.iter().sum();
+        let sum_squares: f32 = self.window.iter().map(|w| w * w).sum();
+        self.amplitude_scale = if sum > 0.0 { 2.0 / sum } else { 0.0 };
+        self.noise_bandwidth = if sum > 0.0 { size as f32 * sum_squares / (sum * sum) } else { 1.0 };
+    }
+
+    // Take new settings, replanning and reallocating only when they change.
+    // The spectrum starts over from silence.
+    pub fn configure(&mut self, settings: AnalysisSettings) {
+        let settings = settings.sanitized();
+        if settings == self.settings {
+            return;
+        }
+        if settings.fft_size != self.settings.fft_size {
+            // The planner keeps every size it has planned, so going back is cheap
+            self.fft = self.planner.plan_fft_forward(settings.fft_size);
+        }
+        println!("[SPECTRUM] {} window, capture {}, FFT {}, hop {}",
+                 settings.window.name(), settings.capture_length, settings.fft_size, settings.hop);
+        self.settings = settings;
+        self.until_hop = 0;
+        self.allocate();
+    }
+
+    // Window, transform and add one frame of `capture_length` samples to the power sum
+    fn transform(&mut self, frame: &[f32]) {
+        for (out, (sample, w)) in self.buffer.iter_mut().zip(frame.iter().zip(&self.window)) {
+            *out = Complex { re: sample * w, im: 0.0 };
+        }
+        self.buffer[frame.len()..].fill(Complex::default());
+        self.fft.process_with_scratch(&mut self.buffer, &mut self.scratch);
+        for (power, x) in self.power.iter_mut().zip(&self.buffer) {
+            *power += x.norm_sqr();
+        }
+    }
+
+    // Analyse the frames that ended among the `received` newest samples of
+    // `history` (oldest first, with at least a capture before them), one
+    // every hop, and average their power. Returns false if no frame ended,
+    // in which case the last spectrum stands.
+    pub fn process(&mut self, history: &[f32], received: usize, sample_rate: f32) -> bool {
+        let capture = self.settings.capture_length;
+        let first_new = history.len().saturating_sub(received);
+        let mut ends = Vec::new();
+        let mut offset = self.until_hop;
+        while offset <= received {
+            let end = first_new + offset;
+            if end >= capture {
+                ends.push(end);
+            }
+            offset += self.settings.hop;
+        }
+        self.until_hop = offset - received;
+        if ends.is_empty() {
+            return false;
+        }
+        self.sample_rate = sample_rate;
+
+        self.power.fill(0.0);
+        let skip = ends.len().saturating_sub(MAX_HOPS_PER_UPDATE);
+        for &end in &ends[skip..] {
+            self.transform(&history[end - capture..end]);
+        }
+        let frames = (ends.len() - skip) as f32;
+
+        let nyquist = self.magnitudes.len() - 1;
+        for (i, (magnitude, db)) in self.magnitudes.iter_mut().zip(self.db.iter_mut()).enumerate() {
+            // DC and Nyquist have no mirror image in the discarded half
+            let edge = if i == 0 || i == nyquist { 0.5 } else { 1.0 };
+            *magnitude = (self.power[i] / frames).sqrt() * edge * self.amplitude_scale;
+            *db = (20.0 * magnitude.log10()).max(MIN_DB);
+        }
+        true
+    }
+
+    // Peak amplitude per bin, DC to Nyquist
+    pub fn magnitudes(&self) -> &[f32] {
+        &self.magnitudes
+    }
+
+    // The magnitudes in dB relative to full scale, floored at MIN_DB
+    pub fn db(&self) -> &[f32] {
+        &self.db
+    }
+
+    // Bins a sine's power is spread over by the window
+    pub fn noise_bandwidth(&self) -> f32 {
+        self.noise_bandwidth
+    }
+
+    // Hz between bins
+    pub fn bin_width(&self) -> f32 {
+        self.sample_rate / self.size() as f32
+    }
+
+    pub fn bin_frequency(&self, bin: usize) -> f32 {
+        bin as f32 * self.bin_width()
+    }
+
+    // Amplitude of everything from `low` to `high` Hz: the bins' summed
+    // power over the window's noise bandwidth, so a lone sine in the band
+    // reads as its own amplitude however far it leaks into its neighbours
+    pub fn band_amplitude(&self, low: f32, high: f32) -> f32 {
+        let power: f32 = self
+            .magnitudes
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| (low..=high).contains(&self.bin_frequency(*i)))
+            .map(|(_, m)| m * m)
+            .sum();
+        (power / self.noise_bandwidth).sqrt()
+    }
+}
+
+fn sizes(from: usize) -> impl Iterator<Item = usize> {
+    std::iter::successors(Some(from), |size| Some(size * 2)).take_while(|size| *size <= MAX_FFT_SIZE)
+}
+
+// Analysis settings, what they amount to, and the loudest bin. Raw analysis
+// only uses the capture length; the rest is greyed out unless `fft_mode`.
+pub fn spectrum_panel(ui: &mut egui::Ui, settings: &mut AnalysisSettings, analyzer: &SpectrumAnalyzer, sample_rate: f32, fft_mode: bool) {
+    egui::ComboBox::from_label("Capture")
+        .selected_text(settings.capture_length.to_string())
+        .show_ui(ui, |ui| {
+            for size in sizes(MIN_FFT_SIZE) {
+                ui.selectable_value(&mut settings.capture_length, size, size.to_string());
+            }
+        });
+    // A longer capture takes the FFT and hop with it
+    settings.fft_size = settings.fft_size.max(settings.capture_length);
+    if settings.hop > settings.capture_length {
+        settings.hop = settings.capture_length / 2;
+    }
+    ui.add_enabled_ui(fft_mode, |ui| {
+        egui::ComboBox::from_label("Window")
+            .selected_text(settings.window.name())
+            .show_ui(ui, |ui| {
+                for w in WindowFunction::ALL {
+                    ui.selectable_value(&mut settings.window, w, w.name());
+                }
+            });
+        egui::ComboBox::from_label("FFT size")
+            .selected_text(settings.fft_size.to_string())
+            .show_ui(ui, |ui| {
+                for size in sizes(settings.capture_length) {
+                    let padding = size / settings.capture_length;
+                    let label = if padding > 1 { format!("{} ({}x zero-padded)", size, padding) } else { size.to_string() };
+                    ui.selectable_value(&mut settings.fft_size, size, label);
+                }
+            });
+        egui::ComboBox::from_label("Hop")
+            .selected_text(settings.hop.to_string())
+            .show_ui(ui, |ui| {
+                for overlap in [1, 2, 4, 8] {
+                    let hop = settings.capture_length / overlap;
+                    let label = format!("{} ({}% overlap)", hop, 100 - 100 / overlap);
+                    ui.selectable_value(&mut settings.hop, hop, label);
+                }
+            });
+
+        ui.label(format!("Bins {:.1} Hz apart, {:.1} Hz resolution",
+                         settings.bin_spacing(sample_rate), settings.resolution(sample_rate)));
+        ui.label(format!("Latency {:.0} ms, {:.0} frames/s",
+                         settings.latency(sample_rate) * 1000.0, sample_rate / settings.hop as f32));
+        if settings.zero_padding() > 0 {
+            ui.label(format!("{} samples of zero-padding", settings.zero_padding()));
+        }
+
+        // DC is left out, it's only ever offset
+        let peak = analyzer
+            .magnitudes()
+            .iter()
+            .enumerate()
+            .skip(1)
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(bin, _)| bin);
+        match peak {
+            Some(bin) if analyzer.db()[bin] > MIN_DB => {
+                ui.label(format!("Peak: {:.1} Hz, {:.1} dBFS", analyzer.bin_frequency(bin), analyzer.db()[bin]));
+            }
+            _ => {
+                ui.label("Peak: silence");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: f32 = 48000.0;
+
+    fn settings(window: WindowFunction, capture_length: usize, fft_size: usize) -> AnalysisSettings {
+        AnalysisSettings { window, capture_length, fft_size, hop: capture_length }
+    }
+
+    // A capture of a full-scale sine at `frequency`
+    fn sine(frequency: f32, len: usize) -> Vec<f32> {
+        (0..len).map(|i| (std::f32::consts::TAU * frequency * i as f32 / RATE).sin()).collect()
+    }
+
+    #[test]
+    fn bin_centred_sine_reads_full_scale() {
+        for window in WindowFunction::ALL {
+            // Zero-padding must not change the reading either
+            for fft_size in [1024, 4096] {
+                let mut analyzer = SpectrumAnalyzer::new(settings(window, 1024, fft_size));
+                let bin = 64 * fft_size / 1024;
+                let frequency = 64.0 * RATE / 1024.0;
+                let samples = sine(frequency, 1024);
+                assert!(analyzer.process(&samples, samples.len(), RATE));
+                assert_eq!(analyzer.bin_frequency(bin), frequency);
+
+                let magnitude = analyzer.magnitudes()[bin];
+                assert!((magnitude - 1.0).abs() < 1e-3, "{}, FFT {}: {}", window.name(), fft_size, magnitude);
+                assert!(analyzer.db()[bin].abs() < 0.01, "{}, FFT {}: {} dB", window.name(), fft_size, analyzer.db()[bin]);
+                // However far it leaks, the band around it reads the same
+                let band = analyzer.band_amplitude(frequency - 1000.0, frequency + 1000.0);
+                assert!((band - 1.0).abs() < 0.01, "{}, FFT {}: band {}", window.name(), fft_size, band);
+            }
+        }
+    }
+
+    #[test]
+    fn silence_reads_the_floor() {
+        let mut analyzer = SpectrumAnalyzer::new(AnalysisSettings::default());
+        assert!(analyzer.process(&[0.0; 1024], 1024, RATE));
+        assert!(analyzer.db().iter().all(|&db| db == MIN_DB));
+    }
+
+    #[test]
+    fn sanitized_clamps_sizes_and_hop() {
+        let sanitized = |capture_length, fft_size, hop| {
+            let s = AnalysisSettings { window: WindowFunction::Hann, capture_length, fft_size, hop }.sanitized();
+            (s.capture_length, s.fft_size, s.hop)
+        };
+        assert_eq!(sanitized(1024, 2048, 256), (1024, 2048, 256));
+        // Sizes round up to a power of two within MIN_FFT_SIZE..=MAX_FFT_SIZE
+        assert_eq!(sanitized(1000, 1000, 256), (1024, 1024, 256));
+        assert_eq!(sanitized(8, 8, 4), (MIN_FFT_SIZE, MIN_FFT_SIZE, 4));
+        assert_eq!(sanitized(100_000, 100_000, 512), (MAX_FFT_SIZE, MAX_FFT_SIZE, 512));
+        // The FFT holds at least a capture
+        assert_eq!(sanitized(4096, 1024, 512), (4096, 4096, 512));
+        // At least one sample and at most a capture between frames
+        assert_eq!(sanitized(1024, 1024, 0), (1024, 1024, 1));
+        assert_eq!(sanitized(1024, 1024, 5000), (1024, 1024, 1024));
+    }
+
+    #[test]
+    fn new_settings_rebuild_plan_and_window() {
+        let mut analyzer = SpectrumAnalyzer::new(settings(WindowFunction::Rectangular, 1024, 1024));
+        assert_eq!((analyzer.size(), analyzer.magnitudes().len()), (1024, 513));
+        assert_eq!(analyzer.noise_bandwidth(), 1.0);
+
+        let samples = sine(RATE / 16.0, 4096);
+        analyzer.process(&samples, samples.len(), RATE);
+        // The same settings keep the spectrum
+        analyzer.configure(settings(WindowFunction::Rectangular, 1024, 1024));
+        assert!((analyzer.magnitudes()[64] - 1.0).abs() < 1e-3);
+
+        // A bigger FFT, a longer capture and a Hann window
+        analyzer.configure(settings(WindowFunction::Hann, 2048, 4096));
+        assert_eq!((analyzer.size(), analyzer.magnitudes().len()), (4096, 2049));
+        assert!((analyzer.noise_bandwidth() - 3.0).abs() < 1e-3, "{}", analyzer.noise_bandwidth());
+        // The spectrum starts over
+        assert!(analyzer.magnitudes().iter().all(|&m| m == 0.0));
+
+        // The new plan analyses the new size: the sine lands on bin 256
+        assert!(analyzer.process(&samples, samples.len(), RATE));
+        assert!((analyzer.magnitudes()[256] - 1.0).abs() < 1e-3, "{}", analyzer.magnitudes()[256]);
+
+        // Back to a size planned before
+        analyzer.configure(settings(WindowFunction::Hann, 1024, 1024));
+        assert!(analyzer.process(&samples, samples.len(), RATE));
+        assert!((analyzer.magnitudes()[64] - 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,379 @@
+// Raw PCM piped into stdin
+//
+//   ffmpeg -i song.flac -f f32le -ac 2 -ar 48000 - | bevy_visualizer --stdin --stdin-rate 48000
+//
+// A reader thread takes interleaved little-endian s16 or f32 samples, at a
+// channel count and rate given on the command line, converts them to stereo
+// at the engine rate and pushes them into a PcmQueue that `StdinDsp` plays
+// like the live input. The queue's prefill is a small jitter buffer for
+// producers that write in bursts. A producer running faster than real time
+// (ffmpeg without -re) is held back by not reading while the queue is full.
+//
+// The last few milliseconds are held back so that when stdin ends they can
+// be faded out rather than cut off with a click; the source plays silence
+// from then on.
+//
+// Native only: the web build has no stdin.
+
+use std::io::{self, IsTerminal, Read};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_fundsp::prelude::*;
+
+use crate::decode;
+use crate::pcm_queue::{PcmQueue, QueuePlayer};
+use crate::stream::StreamResampler;
+
+// Bytes read from stdin at a time
+const READ_SIZE: usize = 16384;
+// Fade to silence at the end of stdin
+const FADE_SECONDS: f32 = 0.02;
+// How often a full queue is checked for room
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SampleFormat {
+    S16Le,
+    F32Le,
+}
+
+impl SampleFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SampleFormat::S16Le => "s16le",
+            SampleFormat::F32Le => "f32le",
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        match self {
+            SampleFormat::S16Le => 2,
+            SampleFormat::F32Le => 4,
+        }
+    }
+
+    fn sample(&self, bytes: &[u8]) -> f32 {
+        match self {
+            SampleFormat::S16Le => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            SampleFormat::F32Le => {
+                let sample = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                // Garbage on the pipe shouldn't put NaNs into the mix
+                if sample.is_finite() { sample } else { 0.0 }
+            }
+        }
+    }
+}
+
+// Layout of the PCM on stdin
+#[derive(Clone, Copy, Debug)]
+pub struct PcmFormat {
+    pub sample_format: SampleFormat,
+    pub channels: usize,
+    pub sample_rate: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StdinState {
+    #[default]
+    Waiting,
+    Playing,
+    Ended,
+}
+
+// What the reader thread reports back to the UI
+#[derive(Clone, Debug, Default)]
+struct StdinStatus {
+    state: StdinState,
+    // Input frames read so far
+    frames: u64,
+    error: Option<String>,
+}
+
+// Push frames as the queue makes room for them, a quarter of it at a time
+fn push_paced(queue: &PcmQueue, frames: &[[f32; 2]]) {
+    let capacity = queue.capacity();
+    for chunk in frames.chunks((capacity / 4).max(1)) {
+        while queue.len() + chunk.len() > capacity {
+            std::thread::sleep(POLL_INTERVAL);
+        }
+        queue.push_frames(chunk.iter().copied());
+    }
+}
+
+// Convert interleaved PCM from `input` until it ends, handing stereo frames
+// at the engine rate to `output` as they are ready. The last FADE_SECONDS
+// are held back and faded out once the input ends. Errors end the input
+// early and are recorded in `status`.
+fn read_pcm(mut input: impl Read, format: PcmFormat, sample_rate: f32, status: &Mutex<StdinStatus>, mut output: impl FnMut(&[[f32; 2]])) {
+    let mut resampler = match StreamResampler::new(format.sample_rate as f64, sample_rate as f64) {
+        Ok(resampler) => resampler,
+        Err(e) => {
+            eprintln!("[STDIN] {}", e);
+            status.lock().unwrap().error = Some(e.to_string());
+            return;
+        }
+    };
+    let sample_bytes = format.sample_format.bytes();
+    let frame_bytes = sample_bytes * format.channels;
+    let fade_frames = (FADE_SECONDS * sample_rate) as usize;
+
+    let mut buf = vec![0u8; READ_SIZE];
+    // Bytes of a frame split across reads
+    let mut partial: Vec<u8> = Vec::with_capacity(frame_bytes);
+    // Converted audio not yet handed on, at least the fade's worth
+    let mut held: Vec<[f32; 2]> = Vec::new();
+
+    loop {
+        let read = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                eprintln!("[STDIN] Read error: {}", e);
+                status.lock().unwrap().error = Some(e.to_string());
+                break;
+            }
+        };
+        partial.extend_from_slice(&buf[..read]);
+        let whole = partial.len() - partial.len() % frame_bytes;
+        let samples: Vec<f32> = partial[..whole]
+            .chunks_exact(sample_bytes)
+            .map(|bytes| format.sample_format.sample(bytes))
+            .collect();
+        partial.drain(..whole);
+
+        {
+            let mut status = status.lock().unwrap();
+            if status.state == StdinState::Waiting {
+                println!("[STDIN] Receiving audio");
+                status.state = StdinState::Playing;
+            }
+            status.frames += (whole / frame_bytes) as u64;
+        }
+
+        match resampler.process(&decode::stereo_frames(&samples, format.channels)) {
+            Ok(frames) => held.extend(frames),
+            Err(e) => {
+                eprintln!("[STDIN] Sample rate conversion failed: {}", e);
+                status.lock().unwrap().error = Some(e.to_string());
+                break;
+            }
+        }
+        let ready = held.len().saturating_sub(fade_frames);
+        output(&held[..ready]);
+        held.drain(..ready);
+    }
+
+    if !partial.is_empty() {
+        debug!("Dropped {} bytes of a partial frame at the end of stdin", partial.len());
+    }
+    match resampler.flush() {
+        Ok(frames) => held.extend(frames),
+        Err(e) => {
+            eprintln!("[STDIN] Sample rate conversion failed: {}", e);
+            status.lock().unwrap().error = Some(e.to_string());
+        }
+    }
+    let fade = held.len().min(fade_frames);
+    let start = held.len() - fade;
+    for (i, frame) in held[start..].iter_mut().enumerate() {
+        let gain = 1.0 - (i + 1) as f32 / fade as f32;
+        frame[0] *= gain;
+        frame[1] *= gain;
+    }
+    output(&held);
+}
+
+// The reader thread: convert and queue until stdin ends, then fade out
+fn run_reader(format: PcmFormat, queue: PcmQueue, sample_rate: f32, status: Arc<Mutex<StdinStatus>>) {
+    read_pcm(io::stdin().lock(), format, sample_rate, &status, |frames| push_paced(&queue, frames));
+    // Enough silence behind it to get past the prefill, so input shorter
+    // than the jitter buffer still plays
+    push_paced(&queue, &vec![[0.0; 2]; queue.prefill()]);
+
+    let mut status = status.lock().unwrap();
+    status.state = StdinState::Ended;
+    println!("[STDIN] End of input after {:.1} s", status.frames as f64 / format.sample_rate as f64);
+}
+
+// Graph for the stdin source
+pub struct StdinDsp {
+    pub queue: PcmQueue,
+}
+
+impl DspGraph for StdinDsp {
+    fn id(&self) -> Uuid {
+        crate::sources::STDIN
+    }
+
+    fn generate_graph(&self) -> Box<dyn AudioUnit> {
+        Box::new(QueuePlayer::new(self.queue.clone()))
+    }
+}
+
+#[derive(Resource)]
+pub struct StdinPcm {
+    pub format: PcmFormat,
+    status: Arc<Mutex<StdinStatus>>,
+    pub queue: PcmQueue,
+}
+
+impl StdinPcm {
+    // Stdin can only be read once, so the reader starts straight away
+    // rather than when the source joins the mix. Until the queue is played
+    // it fills and then stops reading.
+    pub fn start(format: PcmFormat, queue: PcmQueue, sample_rate: f32) -> Self {
+        let status = Arc::new(Mutex::new(StdinStatus::default()));
+        let stdin = Self { format, status: status.clone(), queue: queue.clone() };
+
+        if io::stdin().is_terminal() {
+            eprintln!("[STDIN] stdin is a terminal; pipe raw PCM into it");
+            let mut status = status.lock().unwrap();
+            status.error = Some(String::from("stdin is a terminal, nothing is piped in"));
+            status.state = StdinState::Ended;
+            return stdin;
+        }
+
+        println!("[STDIN] Reading {} at {} Hz, {} channels",
+                 format.sample_format.name(), format.sample_rate, format.channels);
+        let spawned = std::thread::Builder::new()
+            .name(String::from("stdin"))
+            .spawn(move || run_reader(format, queue, sample_rate, status));
+        if let Err(e) = spawned {
+            eprintln!("[STDIN] Could not start the reader thread: {}", e);
+            let mut status = stdin.status.lock().unwrap();
+            status.error = Some(e.to_string());
+            status.state = StdinState::Ended;
+        }
+        stdin
+    }
+}
+
+// Side panel section for the stdin source
+pub fn stdin_panel(ui: &mut egui::Ui, stdin: &StdinPcm, sample_rate: f32) {
+    ui.separator();
+    ui.label("Stdin");
+
+    let format = stdin.format;
+    ui.label(format!("Format: {}, {} Hz, {} channels",
+                     format.sample_format.name(), format.sample_rate, format.channels));
+
+    let status = stdin.status.lock().unwrap().clone();
+    let state = match status.state {
+        StdinState::Waiting => "Waiting for data",
+        StdinState::Playing => "Playing",
+        StdinState::Ended => "Ended",
+    };
+    ui.label(format!("Status: {}, {:.1} s read", state, status.frames as f64 / format.sample_rate as f64));
+
+    ui.label(format!("Buffered: {:.0} ms, {} underruns",
+                     stdin.queue.len() as f32 * 1000.0 / sample_rate, stdin.queue.underruns()));
+
+    if let Some(error) = &status.error {
+        ui.colored_label(egui::Color32::RED, error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One millisecond per frame, so the fade is 20 frames
+    const RATE: u32 = 1000;
+    const FADE: usize = 20;
+
+    // Hands out at most `chunk` bytes per read, like a pipe might
+    struct Chunked<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl Read for Chunked<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data = &self.data[len..];
+            Ok(len)
+        }
+    }
+
+    fn format(sample_format: SampleFormat, channels: usize) -> PcmFormat {
+        PcmFormat { sample_format, channels, sample_rate: RATE }
+    }
+
+    // Everything read from `data` in reads of `chunk` bytes, and the frames counted
+    fn read(data: &[u8], chunk: usize, format: PcmFormat) -> (Vec<[f32; 2]>, u64) {
+        let status = Mutex::new(StdinStatus::default());
+        let mut frames = Vec::new();
+        read_pcm(Chunked { data, chunk }, format, RATE as f32, &status, |chunk| frames.extend_from_slice(chunk));
+        let status = status.into_inner().unwrap();
+        assert_eq!(status.error, None);
+        (frames, status.frames)
+    }
+
+    fn s16le(samples: &[i16]) -> Vec<u8> {
+        samples.iter().flat_map(|s| s.to_le_bytes()).collect()
+    }
+
+    // The last FADE frames ramp down to silence
+    fn faded(mut frames: Vec<[f32; 2]>) -> Vec<[f32; 2]> {
+        let start = frames.len() - FADE;
+        for (i, frame) in frames[start..].iter_mut().enumerate() {
+            let gain = 1.0 - (i + 1) as f32 / FADE as f32;
+            *frame = [frame[0] * gain, frame[1] * gain];
+        }
+        frames
+    }
+
+    #[test]
+    fn s16le_stereo() {
+        let samples: Vec<i16> = (0..200).map(|i| if i % 2 == 0 { 16384 } else { -32768 }).collect();
+        let (frames, count) = read(&s16le(&samples), READ_SIZE, format(SampleFormat::S16Le, 2));
+        assert_eq!(count, 100);
+        assert_eq!(frames, faded(vec![[0.5, -1.0]; 100]));
+        assert_eq!(frames[99], [0.0, 0.0]);
+    }
+
+    #[test]
+    fn f32le_mono_drops_non_finite_samples() {
+        let samples = [0.25, f32::NAN, -0.5, f32::INFINITY, f32::NEG_INFINITY];
+        let bytes: Vec<u8> = samples.iter().cycle().take(100).flat_map(|s| s.to_le_bytes()).collect();
+        let (frames, count) = read(&bytes, READ_SIZE, format(SampleFormat::F32Le, 1));
+        assert_eq!(count, 100);
+        let expected = [0.25, 0.0, -0.5, 0.0, 0.0].iter().cycle().take(100).map(|&s| [s, s]).collect();
+        assert_eq!(frames, faded(expected));
+    }
+
+    #[test]
+    fn frames_split_across_reads() {
+        let samples: Vec<i16> = (0..300).map(|i| i * 100).collect();
+        let bytes = s16le(&samples);
+        let format = format(SampleFormat::S16Le, 3);
+        let whole = read(&bytes, READ_SIZE, format);
+        assert_eq!(whole.1, 100);
+        // Reads that split samples as well as frames
+        for chunk in [1, 3, 5, 7, 13] {
+            assert_eq!(read(&bytes, chunk, format), whole, "reads of {} bytes", chunk);
+        }
+    }
+
+    #[test]
+    fn end_of_input_mid_frame() {
+        // 50 whole f32 stereo frames, then one sample and half of another
+        let mut bytes: Vec<u8> = (0..100).flat_map(|_| 0.5f32.to_le_bytes()).collect();
+        bytes.extend_from_slice(&[0, 0, 128, 63, 0, 0]);
+        let (frames, count) = read(&bytes, 7, format(SampleFormat::F32Le, 2));
+        assert_eq!(count, 50);
+        assert_eq!(frames, faded(vec![[0.5, 0.5]; 50]));
+    }
+
+    #[test]
+    fn input_shorter_than_the_fade() {
+        let (frames, _) = read(&s16le(&[16384; 10]), READ_SIZE, format(SampleFormat::S16Le, 2));
+        let expected: Vec<[f32; 2]> = (0..5).map(|i| 0.5 * (1.0 - (i + 1) as f32 / 5.0)).map(|s| [s, s]).collect();
+        assert_eq!(frames, expected);
+    }
+}
@@ -0,0 +1,766 @@
+// Streaming playback for long tracks
+//
+// Instead of decoding a whole file into a Wave up front, a `TrackStream`
+// checks the header the asset loader read and hands the file's path to a
+// background thread, which opens it and decodes ahead into a bounded
+// buffer of stereo frames at the engine rate. Opening costs the same
+// whatever the file's length, memory is capped by the buffer, and the
+// loader never blocks on the disk.
+//
+// `StreamPlayer` reads the buffer at the transport's playhead. When the
+// playhead jumps outside what is buffered (a seek, or a rewind for a new
+// play) it asks the thread to seek the decoder and refill from there; it
+// plays silence until the new audio arrives.
+//
+// Native only: the decoder runs on its own thread. On wasm every track is
+// decoded up front into a `Track::Memory`.
+
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::collections::VecDeque;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::File;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{BufReader, Cursor};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::{Path, PathBuf};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Mutex;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread::Thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::log::debug;
+use bevy_fundsp::prelude::*;
+use fundsp::wave::Wave;
+#[cfg(not(target_arch = "wasm32"))]
+use hound::{SampleFormat, WavReader, WavSpec};
+#[cfg(not(target_arch = "wasm32"))]
+use rubato::{FftFixedIn, Resampler};
+#[cfg(not(target_arch = "wasm32"))]
+use symphonia::core::audio::SampleBuffer as DecodedBuffer;
+#[cfg(not(target_arch = "wasm32"))]
+use symphonia::core::codecs::{Decoder, DecoderOptions};
+#[cfg(not(target_arch = "wasm32"))]
+use symphonia::core::errors::Error as SymphoniaError;
+#[cfg(not(target_arch = "wasm32"))]
+use symphonia::core::formats::{FormatReader, SeekMode, SeekTo};
+#[cfg(not(target_arch = "wasm32"))]
+use symphonia::core::io::MediaSourceStream;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::decode::{self, AudioFormat, DecodeError};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::pcm_queue::{pack, unpack};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::resample::ResampleError;
+use crate::transport::{PlayheadState, TrackPlayer};
+
+// How far ahead the decoder runs, in seconds at the engine rate
+#[cfg(not(target_arch = "wasm32"))]
+const BUFFER_SECONDS: f64 = 10.0;
+// Frames read from the file per block
+#[cfg(not(target_arch = "wasm32"))]
+const BLOCK_FRAMES: usize = 4096;
+// Frames fed to the resampler per call
+#[cfg(not(target_arch = "wasm32"))]
+const CHUNK_SIZE: usize = 1024;
+// How often a full buffer is checked for room
+#[cfg(not(target_arch = "wasm32"))]
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+// Length while a file that doesn't declare one is still being decoded
+#[cfg(not(target_arch = "wasm32"))]
+const UNKNOWN_LENGTH: u64 = u64::MAX;
+// No seek pending
+#[cfg(not(target_arch = "wasm32"))]
+const NO_SEEK: u64 = u64::MAX;
+
+// Anything a stream can be decoded from: file frames in, interleaved
+// samples out
+#[cfg(not(target_arch = "wasm32"))]
+pub trait PacketSource: Send {
+    fn channels(&self) -> usize;
+    fn sample_rate(&self) -> f64;
+    fn frames(&self) -> Option<u64>;
+    // Next block of interleaved samples, None at the end of the file
+    fn next_block(&mut self) -> Result<Option<Vec<f32>>, DecodeError>;
+    // After this the next block starts exactly at `frame`
+    fn seek(&mut self, frame: u64) -> Result<(), DecodeError>;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct WavSource {
+    reader: WavReader<BufReader<File>>,
+    scale: Option<f32>, // None for float samples
+}
+
+// Scale from integer samples to -1..1, None for float samples; same
+// scaling as decode_wav
+#[cfg(not(target_arch = "wasm32"))]
+fn wav_scale(spec: WavSpec) -> Result<Option<f32>, DecodeError> {
+    if spec.channels == 0 {
+        return Err(DecodeError::Empty);
+    }
+    match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Float, 32) => Ok(None),
+        (SampleFormat::Int, bits @ 8..=32) => Ok(Some(1.0 / (1u64 << (bits - 1)) as f32)),
+        _ => Err(DecodeError::Unsupported("WAV sample format")),
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl WavSource {
+    fn new(file: File) -> Result<Self, DecodeError> {
+        let reader = WavReader::new(BufReader::new(file))?;
+        let scale = wav_scale(reader.spec())?;
+        Ok(Self { reader, scale })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PacketSource for WavSource {
+    fn channels(&self) -> usize {
+        self.reader.spec().channels as usize
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.reader.spec().sample_rate as f64
+    }
+
+    fn frames(&self) -> Option<u64> {
+        Some(self.reader.duration() as u64)
+    }
+
+    fn next_block(&mut self) -> Result<Option<Vec<f32>>, DecodeError> {
+        let count = BLOCK_FRAMES * self.channels();
+        let samples = match self.scale {
+            None => self.reader.samples::<f32>().take(count).collect::<Result<Vec<f32>, _>>()?,
+            Some(scale) => self
+                .reader
+                .samples::<i32>()
+                .take(count)
+                .map(|sample| sample.map(|s| s as f32 * scale))
+                .collect::<Result<Vec<f32>, _>>()?,
+        };
+        Ok(if samples.is_empty() { None } else { Some(samples) })
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<(), DecodeError> {
+        let frame = frame.min(self.reader.duration() as u64);
+        self.reader.seek(frame as u32).map_err(hound::Error::from)?;
+        Ok(())
+    }
+}
+
+// Any symphonia demuxer and decoder, over a file or a network stream
+#[cfg(not(target_arch = "wasm32"))]
+pub struct SymphoniaSource {
+    reader: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    channels: usize,
+    sample_rate: f64,
+    frames: Option<u64>,
+    buffer: Option<DecodedBuffer<f32>>,
+    // Frames still to drop after a seek landed before its target
+    skip: u64,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl SymphoniaSource {
+    pub fn new(format: AudioFormat, stream: MediaSourceStream) -> Result<Self, DecodeError> {
+        let reader = decode::symphonia_reader(format, stream)?;
+        let track = reader.default_track().ok_or(DecodeError::Empty)?;
+        let params = track.codec_params.clone();
+        let decoder = symphonia::default::get_codecs().make(&params, &DecoderOptions::default())?;
+        let channels = params.channels.map(|c| c.count()).ok_or(DecodeError::Unsupported("unknown channel layout"))?;
+        let sample_rate = params.sample_rate.ok_or(DecodeError::Unsupported("unknown sample rate"))? as f64;
+        Ok(Self {
+            track_id: track.id,
+            reader,
+            decoder,
+            channels,
+            sample_rate,
+            frames: params.n_frames,
+            buffer: None,
+            skip: 0,
+        })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PacketSource for SymphoniaSource {
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> f64 {
+        self.sample_rate
+    }
+
+    fn frames(&self) -> Option<u64> {
+        self.frames
+    }
+
+    fn next_block(&mut self) -> Result<Option<Vec<f32>>, DecodeError> {
+        loop {
+            let packet = match self.reader.next_packet() {
+                Ok(packet) => packet,
+                Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt frame is skipped rather than ending the stream
+                Err(SymphoniaError::DecodeError(msg)) => {
+                    debug!("Skipping undecodable packet: {}", msg);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let spec = *decoded.spec();
+            if spec.channels.count() != self.channels {
+                return Err(DecodeError::Unsupported("channel count changing mid-stream"));
+            }
+            let needed = decoded.capacity() * self.channels;
+            if self.buffer.as_ref().is_none_or(|b| b.capacity() < needed) {
+                self.buffer = Some(DecodedBuffer::new(decoded.capacity() as u64, spec));
+            }
+            let Some(buffer) = self.buffer.as_mut() else {
+                continue;
+            };
+            buffer.copy_interleaved_ref(decoded);
+
+            let samples = buffer.samples();
+            let skip = (self.skip as usize * self.channels).min(samples.len());
+            self.skip -= (skip / self.channels) as u64;
+            if skip < samples.len() {
+                return Ok(Some(samples[skip..].to_vec()));
+            }
+        }
+    }
+
+    fn seek(&mut self, frame: u64) -> Result<(), DecodeError> {
+        let seeked = self.reader.seek(SeekMode::Accurate, SeekTo::TimeStamp { ts: frame, track_id: self.track_id })?;
+        self.decoder.reset();
+        self.skip = seeked.required_ts.saturating_sub(seeked.actual_ts);
+        Ok(())
+    }
+}
+
+// Streaming sample rate conversion to the engine rate, the same resampler
+// `resample_wave` uses at load time
+#[cfg(not(target_arch = "wasm32"))]
+pub struct StreamResampler {
+    resampler: Option<FftFixedIn<f32>>,
+    input: [Vec<f32>; 2],
+    // Output frames still to drop as filter latency
+    delay: usize,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StreamResampler {
+    pub fn new(source_rate: f64, target_rate: f64) -> Result<Self, DecodeError> {
+        let resampler = if source_rate.round() == target_rate.round() {
+            None
+        } else {
+            println!("[RESAMPLE] Streaming {} Hz -> {} Hz", source_rate, target_rate);
+            let resampler = FftFixedIn::<f32>::new(
+                source_rate.round() as usize,
+                target_rate.round() as usize,
+                CHUNK_SIZE,
+                2,
+                2,
+            )
+            .map_err(|_| DecodeError::Unsupported("sample rate conversion"))?;
+            Some(resampler)
+        };
+        let delay = resampler.as_ref().map_or(0, |r| r.output_delay());
+        Ok(Self { resampler, input: [Vec::new(), Vec::new()], delay })
+    }
+
+    pub fn reset(&mut self) {
+        if let Some(resampler) = self.resampler.as_mut() {
+            resampler.reset();
+            self.delay = resampler.output_delay();
+        }
+        self.input[0].clear();
+        self.input[1].clear();
+    }
+
+    fn output(&mut self, chunk: Vec<Vec<f32>>, out: &mut Vec<[f32; 2]>) {
+        let skip = self.delay.min(chunk[0].len());
+        self.delay -= skip;
+        out.extend(chunk[0][skip..].iter().zip(&chunk[1][skip..]).map(|(&l, &r)| [l, r]));
+    }
+
+    // Resampled frames for `frames`, holding back what doesn't fill a chunk.
+    // On an error the input held so far is dropped.
+    pub fn process(&mut self, frames: &[[f32; 2]]) -> Result<Vec<[f32; 2]>, ResampleError> {
+        let Some(resampler) = self.resampler.as_mut() else {
+            return Ok(frames.to_vec());
+        };
+        for frame in frames {
+            self.input[0].push(frame[0]);
+            self.input[1].push(frame[1]);
+        }
+        let mut chunks = Vec::new();
+        let mut pos = 0;
+        while pos + CHUNK_SIZE <= self.input[0].len() {
+            let chunk = [&self.input[0][pos..pos + CHUNK_SIZE], &self.input[1][pos..pos + CHUNK_SIZE]];
+            match resampler.process(&chunk, None) {
+                Ok(chunk) => chunks.push(chunk),
+                Err(e) => {
+                    self.input[0].clear();
+                    self.input[1].clear();
+                    return Err(e.into());
+                }
+            }
+            pos += CHUNK_SIZE;
+        }
+        self.input[0].drain(..pos);
+        self.input[1].drain(..pos);
+        let mut out = Vec::new();
+        for chunk in chunks {
+            self.output(chunk, &mut out);
+        }
+        Ok(out)
+    }
+
+    // Whatever is left at the end of the file, with the filter tail
+    pub fn flush(&mut self) -> Result<Vec<[f32; 2]>, ResampleError> {
+        let mut out = Vec::new();
+        let Some(resampler) = self.resampler.as_mut() else {
+            return Ok(out);
+        };
+        let chunk = [&self.input[0][..], &self.input[1][..]];
+        let rest = resampler.process_partial(Some(&chunk[..]), None);
+        let tail = resampler.process_partial::<&[f32]>(None, None);
+        self.input[0].clear();
+        self.input[1].clear();
+        for chunk in [rest?, tail?] {
+            self.output(chunk, &mut out);
+        }
+        Ok(out)
+    }
+}
+
+// Decoded frames between the decoder thread and the audio thread: a
+// lock-free ring like PcmQueue's, indexed by engine frame. The audio thread
+// only moves `start` and asks for seeks; the decoder only moves `end`, and
+// resets both while a seek is pending, when the audio thread keeps off them.
+#[cfg(not(target_arch = "wasm32"))]
+struct StreamShared {
+    slots: Box<[AtomicU64]>,
+    start: AtomicU64, // engine frame in the oldest slot
+    end: AtomicU64,   // engine frame after the newest slot
+    seek: AtomicU64,  // frame the decoder should restart at, or NO_SEEK
+    eof: AtomicBool,
+    closed: AtomicBool,
+    // Track length in engine frames; exact once the decoder reaches the end
+    length: AtomicU64,
+    // Furthest frame decoded so far, which stands in for an unknown length
+    decoded: AtomicU64,
+    // Why the decoder stopped early, for the UI. Never touched by the audio thread.
+    error: Mutex<Option<String>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StreamShared {
+    fn capacity(&self) -> u64 {
+        self.slots.len() as u64
+    }
+
+    fn slot(&self, frame: u64) -> &AtomicU64 {
+        &self.slots[(frame % self.capacity()) as usize]
+    }
+
+    fn fail(&self, message: String) {
+        eprintln!("[STREAM] {}", message);
+        *self.error.lock().unwrap() = Some(message);
+    }
+}
+
+// Handle on a streaming track. The decoder thread stops when the last
+// handle is dropped.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct TrackStream {
+    shared: Arc<StreamShared>,
+    decoder: Thread,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TrackStream {
+    // `head` is the start of the file, as far as the asset loader read it.
+    // The file itself is opened on the decoder thread.
+    pub fn open(format: AudioFormat, path: PathBuf, head: &[u8], engine_rate: f64) -> Result<Self, DecodeError> {
+        check_header(format, head)?;
+
+        let capacity = (BUFFER_SECONDS * engine_rate) as usize;
+        let shared = Arc::new(StreamShared {
+            slots: (0..capacity).map(|_| AtomicU64::new(0)).collect(),
+            start: AtomicU64::new(0),
+            end: AtomicU64::new(0),
+            seek: AtomicU64::new(NO_SEEK),
+            eof: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            length: AtomicU64::new(UNKNOWN_LENGTH),
+            decoded: AtomicU64::new(0),
+            error: Mutex::new(None),
+        });
+
+        let thread_shared = shared.clone();
+        let decoder = std::thread::Builder::new()
+            .name(String::from("track-stream"))
+            .spawn(move || match open_source(format, &path, engine_rate) {
+                Ok((source, resampler, ratio)) => {
+                    let length = source.frames().map_or(UNKNOWN_LENGTH, |frames| (frames as f64 * ratio).round() as u64);
+                    thread_shared.length.store(length, Ordering::Relaxed);
+                    decode_ahead(source, resampler, ratio, &thread_shared);
+                }
+                Err(message) => {
+                    thread_shared.fail(message);
+                    thread_shared.eof.store(true, Ordering::Relaxed);
+                    thread_shared.length.store(0, Ordering::Relaxed);
+                }
+            })
+            .map_err(|_| DecodeError::Unsupported("starting the decoder thread"))?;
+        Ok(Self { shared, decoder: decoder.thread().clone() })
+    }
+
+    // Length in engine frames; u64::MAX until known for files that don't declare it
+    pub fn len(&self) -> u64 {
+        self.shared.length.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Why the stream stopped before the end of the file, if it did
+    pub fn error(&self) -> Option<String> {
+        self.shared.error.lock().unwrap().clone()
+    }
+
+    // Audio thread side. Never blocks or allocates.
+    fn frame_at(&self, pos: u64) -> Option<[f32; 2]> {
+        let shared = &*self.shared;
+        if shared.seek.load(Ordering::Acquire) != NO_SEEK {
+            return None;
+        }
+
+        let start = shared.start.load(Ordering::Relaxed);
+        let end = shared.end.load(Ordering::Acquire);
+        if pos < start || pos > end + shared.capacity() {
+            // Too far to catch up by dropping frames
+            shared.seek.store(pos, Ordering::Release);
+            self.decoder.unpark();
+            return None;
+        }
+
+        // Drop whatever the playhead skipped
+        if pos >= end {
+            shared.start.store(end, Ordering::Release);
+            return None;
+        }
+        let frame = unpack(shared.slot(pos).load(Ordering::Relaxed));
+        shared.start.store(pos + 1, Ordering::Release);
+        Some(frame)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for TrackStream {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Relaxed);
+        self.decoder.unpark();
+    }
+}
+
+// Check a file's header before handing it to the decoder thread, so a
+// broken file fails to load rather than playing silence
+#[cfg(not(target_arch = "wasm32"))]
+fn check_header(format: AudioFormat, head: &[u8]) -> Result<(), DecodeError> {
+    let result = match format {
+        AudioFormat::Wav => WavReader::new(Cursor::new(head))
+            .map_err(DecodeError::from)
+            .and_then(|reader| wav_scale(reader.spec()).map(|_| ())),
+        AudioFormat::Flac | AudioFormat::OggVorbis | AudioFormat::Mp3 => {
+            let stream = MediaSourceStream::new(Box::new(Cursor::new(head.to_vec())), Default::default());
+            SymphoniaSource::new(format, stream).map(|_| ())
+        }
+        AudioFormat::OggOpus => Err(DecodeError::Unsupported("streaming Opus")),
+    };
+    match result {
+        // Headers longer than what was read are checked when the file is opened
+        Err(DecodeError::Wav(hound::Error::IoError(e))) | Err(DecodeError::Symphonia(SymphoniaError::IoError(e)))
+            if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+        {
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+// Decoder thread side of opening: the file, its decoder, and a resampler
+// to the engine rate with the ratio between the two
+#[cfg(not(target_arch = "wasm32"))]
+fn open_source(format: AudioFormat, path: &Path, engine_rate: f64) -> Result<(Box<dyn PacketSource>, StreamResampler, f64), String> {
+    let error = |e: DecodeError| format!("could not decode {}: {}", path.display(), e);
+    let file = File::open(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    let source: Box<dyn PacketSource> = match format {
+        AudioFormat::Wav => Box::new(WavSource::new(file).map_err(error)?),
+        AudioFormat::Flac | AudioFormat::OggVorbis | AudioFormat::Mp3 => {
+            let stream = MediaSourceStream::new(Box::new(file), Default::default());
+            Box::new(SymphoniaSource::new(format, stream).map_err(error)?)
+        }
+        AudioFormat::OggOpus => return Err(error(DecodeError::Unsupported("streaming Opus"))),
+    };
+    println!("[STREAM] {} channels, {} Hz", source.channels(), source.sample_rate());
+    let resampler = StreamResampler::new(source.sample_rate(), engine_rate).map_err(error)?;
+    let ratio = engine_rate / source.sample_rate();
+    Ok((source, resampler, ratio))
+}
+
+// The decoder thread: keep the buffer full, restart wherever the player
+// asks, and stop once the stream is closed
+#[cfg(not(target_arch = "wasm32"))]
+fn decode_ahead(mut source: Box<dyn PacketSource>, mut resampler: StreamResampler, ratio: f64, shared: &StreamShared) {
+    let channels = source.channels();
+    // Decoded frames that didn't fit in the buffer yet
+    let mut pending: VecDeque<[f32; 2]> = VecDeque::new();
+    while !shared.closed.load(Ordering::Relaxed) {
+        let target = shared.seek.load(Ordering::Acquire);
+        if target != NO_SEEK {
+            let frame = (target as f64 / ratio).round() as u64;
+            if let Err(e) = source.seek(frame) {
+                eprintln!("[STREAM] Seek failed: {}", e);
+            }
+            resampler.reset();
+            pending.clear();
+            shared.start.store(target, Ordering::Relaxed);
+            shared.end.store(target, Ordering::Relaxed);
+            shared.eof.store(false, Ordering::Relaxed);
+            // Hands the buffer back to the player
+            shared.seek.store(NO_SEEK, Ordering::Release);
+            continue;
+        }
+
+        let end = shared.end.load(Ordering::Relaxed);
+        let room = shared.capacity() - (end - shared.start.load(Ordering::Acquire).min(end));
+        if !pending.is_empty() && room > 0 {
+            let count = pending.len().min(room as usize);
+            for (offset, frame) in pending.drain(..count).enumerate() {
+                shared.slot(end + offset as u64).store(pack(frame), Ordering::Relaxed);
+            }
+            let end = end + count as u64;
+            shared.end.store(end, Ordering::Release);
+            shared.decoded.fetch_max(end, Ordering::Relaxed);
+            if pending.is_empty() && shared.eof.load(Ordering::Relaxed) {
+                shared.length.store(end, Ordering::Relaxed);
+            }
+            continue;
+        }
+        if !pending.is_empty() || shared.eof.load(Ordering::Relaxed) {
+            std::thread::park_timeout(POLL_INTERVAL);
+            continue;
+        }
+
+        let frames = match source.next_block() {
+            Ok(Some(samples)) => resampler.process(&decode::stereo_frames(&samples, channels)),
+            Ok(None) => {
+                shared.eof.store(true, Ordering::Relaxed);
+                resampler.flush()
+            }
+            Err(e) => {
+                shared.fail(e.to_string());
+                shared.eof.store(true, Ordering::Relaxed);
+                resampler.flush()
+            }
+        };
+        match frames {
+            Ok(frames) => pending.extend(frames),
+            // Playing on would leave a gap, so the track ends here
+            Err(e) => {
+                shared.fail(format!("sample rate conversion failed: {}", e));
+                shared.eof.store(true, Ordering::Relaxed);
+            }
+        }
+        // A file that ends on a block boundary has nothing left to write
+        if pending.is_empty() && shared.eof.load(Ordering::Relaxed) {
+            shared.length.store(end, Ordering::Relaxed);
+        }
+    }
+}
+
+// What the wave file source plays: a decoded Wave, or a stream for files
+// read from disk
+#[derive(Clone)]
+pub enum Track {
+    Memory(Arc<Wave>),
+    #[cfg(not(target_arch = "wasm32"))]
+    Stream(Arc<TrackStream>),
+}
+
+impl Track {
+    pub fn len(&self) -> u64 {
+        match self {
+            Track::Memory(wave) => wave.len() as u64,
+            #[cfg(not(target_arch = "wasm32"))]
+            Track::Stream(stream) => stream.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Length in seconds. Until a stream that doesn't declare its length
+    // reaches the end, this is as far as it has been decoded.
+    pub fn duration(&self, sample_rate: f64) -> f64 {
+        let len = match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Track::Stream(stream) if stream.len() == UNKNOWN_LENGTH => stream.shared.decoded.load(Ordering::Relaxed),
+            track => track.len(),
+        };
+        len as f64 / sample_rate
+    }
+
+    // Why a stream stopped early; decoded tracks can't fail once loaded
+    pub fn error(&self) -> Option<String> {
+        match self {
+            Track::Memory(_) => None,
+            #[cfg(not(target_arch = "wasm32"))]
+            Track::Stream(stream) => stream.error(),
+        }
+    }
+
+    pub fn player(&self, state: Arc<PlayheadState>) -> Box<dyn AudioUnit> {
+        match self {
+            Track::Memory(wave) => Box::new(TrackPlayer::new(wave.clone(), state)),
+            #[cfg(not(target_arch = "wasm32"))]
+            Track::Stream(stream) => Box::new(StreamPlayer::new(stream.clone(), state)),
+        }
+    }
+}
+
+// Plays a TrackStream from the shared playhead
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone)]
+pub struct StreamPlayer {
+    stream: Arc<TrackStream>,
+    state: Arc<PlayheadState>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl StreamPlayer {
+    pub fn new(stream: Arc<TrackStream>, state: Arc<PlayheadState>) -> An<Self> {
+        An(Self { stream, state })
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl AudioNode for StreamPlayer {
+    const ID: u64 = 0x7374_7265_616d;
+    type Inputs = U0;
+    type Outputs = U2;
+
+    fn tick(&mut self, _input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let Some(pos) = self.state.next_frame(self.stream.len()) else {
+            return Frame::default();
+        };
+        let Some([left, right]) = self.stream.frame_at(pos) else {
+            // Still buffering: hold the playhead rather than skip audio
+            self.state.stall(pos);
+            return Frame::default();
+        };
+        [left, right].into()
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    // Low enough that the file outlasts the buffer without being large
+    const RATE: u32 = 8000;
+
+    // A stereo 16 bit WAV file of `frames` frames, every sample different
+    fn wav_file(frames: u32) -> Vec<u8> {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut bytes = Cursor::new(Vec::new());
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for i in 0..frames * 2 {
+            writer.write_sample((i.wrapping_mul(7919) % 65536) as u16 as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+        bytes.into_inner()
+    }
+
+    // `count` frames from `pos`, waiting for the decoder like the player does
+    fn read(stream: &TrackStream, pos: u64, count: u64) -> Vec<[f32; 2]> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        (pos..pos + count)
+            .map(|pos| loop {
+                if let Some(frame) = stream.frame_at(pos) {
+                    break frame;
+                }
+                assert!(Instant::now() < deadline, "frame {} never arrived", pos);
+                std::thread::sleep(Duration::from_millis(1));
+            })
+            .collect()
+    }
+
+    #[test]
+    fn seeking_matches_whole_file_decode() {
+        let bytes = wav_file(RATE * 15);
+        let wave = decode::decode_audio(&bytes).unwrap();
+        let expected = |pos: u64, count: u64| -> Vec<[f32; 2]> {
+            (pos..pos + count).map(|i| [wave.at(0, i as usize), wave.at(1, i as usize)]).collect()
+        };
+
+        let path = std::env::temp_dir().join(format!("track-stream-seek-{}.wav", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let stream = TrackStream::open(AudioFormat::Wav, path.clone(), &bytes[..64], RATE as f64).unwrap();
+
+        assert_eq!(read(&stream, 0, 1000), expected(0, 1000));
+        // Back to before the buffer
+        assert_eq!(read(&stream, 1000, 1000), expected(1000, 1000));
+        assert_eq!(read(&stream, 100, 1000), expected(100, 1000));
+        // Further ahead than the buffer holds
+        let far = RATE as u64 * 12;
+        assert_eq!(read(&stream, far, 1000), expected(far, 1000));
+        assert_eq!(stream.len(), RATE as u64 * 15);
+        assert_eq!(stream.error(), None);
+
+        drop(stream);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn missing_file_reports_an_error() {
+        let bytes = wav_file(100);
+        let path = std::env::temp_dir().join(format!("track-stream-missing-{}.wav", std::process::id()));
+        let stream = TrackStream::open(AudioFormat::Wav, path, &bytes, RATE as f64).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while stream.error().is_none() {
+            assert!(Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(stream.is_empty());
+    }
+}
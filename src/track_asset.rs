@@ -0,0 +1,229 @@
+// Playlist tracks as Bevy assets
+//
+// `AudioTrack` is a decoded track (or an open stream) ready for the wave
+// file or MIDI source. `AudioTrackLoader` makes it from any asset source,
+// so loading happens off the main thread and a track reloads when its file
+// changes on disk.
+//
+// Native builds read each folder of the startup playlist through a watched
+// asset source of its own, and any other path through the unwatched "files"
+// source, rooted at the working directory like the command line. The web
+// build only has the default source, which serves the embedded assets folder.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::PathBuf;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::OnceLock;
+
+use bevy::asset::io::Reader;
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::asset::io::{AssetSource, AssetSourceBuilder};
+use bevy::asset::{AssetLoader, AssetPath, LoadContext};
+use bevy::prelude::*;
+
+use crate::decode::{self, LoadError};
+use crate::midi::{self, MidiSong};
+use crate::resample;
+use crate::stream::Track;
+
+// Asset source for tracks outside the watched folders
+#[cfg(not(target_arch = "wasm32"))]
+pub const FILES: &str = "files";
+
+// Folders of the startup playlist and the watched asset source for each
+#[cfg(not(target_arch = "wasm32"))]
+static TRACK_FOLDERS: OnceLock<Vec<(PathBuf, &'static str)>> = OnceLock::new();
+
+// How much of a file is read to detect its format. An MP3 whose ID3 tag is
+// bigger than this isn't recognised up front and is decoded whole instead.
+#[cfg(not(target_arch = "wasm32"))]
+const SNIFF_BYTES: usize = 64 * 1024;
+
+// A playlist entry ready for its source
+#[derive(Clone)]
+pub enum LoadedTrack {
+    Wave(Track),
+    Midi(Arc<MidiSong>),
+}
+
+#[derive(Asset, TypePath)]
+pub struct AudioTrack {
+    pub track: LoadedTrack,
+}
+
+// Decodes to the engine rate, so it is registered once the rate is known
+#[derive(TypePath)]
+pub struct AudioTrackLoader {
+    pub sample_rate: f32,
+}
+
+impl AssetLoader for AudioTrackLoader {
+    type Asset = AudioTrack;
+    type Settings = ();
+    type Error = LoadError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<AudioTrack, LoadError> {
+        let path = load_context.asset_path().clone();
+        println!("[ASSET] Loading track: {}", path);
+
+        let io_error = |source| LoadError::Io {
+            path: path.to_string(),
+            source,
+        };
+        let mut bytes = Vec::new();
+
+        // Files on disk can be streamed rather than read whole, if the start
+        // of the file shows a format that can
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(file) = disk_path(&path) {
+            read_head(reader, &mut bytes).await.map_err(io_error)?;
+            if let Some(track) = open_stream(&bytes, file, &path, self.sample_rate)? {
+                return Ok(AudioTrack { track });
+            }
+        }
+
+        // Carries on after the head, if that was read
+        reader.read_to_end(&mut bytes).await.map_err(io_error)?;
+        let track = decode_track(&bytes, &path.to_string(), self.sample_rate)?;
+        Ok(AudioTrack { track })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["wav", "flac", "ogg", "oga", "opus", "mp3", "mid", "midi"]
+    }
+}
+
+// Native builds read tracks from disk through asset sources: a watched one
+// per folder the startup playlist uses, so a track reloads when its file
+// changes, and the unwatched "files" source for anything else. Watching the
+// working directory instead would take in target/ and .git/. Has to be
+// added before the AssetPlugin.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn add_file_sources(app: &mut App, tracks: &[String]) {
+    let root = std::env::current_dir().unwrap_or_default();
+    app.register_asset_source(
+        FILES,
+        AssetSourceBuilder::default().with_reader(AssetSource::get_default_reader(root.to_string_lossy().into_owned())),
+    );
+
+    let mut folders: Vec<(PathBuf, &'static str)> = Vec::new();
+    for track in tracks {
+        let Some(folder) = std::fs::canonicalize(track).ok().and_then(|p| p.parent().map(PathBuf::from)) else {
+            continue;
+        };
+        if folders.iter().any(|(known, _)| *known == folder) {
+            continue;
+        }
+        // Source names have to outlive the app; there are only a few
+        let name: &'static str = Box::leak(format!("tracks{}", folders.len()).into_boxed_str());
+        println!("[ASSET] Watching {}", folder.display());
+        app.register_asset_source(name, AssetSourceBuilder::platform_default(&folder.to_string_lossy(), None));
+        folders.push((folder, name));
+    }
+    let _ = TRACK_FOLDERS.set(folders);
+}
+
+// Where the asset server finds a playlist entry. An explicit source
+// ("embedded://...") is used as given.
+pub fn track_asset_path(path: &str) -> AssetPath<'static> {
+    if path.contains("://") {
+        return AssetPath::parse(path).into_owned();
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    let asset_path = {
+        let watched = std::fs::canonicalize(path).ok().and_then(|file| {
+            TRACK_FOLDERS.get()?.iter().find_map(|(folder, name)| {
+                file.strip_prefix(folder).ok().map(|relative| AssetPath::from(relative.to_path_buf()).with_source(*name))
+            })
+        });
+        watched.unwrap_or_else(|| AssetPath::from(PathBuf::from(path)).with_source(FILES))
+    };
+    // The embedded assets folder is the root of the default source
+    #[cfg(target_arch = "wasm32")]
+    let asset_path = AssetPath::from(path.strip_prefix("assets/").unwrap_or(path).to_string());
+    asset_path
+}
+
+// The file on disk behind an asset path, for the sources that read from disk
+#[cfg(not(target_arch = "wasm32"))]
+fn disk_path(path: &AssetPath) -> Option<PathBuf> {
+    let source = path.source().as_str()?;
+    if source == FILES {
+        // Relative to the working directory, like the source's root
+        return Some(path.path().to_path_buf());
+    }
+    TRACK_FOLDERS
+        .get()?
+        .iter()
+        .find(|(_, name)| *name == source)
+        .map(|(folder, _)| folder.join(path.path()))
+}
+
+// Read up to SNIFF_BYTES from the start of a file
+#[cfg(not(target_arch = "wasm32"))]
+async fn read_head(reader: &mut dyn Reader, head: &mut Vec<u8>) -> std::io::Result<()> {
+    head.resize(SNIFF_BYTES, 0);
+    let mut filled = 0;
+    while filled < head.len() {
+        match bevy::asset::AsyncReadExt::read(reader, &mut head[filled..]).await? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+    head.truncate(filled);
+    Ok(())
+}
+
+// Open a streamable file for playback, or None if it has to be decoded
+// whole. The decoder thread opens `file`; only `head` is read here.
+#[cfg(not(target_arch = "wasm32"))]
+fn open_stream(head: &[u8], file: PathBuf, path: &AssetPath, sample_rate: f32) -> Result<Option<LoadedTrack>, LoadError> {
+    // MIDI and Opus files are small enough to load whole
+    match decode::AudioFormat::sniff(head) {
+        Some(format) if format != decode::AudioFormat::OggOpus => {
+            let stream = crate::stream::TrackStream::open(format, file, head, sample_rate as f64).map_err(|source| {
+                LoadError::Decode {
+                    path: path.to_string(),
+                    source,
+                }
+            })?;
+            println!("[STREAM] Streaming {} file: {}", format.name(), path);
+            Ok(Some(LoadedTrack::Wave(Track::Stream(Arc::new(stream)))))
+        }
+        _ => Ok(None),
+    }
+}
+
+// Decode a whole file held in memory
+fn decode_track(bytes: &[u8], path: &str, sample_rate: f32) -> Result<LoadedTrack, LoadError> {
+    // MIDI files go to the MIDI source instead of the decoder
+    if midi::is_midi(bytes) {
+        let song = midi::parse_midi(bytes).map_err(|source| LoadError::Midi {
+            path: path.to_string(),
+            source,
+        })?;
+        return Ok(LoadedTrack::Midi(Arc::new(song)));
+    }
+
+    // The decoder is chosen from the file contents, not the extension
+    let wave = decode::decode_audio(bytes).map_err(|source| LoadError::Decode {
+        path: path.to_string(),
+        source,
+    })?;
+
+    // Playback is stereo, fold surround layouts down before they reach the graph
+    let wave = decode::downmix_to_stereo(wave);
+
+    // Match the engine rate so pitch, speed and FFT bins all line up
+    let wave = resample::resample_wave(wave, sample_rate as f64).map_err(|source| LoadError::Resample {
+        path: path.to_string(),
+        source,
+    })?;
+    Ok(LoadedTrack::Wave(Track::Memory(Arc::new(wave))))
+}
@@ -0,0 +1,251 @@
+// Transport: play/pause/stop, seeking and playback position
+//
+// The wave file source is played by `TrackPlayer`, which reads the loaded
+// Wave at a playhead shared with the ECS side; the MIDI player follows the
+// same playhead. Systems move the playhead and flip the playing flag; the
+// audio thread picks both up on the next sample.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use bevy_fundsp::prelude::*;
+use fundsp::wave::Wave;
+
+// No seek pending
+const NO_SEEK: u64 = u64::MAX;
+
+// Playhead shared between the audio thread and the ECS
+pub struct PlayheadState {
+    position: AtomicU64, // in frames
+    seek: AtomicU64,     // requested frame, or NO_SEEK
+    playing: AtomicBool,
+}
+
+impl Default for PlayheadState {
+    fn default() -> Self {
+        Self {
+            position: AtomicU64::new(0),
+            seek: AtomicU64::new(NO_SEEK),
+            playing: AtomicBool::new(true),
+        }
+    }
+}
+
+impl PlayheadState {
+    // Audio thread side: apply any pending seek, then hand out the frame to
+    // play and step past it. None while paused or once `len` is reached.
+    pub fn next_frame(&self, len: u64) -> Option<u64> {
+        if self.seek.load(Ordering::Relaxed) != NO_SEEK {
+            let target = self.seek.swap(NO_SEEK, Ordering::Relaxed);
+            self.position.store(target, Ordering::Relaxed);
+        }
+
+        if !self.playing.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let pos = self.position.load(Ordering::Relaxed);
+        if pos >= len {
+            return None;
+        }
+        self.position.store(pos + 1, Ordering::Relaxed);
+        Some(pos)
+    }
+
+    // Audio thread side: give back a frame from next_frame that couldn't be
+    // played yet, so a buffering stream doesn't skip ahead. A seek that came
+    // in meanwhile wins.
+    pub fn stall(&self, pos: u64) {
+        let _ = self.position.compare_exchange(pos + 1, pos, Ordering::Relaxed, Ordering::Relaxed);
+    }
+}
+
+// Plays a Wave from the shared playhead. Mono is sent to both outputs.
+#[derive(Clone)]
+pub struct TrackPlayer {
+    wave: Arc<Wave>,
+    state: Arc<PlayheadState>,
+}
+
+impl TrackPlayer {
+    pub fn new(wave: Arc<Wave>, state: Arc<PlayheadState>) -> An<Self> {
+        An(Self { wave, state })
+    }
+}
+
+impl AudioNode for TrackPlayer {
+    const ID: u64 = 0x7472_6163_6b70;
+    type Inputs = U0;
+    type Outputs = U2;
+
+    fn tick(&mut self, _input: &Frame<f32, Self::Inputs>) -> Frame<f32, Self::Outputs> {
+        let Some(pos) = self.state.next_frame(self.wave.len() as u64) else {
+            return Frame::default();
+        };
+        let pos = pos as usize;
+
+        let left = self.wave.at(0, pos);
+        let right = if self.wave.channels() > 1 { self.wave.at(1, pos) } else { left };
+        [left, right].into()
+    }
+}
+
+// ECS handle on the playhead
+#[derive(Resource, Clone, Default)]
+pub struct Transport {
+    pub state: Arc<PlayheadState>,
+}
+
+impl Transport {
+    pub fn set_playing(&self, playing: bool) {
+        self.state.playing.store(playing, Ordering::Relaxed);
+    }
+
+    // Jump to an absolute time in seconds
+    pub fn seek(&self, seconds: f64, sample_rate: f64) {
+        let frame = (seconds.max(0.0) * sample_rate) as u64;
+        self.state.seek.store(frame, Ordering::Relaxed);
+        // Reflect the jump immediately so the UI doesn't flicker back
+        self.state.position.store(frame, Ordering::Relaxed);
+    }
+
+    pub fn rewind(&self) {
+        self.seek(0.0, 1.0);
+    }
+
+    pub fn position_frames(&self) -> u64 {
+        self.state.position.load(Ordering::Relaxed)
+    }
+
+    pub fn position_seconds(&self, sample_rate: f64) -> f64 {
+        self.position_frames() as f64 / sample_rate
+    }
+}
+
+// mm:ss for the position readout
+fn format_time(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    format!("{}:{:02}", total / 60, total % 60)
+}
+
+// Side panel section with the transport controls for the loaded wave
+pub fn transport_panel(ui: &mut egui::Ui, transport: &Transport, paused: &mut bool, duration: f64, sample_rate: f64) {
+    ui.separator();
+    ui.label("Transport");
+
+    ui.horizontal(|ui| {
+        if ui.button("Play").clicked() {
+            // Playing from the very end starts the track over
+            if transport.position_seconds(sample_rate) >= duration {
+                transport.rewind();
+            }
+            *paused = false;
+        }
+        if ui.button("Pause").clicked() {
+            *paused = true;
+        }
+        if ui.button("Stop").clicked() {
+            *paused = true;
+            transport.rewind();
+        }
+    });
+
+    let mut position = transport.position_seconds(sample_rate).min(duration);
+    let slider = egui::Slider::new(&mut position, 0.0..=duration.max(0.0))
+        .show_value(false)
+        .text(format!("{} / {}", format_time(position), format_time(duration)));
+    if ui.add(slider).changed() {
+        transport.seek(position, sample_rate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Frames handed out over `count` calls
+    fn play(state: &PlayheadState, len: u64, count: usize) -> Vec<Option<u64>> {
+        (0..count).map(|_| state.next_frame(len)).collect()
+    }
+
+    #[test]
+    fn plays_to_the_end() {
+        let transport = Transport::default();
+        assert_eq!(play(&transport.state, 3, 5), [Some(0), Some(1), Some(2), None, None]);
+        assert_eq!(transport.position_frames(), 3);
+    }
+
+    #[test]
+    fn pause_holds_the_position() {
+        let transport = Transport::default();
+        play(&transport.state, 100, 10);
+        transport.set_playing(false);
+        assert_eq!(play(&transport.state, 100, 3), [None, None, None]);
+        assert_eq!(transport.position_frames(), 10);
+        transport.set_playing(true);
+        assert_eq!(transport.state.next_frame(100), Some(10));
+    }
+
+    #[test]
+    fn seek_moves_the_playhead() {
+        let transport = Transport::default();
+        play(&transport.state, 48000, 100);
+
+        transport.seek(0.5, 48000.0);
+        // Shown straight away, before the audio thread picks it up
+        assert_eq!(transport.position_frames(), 24000);
+        assert_eq!(transport.position_seconds(48000.0), 0.5);
+        assert_eq!(transport.state.next_frame(48000), Some(24000));
+        assert_eq!(transport.state.next_frame(48000), Some(24001));
+
+        // Negative times clamp to the start, and rewind goes there too
+        transport.seek(-3.0, 48000.0);
+        assert_eq!(transport.state.next_frame(48000), Some(0));
+        play(&transport.state, 48000, 10);
+        transport.rewind();
+        assert_eq!(transport.state.next_frame(48000), Some(0));
+    }
+
+    #[test]
+    fn seek_while_paused_applies_on_resume() {
+        let transport = Transport::default();
+        transport.set_playing(false);
+        transport.seek(2.0, 10.0);
+        assert_eq!(transport.state.next_frame(100), None);
+        assert_eq!(transport.position_frames(), 20);
+        transport.set_playing(true);
+        assert_eq!(transport.state.next_frame(100), Some(20));
+    }
+
+    #[test]
+    fn seek_past_the_end_plays_nothing() {
+        let transport = Transport::default();
+        transport.seek(10.0, 10.0);
+        assert_eq!(transport.state.next_frame(50), None);
+        assert_eq!(transport.position_frames(), 100);
+    }
+
+    #[test]
+    fn stall_gives_the_frame_back() {
+        let state = PlayheadState::default();
+        assert_eq!(state.next_frame(100), Some(0));
+        state.stall(0);
+        assert_eq!(state.next_frame(100), Some(0));
+
+        // A seek that came in meanwhile wins
+        let transport = Transport { state: Arc::new(PlayheadState::default()) };
+        let pos = transport.state.next_frame(100).unwrap();
+        transport.seek(5.0, 10.0);
+        transport.state.stall(pos);
+        assert_eq!(transport.state.next_frame(100), Some(50));
+    }
+
+    #[test]
+    fn time_readout() {
+        assert_eq!(format_time(0.0), "0:00");
+        assert_eq!(format_time(65.9), "1:05");
+        assert_eq!(format_time(-1.0), "0:00");
+    }
+}